@@ -38,7 +38,7 @@ fn main() {
 }
 
 fn run_test(cart: &str, start_at_frames: u64, end_at_frames: u64) -> Duration {
-    let mut emulator = Emulator::new(None, cart);
+    let mut emulator = Emulator::new(None, cart).unwrap();
     let mut app = BenchmarkApp {
         frame_counter: 0,
         run_till: start_at_frames,
@@ -15,7 +15,7 @@ fn main() {
     // let cartridge_rom = "blargg_test_roms/cpu_instrs/individual/02-interrupts.gb";
     // let cartridge_rom = "blargg_test_roms/cpu_instrs/individual/06-ld_r_r.gb";
     let cartridge_rom = "../ROMs/tetris.gb";
-    let mut emulator = Emulator::new(None, cartridge_rom);
+    let mut emulator = Emulator::new(None, cartridge_rom).unwrap();
 
     emulator.set_tracing(true);
     let mut app = DummyApp {};
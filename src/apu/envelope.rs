@@ -0,0 +1,68 @@
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+
+// Volume envelope of the square and noise channels, clocked at 64 Hz.
+// The NRx2 register holds the initial volume in bits 4 - 7, the
+// direction in bit 3 and the number of clocks per step in bits 0 - 2
+#[derive(Default)]
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    // The channel's DAC is off if the initial volume is 0 and the
+    // envelope is decreasing, which also disables the channel
+    pub fn is_dac_enabled(&self) -> bool {
+        self.register & 0b1111_1000 != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0b111;
+    }
+
+    pub fn clock(&mut self) {
+        let period = self.register & 0b111;
+        if period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            if self.register.get_bit(3) {
+                if self.volume < 15 {
+                    self.volume += 1;
+                }
+            } else if self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.register = reader.read_u8()?;
+        self.volume = reader.read_u8()?;
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
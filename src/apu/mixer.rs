@@ -0,0 +1,63 @@
+use crate::cpu;
+
+// Produces stereo samples at a fixed rate from the apu's mixed output
+pub struct Mixer {
+    sample_rate: u32,
+    // Cycles times sample rate since the last sample
+    sample_timer: u64,
+    // High pass filter, removing the dc offset of the DACs as the real hardware does
+    charge_factor: f32,
+    capacitors: [f32; 2],
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Mixer {
+        assert!(sample_rate > 0, "sample rate must be above zero");
+        // More than one sample a cycle would leave sample_timer behind
+        assert!(
+            u64::from(sample_rate) <= cpu::CLOCK_SPEED,
+            "sample rate must be at most {} Hz",
+            cpu::CLOCK_SPEED
+        );
+        Mixer {
+            sample_rate,
+            sample_timer: 0,
+            charge_factor: 0.999_958f32.powf(cpu::CLOCK_SPEED as f32 / sample_rate as f32),
+            capacitors: [0.0; 2],
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn get_cycles_until_sample(&self) -> u64 {
+        let rate = u64::from(self.sample_rate);
+        // Rounded up, and at least one cycle
+        let remaining = cpu::CLOCK_SPEED - self.sample_timer;
+        remaining.saturating_sub(1) / rate + 1
+    }
+
+    // Returns true when it is time for a sample
+    pub fn step(&mut self, cycles: u64) -> bool {
+        self.sample_timer += cycles * u64::from(self.sample_rate);
+        if self.sample_timer >= cpu::CLOCK_SPEED {
+            self.sample_timer -= cpu::CLOCK_SPEED;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Output is silent while every DAC is off
+    pub fn filter(&mut self, stereo: [f32; 2], any_dac_enabled: bool) -> [f32; 2] {
+        let mut output = [0.0; 2];
+        if any_dac_enabled {
+            for (i, x) in stereo.iter().enumerate() {
+                output[i] = x - self.capacitors[i];
+                self.capacitors[i] = x - output[i] * self.charge_factor;
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,428 @@
+mod envelope;
+mod length_counter;
+mod mixer;
+mod noise;
+mod square;
+mod wav;
+mod wave;
+use self::mixer::Mixer;
+use self::noise::Noise;
+use self::square::Square;
+use self::wav::WavRecorder;
+use self::wave::{Wave, WAVE_RAM_SIZE};
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::memory::io_regs;
+use crate::save_state::{bad_state, StateReader, StateWriter};
+
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+// NR10 - NR51, NR52 isn't stored
+const REGISTER_COUNT: usize = 0x16;
+// Bits that always read back as 1 in NR10 - NR51
+const READ_MASKS: [u8; REGISTER_COUNT] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10 - NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf, // NR20 - NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30 - NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf, // NR40 - NR44
+    0x00, 0x00, // NR50, NR51
+];
+// Stereo samples kept before the oldest are dropped, if they aren't taken
+const MAX_BUFFERED_SECONDS: usize = 1;
+// Used for recording when the host hasn't chosen a sample rate
+const DEFAULT_RECORDING_SAMPLE_RATE: u32 = 44_100;
+
+pub struct Apu {
+    power: bool,
+    registers: [u8; REGISTER_COUNT],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_sequencer_step: u8,
+    frame_sequencer_timer: u32,
+    last_cycles: u64,
+    output: Option<(Mixer, Vec<f32>)>,
+    // Recordings keep the sample rate they were started with
+    recording: Option<(Mixer, WavRecorder)>,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            power: false,
+            registers: [0; REGISTER_COUNT],
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_sequencer_step: 0,
+            frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            last_cycles: 0,
+            output: None,
+            recording: None,
+        }
+    }
+
+    // Samples are only generated once a sample rate is set
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.output = sample_rate.map(|x| (Mixer::new(x), Vec::new()));
+    }
+
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.output
+            .as_ref()
+            .map(|(mixer, _)| mixer.get_sample_rate())
+    }
+
+    // Interleaved left and right samples, from -1.0 to 1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        match &mut self.output {
+            Some((_, samples)) => samples.split_off(0),
+            None => Vec::new(),
+        }
+    }
+
+    // Records at the current sample rate, or a default if there isn't one
+    pub fn start_recording(&mut self, path: &str, separate_channels: bool) -> Result<()> {
+        self.stop_recording()?;
+        let sample_rate = self
+            .get_sample_rate()
+            .unwrap_or(DEFAULT_RECORDING_SAMPLE_RATE);
+        let recorder = WavRecorder::create(path, sample_rate, separate_channels)?;
+        self.recording = Some((Mixer::new(sample_rate), recorder));
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recording.take() {
+            Some((_, recorder)) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn get_u8(&self, index: usize) -> u8 {
+        match index {
+            io_regs::NR52 => {
+                let mut x = 0b0111_0000;
+                if self.power {
+                    x = x.set_bit(7);
+                }
+                let channels = [
+                    self.square1.is_enabled(),
+                    self.square2.is_enabled(),
+                    self.wave.is_enabled(),
+                    self.noise.is_enabled(),
+                ];
+                for (i, enabled) in channels.iter().enumerate() {
+                    if *enabled {
+                        x = x.set_bit(i as u8);
+                    }
+                }
+                x
+            }
+            io_regs::WAVE_PATTERN_RAM_START..=io_regs::WAVE_PATTERN_RAM_END => {
+                self.wave.get_ram(index - io_regs::WAVE_PATTERN_RAM_START)
+            }
+            io_regs::NR10..=io_regs::NR51 => {
+                let i = index - io_regs::NR10;
+                self.registers[i] | READ_MASKS[i]
+            }
+            _ => 0xff,
+        }
+    }
+
+    pub fn set_u8(&mut self, index: usize, value: u8) {
+        match index {
+            io_regs::NR52 => {
+                let power = value.get_bit(7);
+                if self.power && !power {
+                    self.power_off();
+                } else if !self.power && power {
+                    self.frame_sequencer_step = 0;
+                    self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                }
+                self.power = power;
+            }
+            io_regs::WAVE_PATTERN_RAM_START..=io_regs::WAVE_PATTERN_RAM_END => {
+                self.wave
+                    .set_ram(index - io_regs::WAVE_PATTERN_RAM_START, value);
+            }
+            // Other registers can't be written while the apu is off
+            _ if !self.power => (),
+            io_regs::NR10..=io_regs::NR51 => {
+                let i = index - io_regs::NR10;
+                self.registers[i] = value;
+                match i / 5 {
+                    0 => self.square1.write(i % 5, value),
+                    1 => self.square2.write(i % 5, value),
+                    2 => self.wave.write(i % 5, value),
+                    3 => self.noise.write(i % 5, value),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Turning the apu off clears every register except wave ram
+    fn power_off(&mut self) {
+        let mut ram = [0; WAVE_RAM_SIZE];
+        for (i, x) in ram.iter_mut().enumerate() {
+            *x = self.wave.get_ram(i);
+        }
+
+        self.registers = [0; REGISTER_COUNT];
+        self.square1 = Square::new(true);
+        self.square2 = Square::new(false);
+        self.wave = Wave::new();
+        self.noise = Noise::new();
+
+        for (i, x) in ram.iter().enumerate() {
+            self.wave.set_ram(i, *x);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        let mut elapsed = cycles.saturating_sub(self.last_cycles);
+        self.last_cycles = cycles;
+
+        // Run up to each frame sequencer clock or sample in turn
+        while elapsed > 0 {
+            let mut step = elapsed.min(u64::from(self.frame_sequencer_timer));
+            if let Some((mixer, _)) = &self.output {
+                step = step.min(mixer.get_cycles_until_sample());
+            }
+            if let Some((mixer, _)) = &self.recording {
+                step = step.min(mixer.get_cycles_until_sample());
+            }
+
+            self.square1.step(step as u32);
+            self.square2.step(step as u32);
+            self.wave.step(step as u32);
+            self.noise.step(step as u32);
+
+            self.frame_sequencer_timer -= step as u32;
+            if self.frame_sequencer_timer == 0 {
+                self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                if self.power {
+                    self.clock_frame_sequencer();
+                }
+            }
+
+            let output_due = match &mut self.output {
+                Some((mixer, _)) => mixer.step(step),
+                None => false,
+            };
+            let recording_due = match &mut self.recording {
+                Some((mixer, _)) => mixer.step(step),
+                None => false,
+            };
+            if output_due || recording_due {
+                self.push_samples(output_due, recording_due);
+            }
+
+            elapsed -= step;
+        }
+    }
+
+    // Length counters at 256 Hz, sweep at 128 Hz and envelopes at 64 Hz
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_sequencer_step & 0b1 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_sequencer_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    // The analog output of each channel from -1.0 to 1.0,
+    // or 0.0 if the channel's DAC is off
+    pub fn get_channel_outputs(&self) -> [f32; 4] {
+        let channels = [
+            (self.square1.is_dac_enabled(), self.square1.get_output()),
+            (self.square2.is_dac_enabled(), self.square2.get_output()),
+            (self.wave.is_dac_enabled(), self.wave.get_output()),
+            (self.noise.is_dac_enabled(), self.noise.get_output()),
+        ];
+        let mut outputs = [0.0; 4];
+        for (x, (dac_enabled, digital)) in outputs.iter_mut().zip(channels.iter()) {
+            if self.power && *dac_enabled {
+                *x = f32::from(*digital) / 7.5 - 1.0;
+            }
+        }
+        outputs
+    }
+
+    // Mix the channels with NR51 panning and NR50 master volume
+    fn push_samples(&mut self, output_due: bool, recording_due: bool) {
+        let outputs = self.get_channel_outputs();
+        let any_dac_enabled = outputs.iter().any(|x| *x != 0.0);
+        let nr50 = self.registers[io_regs::NR50 - io_regs::NR10];
+        let nr51 = self.registers[io_regs::NR51 - io_regs::NR10];
+
+        let mut stereo = [0.0; 2];
+        for (i, x) in outputs.iter().enumerate() {
+            if nr51.get_bit(i as u8 + 4) {
+                stereo[0] += x;
+            }
+            if nr51.get_bit(i as u8) {
+                stereo[1] += x;
+            }
+        }
+        stereo[0] *= f32::from(((nr50 >> 4) & 0b111) + 1) / 32.0;
+        stereo[1] *= f32::from((nr50 & 0b111) + 1) / 32.0;
+
+        if output_due {
+            let (mixer, samples) = self.output.as_mut().unwrap();
+            samples.extend(&mixer.filter(stereo, any_dac_enabled));
+
+            let max_samples = mixer.get_sample_rate() as usize * 2 * MAX_BUFFERED_SECONDS;
+            if samples.len() > max_samples {
+                let excess = samples.len() - max_samples;
+                samples.drain(..excess);
+            }
+        }
+        if recording_due {
+            let (mixer, recorder) = self.recording.as_mut().unwrap();
+            recorder.write(mixer.filter(stereo, any_dac_enabled), outputs);
+        }
+    }
+
+    // The sample output is a host setting, so isn't part of the state
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.power);
+        writer.write_bytes(&self.registers);
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        writer.write_u8(self.frame_sequencer_step);
+        writer.write_u32(self.frame_sequencer_timer);
+        writer.write_u64(self.last_cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.power = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.registers)?;
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.frame_sequencer_step = reader.read_u8()? % 8;
+        self.frame_sequencer_timer = reader.read_u32()?;
+        if self.frame_sequencer_timer == 0 || self.frame_sequencer_timer > FRAME_SEQUENCER_PERIOD {
+            return Err(bad_state("bad apu frame sequencer timer"));
+        }
+        self.last_cycles = reader.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu;
+
+    fn create_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.set_u8(io_regs::NR52, 0x80);
+        apu.set_u8(io_regs::NR50, 0x77);
+        apu.set_u8(io_regs::NR51, 0xff);
+        apu
+    }
+
+    #[test]
+    fn length_counter_disables_channel() {
+        let mut apu = create_apu();
+        // Full volume, length of 64 - 62 = 2 clocks
+        apu.set_u8(io_regs::NR21, 0b1000_0000 | 62);
+        apu.set_u8(io_regs::NR22, 0xf0);
+        apu.set_u8(io_regs::NR24, 0b1100_0000);
+        assert_eq!(apu.get_u8(io_regs::NR52), 0xf2);
+
+        // Length is clocked on every other frame sequencer step
+        apu.tick(u64::from(FRAME_SEQUENCER_PERIOD) * 2);
+        assert_eq!(apu.get_u8(io_regs::NR52), 0xf2);
+        apu.tick(u64::from(FRAME_SEQUENCER_PERIOD) * 3);
+        assert_eq!(apu.get_u8(io_regs::NR52), 0xf0);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut apu = create_apu();
+        apu.set_u8(io_regs::NR30, 0x80);
+        apu.set_u8(io_regs::WAVE_PATTERN_RAM_START, 0x12);
+        assert_eq!(apu.get_u8(io_regs::NR30), 0xff);
+
+        apu.set_u8(io_regs::NR52, 0x00);
+        assert_eq!(apu.get_u8(io_regs::NR52), 0x70);
+        assert_eq!(apu.get_u8(io_regs::NR30), 0x7f);
+        assert_eq!(apu.get_u8(io_regs::WAVE_PATTERN_RAM_START), 0x12);
+        // Ignored while off
+        apu.set_u8(io_regs::NR50, 0x77);
+        assert_eq!(apu.get_u8(io_regs::NR50), 0x00);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel() {
+        let mut apu = create_apu();
+        // Period 1, increasing, shift 1
+        apu.set_u8(io_regs::NR10, 0b0001_0001);
+        apu.set_u8(io_regs::NR12, 0xf0);
+        apu.set_u8(io_regs::NR13, 0x00);
+        apu.set_u8(io_regs::NR14, 0b1000_0101);
+        assert_eq!(apu.get_u8(io_regs::NR52) & 0b1, 1);
+
+        // 0x500 sweeps to 0x780, which fails the second overflow check
+        apu.tick(u64::from(FRAME_SEQUENCER_PERIOD) * 3);
+        assert_eq!(apu.get_u8(io_regs::NR52) & 0b1, 0);
+    }
+
+    #[test]
+    fn stereo_samples() {
+        let mut apu = create_apu();
+        apu.set_sample_rate(Some(32_768));
+        apu.set_u8(io_regs::NR51, 0x01);
+        apu.set_u8(io_regs::NR12, 0xf0);
+        apu.set_u8(io_regs::NR14, 0x80);
+        apu.tick(128 * 1000);
+
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 1000 * 2);
+        // Channel 1 is only panned right
+        assert!(samples.iter().step_by(2).all(|x| *x == 0.0));
+        assert!(samples.iter().skip(1).step_by(2).any(|x| *x != 0.0));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn high_sample_rates() {
+        // At most one sample per cycle
+        for rate in [192_000, cpu::CLOCK_SPEED as u32].iter() {
+            let mut apu = create_apu();
+            apu.set_sample_rate(Some(*rate));
+            apu.tick(cpu::CLOCK_SPEED / 4);
+            assert_eq!(apu.take_samples().len(), *rate as usize / 4 * 2);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sample rate must be")]
+    fn sample_rate_above_clock_speed() {
+        create_apu().set_sample_rate(Some(cpu::CLOCK_SPEED as u32 + 1));
+    }
+}
@@ -0,0 +1,125 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Pseudo random noise from a 15 bit linear feedback shift register
+pub struct Noise {
+    enabled: bool,
+    // NR43, shift in bits 4 - 7, 7 bit mode in bit 3, divisor code in bits 0 - 2
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            register: 0,
+            lfsr: 0x7fff,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+        }
+    }
+
+    // Register 0 - 4 are NR40 (unused) - NR44
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => (),
+            1 => self.length.load(value & 0b0011_1111),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.register = value,
+            4 => {
+                self.length.set_enabled(value.get_bit(6));
+                if value.get_bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.get_period();
+        self.envelope.trigger();
+        self.lfsr = 0x7fff;
+    }
+
+    fn get_period(&self) -> u32 {
+        DIVISORS[usize::from(self.register & 0b111)] << (self.register >> 4)
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0b1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.register.get_bit(3) {
+                self.lfsr = (self.lfsr & !(0b1 << 6)) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    // Digital output from 0 to 15
+    pub fn get_output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0b1 == 0 {
+            self.envelope.get_volume()
+        } else {
+            0
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.register);
+        writer.write_u16(self.lfsr);
+        writer.write_u32(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.register = reader.read_u8()?;
+        self.lfsr = reader.read_u16()? & 0x7fff;
+        self.timer = reader.read_u32()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)
+    }
+}
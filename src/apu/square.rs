@@ -0,0 +1,224 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+
+// 12.5%, 25%, 50% and 75% duty cycles, played from the high bit down
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// Frequency sweep, only present on channel 1
+#[derive(Default)]
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn get_period(&self) -> u8 {
+        (self.register >> 4) & 0b111
+    }
+
+    fn get_shift(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8
+        self.timer = match self.get_period() {
+            0 => 8,
+            x => x,
+        };
+    }
+
+    fn calculate(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.get_shift();
+        if self.register.get_bit(3) {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+}
+
+pub struct Square {
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Square {
+        Square {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+            sweep: if has_sweep {
+                Some(Default::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    // Register 0 - 4 are NRx0 - NRx4
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.register = value;
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0b0011_1111);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | u16::from(value),
+            4 => {
+                self.frequency = (self.frequency & 0xff) | (u16::from(value & 0b111) << 8);
+                self.length.set_enabled(value.get_bit(6));
+                if value.get_bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.get_period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow_frequency = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.get_period() != 0 || sweep.get_shift() != 0;
+            if sweep.get_shift() != 0 && sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn get_period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 4
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let sweep = match &mut self.sweep {
+            Some(x) => x,
+            None => return,
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+
+        if sweep.enabled && sweep.get_period() != 0 {
+            let frequency = sweep.calculate();
+            if frequency > 2047 {
+                self.enabled = false;
+            } else if sweep.get_shift() != 0 {
+                sweep.shadow_frequency = frequency;
+                self.frequency = frequency;
+                // The overflow check is done again with the new frequency
+                if sweep.calculate() > 2047 {
+                    self.enabled = false;
+                }
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    // Digital output from 0 to 15
+    pub fn get_output(&self) -> u8 {
+        let pattern = DUTY_PATTERNS[usize::from(self.duty)];
+        if self.enabled && pattern.get_bit(7 - self.duty_step) {
+            self.envelope.get_volume()
+        } else {
+            0
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_step);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        if let Some(sweep) = &self.sweep {
+            writer.write_u8(sweep.register);
+            writer.write_bool(sweep.enabled);
+            writer.write_u16(sweep.shadow_frequency);
+            writer.write_u8(sweep.timer);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()? & 0b11;
+        self.duty_step = reader.read_u8()? & 0b111;
+        self.frequency = reader.read_u16()? & 0x7ff;
+        self.timer = reader.read_u32()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.register = reader.read_u8()?;
+            sweep.enabled = reader.read_bool()?;
+            sweep.shadow_frequency = reader.read_u16()? & 0x7ff;
+            sweep.timer = reader.read_u8()?;
+        }
+        Ok(())
+    }
+}
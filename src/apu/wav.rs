@@ -0,0 +1,211 @@
+use crate::error::Result;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+// The RIFF size after the first 8 bytes has to fit in 32 bits
+const MAX_DATA_SIZE: u32 = u32::MAX - (HEADER_SIZE - 8);
+
+// Writes 16 bit PCM WAV files. The sizes in the header are filled in
+// by finish once the length of the audio is known, or when dropped
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, channels: u16, sample_rate: u32) -> Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // Uncompressed PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            data_size: 0,
+            finished: false,
+        })
+    }
+
+    // Samples from -1.0 to 1.0, interleaved if there is more than one channel.
+    // Nothing is written once the file would be too big for its header
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_size = samples.len() as u64 * 2 + u64::from(self.data_size);
+        if data_size > u64::from(MAX_DATA_SIZE) {
+            return Err(io::Error::other("WAV file is full"));
+        }
+        for x in samples.iter() {
+            let sample = (x.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size = data_size as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        self.write_sizes()?;
+        Ok(())
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file
+            .seek(SeekFrom::Start(u64::from(HEADER_SIZE) - 4))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+// Dropping without finishing still leaves a valid file, but errors are lost
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.write_sizes();
+        }
+    }
+}
+
+// Records the mixed stereo output, and optionally each channel before
+// panning and volume into its own mono file, with _ch1 - _ch4 added to
+// the file name
+pub struct WavRecorder {
+    mixed: WavWriter,
+    channels: Vec<WavWriter>,
+    // Writes happen every sample, so the first error is kept for finish
+    error: Option<io::Error>,
+}
+
+impl WavRecorder {
+    pub fn create(path: &str, sample_rate: u32, separate_channels: bool) -> Result<WavRecorder> {
+        let mixed = WavWriter::create(path, 2, sample_rate)?;
+        let mut channels = Vec::new();
+        if separate_channels {
+            for i in 1..=4 {
+                let path = channel_path(path, i);
+                channels.push(WavWriter::create(&path, 1, sample_rate)?);
+            }
+        }
+        Ok(WavRecorder {
+            mixed,
+            channels,
+            error: None,
+        })
+    }
+
+    pub fn write(&mut self, stereo: [f32; 2], channels: [f32; 4]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut result = self.mixed.write_samples(&stereo);
+        for (writer, x) in self.channels.iter_mut().zip(channels.iter()) {
+            result = result.and_then(|_| writer.write_samples(&[*x]));
+        }
+        self.error = result.err();
+    }
+
+    // Every file is finished, even after an error. The first error is returned
+    pub fn finish(self) -> Result<()> {
+        let mut result = self.mixed.finish();
+        for writer in self.channels.into_iter() {
+            let finished = writer.finish();
+            result = result.and(finished);
+        }
+        match self.error {
+            Some(e) => Err(e.into()),
+            None => result,
+        }
+    }
+}
+
+// out.wav becomes out_ch1.wav
+fn channel_path(path: &str, channel: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+    let name = match path.extension().and_then(|x| x.to_str()) {
+        Some(extension) => format!("{}_ch{}.{}", stem, channel, extension),
+        None => format!("{}_ch{}", stem, channel),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_path, WavWriter, MAX_DATA_SIZE};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn header_sizes() {
+        let path = env::temp_dir().join("gb_emu_wav_writer_test.wav");
+        let path = path.to_str().unwrap();
+        let mut writer = WavWriter::create(path, 2, 48_000).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        writer.finish().unwrap();
+
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(data[4..8], 44u32.to_le_bytes());
+        assert_eq!(data[22..24], 2u16.to_le_bytes());
+        assert_eq!(data[24..28], 48_000u32.to_le_bytes());
+        assert_eq!(data[40..44], 8u32.to_le_bytes());
+        assert_eq!(data[46..48], 0x7fffi16.to_le_bytes());
+        assert_eq!(data[48..50], (-0x7fffi16).to_le_bytes());
+    }
+
+    #[test]
+    fn sizes_written_on_drop() {
+        let path = env::temp_dir().join("gb_emu_wav_writer_drop_test.wav");
+        let path = path.to_str().unwrap();
+        let mut writer = WavWriter::create(path, 1, 48_000).unwrap();
+        writer.write_samples(&[0.0, 1.0]).unwrap();
+        drop(writer);
+
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(data[4..8], 40u32.to_le_bytes());
+        assert_eq!(data[40..44], 4u32.to_le_bytes());
+    }
+
+    #[test]
+    fn full_file() {
+        let path = env::temp_dir().join("gb_emu_wav_writer_full_test.wav");
+        let path = path.to_str().unwrap();
+        let mut writer = WavWriter::create(path, 1, 48_000).unwrap();
+        writer.data_size = MAX_DATA_SIZE - 2;
+        writer.write_samples(&[0.0]).unwrap();
+        assert!(writer.write_samples(&[0.0]).is_err());
+        writer.finish().unwrap();
+
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(data.len(), 44 + 2);
+        assert_eq!(data[4..8], u32::MAX.to_le_bytes());
+        assert_eq!(data[40..44], MAX_DATA_SIZE.to_le_bytes());
+    }
+
+    #[test]
+    fn channel_paths() {
+        assert_eq!(channel_path("out/sound.wav", 2), "out/sound_ch2.wav");
+        assert_eq!(channel_path("sound", 4), "sound_ch4");
+    }
+}
@@ -0,0 +1,139 @@
+use super::length_counter::LengthCounter;
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+// Plays the 32 4-bit samples in wave ram, high nibble first
+pub struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: LengthCounter,
+    ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            length: LengthCounter::new(256),
+            ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    // Register 0 - 4 are NR30 - NR34
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value.get_bit(7);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume_code = (value >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | u16::from(value),
+            4 => {
+                self.frequency = (self.frequency & 0xff) | (u16::from(value & 0b111) << 8);
+                self.length.set_enabled(value.get_bit(6));
+                if value.get_bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_ram(&self, index: usize) -> u8 {
+        self.ram[index]
+    }
+
+    pub fn set_ram(&mut self, index: usize, value: u8) {
+        self.ram[index] = value;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.get_period();
+        self.position = 0;
+    }
+
+    fn get_period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 2
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // Digital output from 0 to 15
+    pub fn get_output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = self.ram[usize::from(self.position / 2)];
+        let sample = if self.position & 0b1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        };
+        // Muted, 100%, 50% and 25% volume
+        match self.volume_code {
+            0 => 0,
+            x => sample >> (x - 1),
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.dac_enabled);
+        writer.write_u8(self.volume_code);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.position);
+        self.length.save_state(writer);
+        writer.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.dac_enabled = reader.read_bool()?;
+        self.volume_code = reader.read_u8()? & 0b11;
+        self.frequency = reader.read_u16()? & 0x7ff;
+        self.timer = reader.read_u32()?;
+        self.position = reader.read_u8()? % 32;
+        self.length.load_state(reader)?;
+        reader.read_bytes_into(&mut self.ram)
+    }
+}
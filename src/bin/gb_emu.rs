@@ -0,0 +1,370 @@
+extern crate gb_emu;
+use gb_emu::{App, ColourMode, Command, Debugger, Emulator, GdbStub, JoyPad, Palette, Palettes};
+use gb_emu::{TerminalApp, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+const USAGE: &str = "usage: gb_emu [options] <rom>
+
+Runs a rom headless, for CI and other scripts, or in the terminal.
+
+options:
+  --frames <n>              stop after n frames
+  --cycles <n>              stop after n cycles
+  --boot-rom <path>         run a boot rom before the cartridge
+  --input <path>            scripted input, see below
+  --screenshot <n>:<path>   save frame n as a PPM image, can be repeated
+  --palette <name>          grey, dmg, pocket or light, for screenshots
+                            and the terminal
+  --terminal                play in the terminal until q is pressed. Arrow
+                            keys are the d-pad, z and x are A and B, enter
+                            is start and space is select. The other run
+                            options are ignored
+  --debug                   run in the debugger, reading commands from
+                            stdin. The other run options are ignored
+  --gdb <port>              wait for gdb to connect to localhost:port, with
+                            target remote. The other run options are ignored
+  --serial <path>           write the serial output to a file, or - for stdout
+  --expect-serial <text>    stop once the serial output contains text,
+                            and exit with 1 if it never does
+
+Without --frames or --cycles, the rom runs for 600 frames. Frame numbers
+count the frames completed, so input for frame 0 is held from power on.
+
+Each line of an input file is a frame number and the buttons held from
+that frame on, separated by commas, or - for none. Buttons are a, b,
+select, start, right, left, up and down. Lines starting with # are ignored.
+
+  60 start
+  62 -
+  120 a,right
+
+Exits with 0 on success, 1 if the expected serial output wasn't seen,
+and 2 for bad arguments or a rom that can't be loaded.";
+
+const DEFAULT_FRAMES: u64 = 600;
+
+struct Options {
+    rom: String,
+    boot_rom: Option<String>,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    input: Option<String>,
+    screenshots: Vec<(u64, String)>,
+    palette: Palette,
+    serial: Option<String>,
+    expect_serial: Option<String>,
+    terminal: bool,
+    debug: bool,
+    gdb_port: Option<u16>,
+}
+
+// The debuggers run without a display, the joypad is left unpressed
+struct NoDisplay;
+
+impl App for NoDisplay {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        Command::Continue
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|x| x == "-h" || x == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(true) => (),
+        Ok(false) => {
+            eprintln!("expected serial output not seen");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        boot_rom: None,
+        frames: None,
+        cycles: None,
+        input: None,
+        screenshots: Vec::new(),
+        palette: Palette::greyscale(),
+        serial: None,
+        expect_serial: None,
+        terminal: false,
+        debug: false,
+        gdb_port: None,
+    };
+    let mut rom = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            rom = Some(arg.clone());
+            continue;
+        }
+        if arg == "--terminal" {
+            options.terminal = true;
+            continue;
+        }
+        if arg == "--debug" {
+            options.debug = true;
+            continue;
+        }
+
+        let value = match args.next() {
+            Some(x) => x,
+            None => return Err(format!("{} needs a value", arg)),
+        };
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(value)?),
+            "--cycles" => options.cycles = Some(parse_number(value)?),
+            "--boot-rom" => options.boot_rom = Some(value.clone()),
+            "--input" => options.input = Some(value.clone()),
+            "--screenshot" => {
+                let mut parts = value.splitn(2, ':');
+                let frame = parse_number(parts.next().unwrap_or(""))?;
+                match parts.next() {
+                    Some(path) if !path.is_empty() => {
+                        options.screenshots.push((frame, path.to_string()))
+                    }
+                    _ => return Err(format!("bad screenshot {}, expected <n>:<path>", value)),
+                }
+            }
+            "--palette" => {
+                options.palette = match value.as_str() {
+                    "grey" => Palette::greyscale(),
+                    "dmg" => Palette::dmg_green(),
+                    "pocket" => Palette::pocket_grey(),
+                    "light" => Palette::light(),
+                    _ => return Err(format!("unknown palette {}", value)),
+                }
+            }
+            "--gdb" => {
+                options.gdb_port = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("{} is not a port", value))?,
+                )
+            }
+            "--serial" => options.serial = Some(value.clone()),
+            "--expect-serial" => options.expect_serial = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| "no rom given".to_string())?;
+    Ok(options)
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+// Frame numbers and the joypad state from that frame, in the
+// JoyPad::get_state format
+fn parse_input(script: &str) -> Result<Vec<(u64, u8)>, String> {
+    let mut inputs = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("input line {}: {}", i + 1, message);
+
+        let mut parts = line.split_whitespace();
+        let frame = parts
+            .next()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| error("expected a frame number"))?;
+        let buttons = parts.next().unwrap_or("-");
+        if parts.next().is_some() {
+            return Err(error("unexpected text after the buttons"));
+        }
+
+        let mut state = 0;
+        if buttons != "-" {
+            for button in buttons.split(',') {
+                let bit = match button {
+                    "a" => 0,
+                    "b" => 1,
+                    "select" => 2,
+                    "start" => 3,
+                    "right" => 4,
+                    "left" => 5,
+                    "up" => 6,
+                    "down" => 7,
+                    _ => return Err(error(&format!("unknown button {}", button))),
+                };
+                state |= 0b1 << bit;
+            }
+        }
+        inputs.push((frame, state));
+    }
+    inputs.sort_by_key(|x| x.0);
+    Ok(inputs)
+}
+
+// Returns false if the expected serial output wasn't seen
+fn run(options: &Options) -> Result<bool, String> {
+    let inputs = match &options.input {
+        Some(path) => parse_input(&fs::read_to_string(path).map_err(|e| file_error(path, e))?)?,
+        None => Vec::new(),
+    };
+    let boot_rom = options.boot_rom.as_deref();
+    let mut emulator = Emulator::new(boot_rom, &options.rom).map_err(|e| e.to_string())?;
+    emulator.set_palettes(Palettes::all(options.palette));
+
+    if options.terminal {
+        let mut app = TerminalApp::new(options.palette, ColourMode::detect())
+            .map_err(|e| format!("can't use the terminal: {}", e))?;
+        emulator.run(&mut app);
+    } else if options.debug {
+        let mut debugger = Debugger::new(emulator);
+        let stdin = io::stdin();
+        debugger
+            .run_repl(&mut NoDisplay, stdin.lock(), io::stdout())
+            .map_err(|e| e.to_string())?;
+        emulator = debugger.into_emulator();
+    } else if let Some(port) = options.gdb_port {
+        eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+        let mut stub = GdbStub::listen(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+        let mut debugger = Debugger::new(emulator);
+        stub.run(&mut debugger, &mut NoDisplay)
+            .map_err(|e| e.to_string())?;
+        emulator = debugger.into_emulator();
+    } else {
+        run_headless(&mut emulator, options, &inputs)?;
+    }
+
+    match options.serial.as_deref() {
+        Some("-") => io::stdout()
+            .write_all(emulator.get_serial_data())
+            .map_err(|e| e.to_string())?,
+        Some(path) => {
+            fs::write(path, emulator.get_serial_data()).map_err(|e| file_error(path, e))?
+        }
+        None => (),
+    }
+    match &options.expect_serial {
+        Some(text) => Ok(serial_contains(&emulator, text)),
+        None => Ok(true),
+    }
+}
+
+fn run_headless(
+    emulator: &mut Emulator,
+    options: &Options,
+    inputs: &[(u64, u8)],
+) -> Result<(), String> {
+    let max_frames = match (options.frames, options.cycles) {
+        (None, None) => Some(DEFAULT_FRAMES),
+        (frames, _) => frames,
+    };
+    let max_cycles = options.cycles.unwrap_or(u64::MAX);
+    let mut next_input = 0;
+
+    loop {
+        let frame = emulator.get_frame_count();
+        if matches!(max_frames, Some(x) if frame >= x) {
+            break;
+        }
+        while let Some((input_frame, state)) = inputs.get(next_input) {
+            if *input_frame > frame {
+                break;
+            }
+            emulator.get_joypad().set_state(*state);
+            next_input += 1;
+        }
+
+        let completed = emulator.run_frame_until(max_cycles);
+        for (_, path) in options
+            .screenshots
+            .iter()
+            .filter(|x| completed && x.0 == emulator.get_frame_count())
+        {
+            save_screenshot(emulator, path)?;
+        }
+
+        if let Some(text) = &options.expect_serial {
+            if serial_contains(emulator, text) {
+                break;
+            }
+        }
+        if !completed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn serial_contains(emulator: &Emulator, text: &str) -> bool {
+    String::from_utf8_lossy(emulator.get_serial_data()).contains(text)
+}
+
+// Binary PPM, which needs no encoder
+fn save_screenshot(emulator: &Emulator, path: &str) -> Result<(), String> {
+    let mut file = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    for pixel in emulator.get_frame_rgba().chunks(4) {
+        file.extend(&pixel[0..3]);
+    }
+    fs::write(path, file).map_err(|e| file_error(path, e))
+}
+
+fn file_error(path: &str, e: io::Error) -> String {
+    format!("{}: {}", path, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_script() {
+        let script = "# comment\n120 a,right\n\n60 start\n62 -\n";
+        let inputs = parse_input(script).unwrap();
+        assert_eq!(inputs, [(60, 0b1000), (62, 0), (120, 0b0001_0001)]);
+
+        assert!(parse_input("10 jump").is_err());
+        assert!(parse_input("start").is_err());
+    }
+
+    #[test]
+    fn arguments() {
+        let args: Vec<String> = ["--frames", "10", "--screenshot", "5:a.ppm", "rom.gb"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let options = parse_args(&args).unwrap();
+        assert_eq!(options.rom, "rom.gb");
+        assert_eq!(options.frames, Some(10));
+        assert_eq!(options.screenshots, [(5, "a.ppm".to_string())]);
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["--frames".to_string()]).is_err());
+    }
+}
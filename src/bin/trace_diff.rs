@@ -0,0 +1,188 @@
+extern crate gb_emu;
+use gb_emu::{Emulator, EmulatorSource, LogSource, TraceDiff};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::process;
+
+const USAGE: &str = "usage: trace_diff [options] <rom> [<rom b>]
+
+Runs two emulators side by side, a and b, an instruction at a time, and
+stops at the first instruction where their registers, memory writes or
+io registers differ. The steps around it are printed, steps that match
+are shown once. b runs the same rom as a unless another is given.
+
+options:
+  --log <path>            compare a against a log instead of a second
+                          emulator, with lines like
+                          A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+                          Logs don't have memory writes or io registers,
+                          and the comparison stops where the log ends
+  --boot-rom <path>       run a boot rom before the cartridge in a
+  --boot-rom-b <path>     run a boot rom before the cartridge in b
+  --state <path>          start a from a save state
+  --state-b <path>        start b from a save state
+  --skip-boot             run each emulator until the boot rom is
+                          unmapped before comparing
+  --steps <n>             give up after n matching steps, 100000000
+                          by default
+  --context <n>           steps to show before and after, 8 by default
+
+Exits with 0 if the traces match, 1 if they diverge, and 2 for bad
+arguments or a rom that can't be loaded.";
+
+const DEFAULT_STEPS: u64 = 100_000_000;
+
+#[derive(Default)]
+struct Config {
+    rom: Option<String>,
+    boot_rom: Option<String>,
+    state: Option<String>,
+}
+
+struct Options {
+    a: Config,
+    b: Config,
+    log: Option<String>,
+    skip_boot: bool,
+    steps: u64,
+    context: usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|x| x == "-h" || x == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(true) => println!("traces match"),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        a: Config::default(),
+        b: Config::default(),
+        log: None,
+        skip_boot: false,
+        steps: DEFAULT_STEPS,
+        context: 8,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if options.a.rom.is_none() {
+                options.a.rom = Some(arg.clone());
+            } else if options.b.rom.is_none() {
+                options.b.rom = Some(arg.clone());
+            } else {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            continue;
+        }
+        if arg == "--skip-boot" {
+            options.skip_boot = true;
+            continue;
+        }
+
+        let value = match args.next() {
+            Some(x) => x,
+            None => return Err(format!("{} needs a value", arg)),
+        };
+        match arg.as_str() {
+            "--log" => options.log = Some(value.clone()),
+            "--boot-rom" => options.a.boot_rom = Some(value.clone()),
+            "--boot-rom-b" => options.b.boot_rom = Some(value.clone()),
+            "--state" => options.a.state = Some(value.clone()),
+            "--state-b" => options.b.state = Some(value.clone()),
+            "--steps" => options.steps = parse_number(value)?,
+            "--context" => options.context = parse_number(value)? as usize,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.a.rom.is_none() {
+        return Err("no rom given".to_string());
+    }
+    if options.log.is_some() {
+        let b = &options.b;
+        if b.rom.is_some() || b.boot_rom.is_some() || b.state.is_some() {
+            return Err("b can't be configured when comparing against a log".to_string());
+        }
+    }
+    if options.b.rom.is_none() {
+        options.b.rom = options.a.rom.clone();
+    }
+    Ok(options)
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+fn file_error(path: &str, e: io::Error) -> String {
+    format!("{}: {}", path, e)
+}
+
+fn create_emulator(config: &Config, skip_boot: bool) -> Result<Emulator, String> {
+    let rom = config.rom.as_deref().unwrap_or_default();
+    let mut emulator = Emulator::new(config.boot_rom.as_deref(), rom).map_err(|e| e.to_string())?;
+    if let Some(path) = &config.state {
+        let state = fs::read(path).map_err(|e| file_error(path, e))?;
+        emulator.load_state(&state).map_err(|e| e.to_string())?;
+    }
+    if skip_boot {
+        while emulator.is_boot_rom_enabled() {
+            emulator.run_frame_until(emulator.get_cycles() + 1);
+        }
+    }
+    Ok(emulator)
+}
+
+// Returns false if the traces diverge
+fn run(options: &Options) -> Result<bool, String> {
+    let mut diff = TraceDiff::new();
+    diff.set_context(options.context);
+    diff.set_max_steps(options.steps);
+    diff.set_allow_early_end(options.log.is_some());
+
+    let mut a = EmulatorSource::new(create_emulator(&options.a, options.skip_boot)?);
+    let result = match &options.log {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| file_error(path, e))?;
+            diff.run(&mut a, &mut LogSource::new(BufReader::new(file)))
+        }
+        None => {
+            let b = create_emulator(&options.b, options.skip_boot)?;
+            diff.run(&mut a, &mut EmulatorSource::new(b))
+        }
+    };
+
+    match result.map_err(|e| e.to_string())? {
+        Some(divergence) => {
+            let stdout = io::stdout();
+            divergence
+                .write_report(&mut stdout.lock())
+                .map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
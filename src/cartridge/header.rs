@@ -0,0 +1,211 @@
+use crate::error::{Error, Result};
+use crate::memory::locations::*;
+use crate::memory::sizes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderMismatch {
+    HeaderChecksum { expected: u8, actual: u8 },
+    GlobalChecksum { expected: u16, actual: u16 },
+    RomSize { expected: usize, actual: usize },
+}
+
+// The cartridge header found at 0x0100 - 0x014f of every rom
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // Only present on newer (mostly CGB) carts, which shortened the title
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: u8,
+    pub new_licensee_code: String,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination_code: u8,
+    pub old_licensee_code: u8,
+    pub mask_rom_version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn from_rom(rom: &[u8]) -> Result<CartridgeHeader> {
+        if rom.len() <= HEADER_END {
+            return Err(Error::TruncatedRom(rom.len()));
+        }
+
+        let cgb_flag = rom[CGB_FLAG];
+        let title_end = if cgb_flag & 0x80 != 0 {
+            MANUFACTURER_CODE
+        } else {
+            CGB_FLAG + 1
+        };
+
+        let manufacturer_code = {
+            let code = &rom[MANUFACTURER_CODE..CGB_FLAG];
+            if cgb_flag & 0x80 != 0 && code.iter().all(u8::is_ascii_alphanumeric) {
+                Some(ascii_string(code))
+            } else {
+                None
+            }
+        };
+
+        let global_checksum = {
+            let high = u16::from(rom[GLOBAL_CHECKSUM]);
+            let low = u16::from(rom[GLOBAL_CHECKSUM + 1]);
+            (high << 8) | low
+        };
+
+        Ok(CartridgeHeader {
+            title: ascii_string(&rom[TITLE..title_end]),
+            manufacturer_code,
+            cgb_flag,
+            new_licensee_code: ascii_string(&rom[NEW_LICENSEE_CODE..NEW_LICENSEE_CODE + 2]),
+            sgb_flag: rom[SGB_FLAG],
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size_code: rom[ROM_SIZE],
+            ram_size_code: rom[RAM_SIZE],
+            destination_code: rom[DESTINATION_CODE],
+            old_licensee_code: rom[OLD_LICENSEE_CODE],
+            mask_rom_version: rom[MASK_ROM_VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum,
+        })
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    pub fn requires_cgb(&self) -> bool {
+        self.cgb_flag == 0xc0
+    }
+
+    // SGB functions are only enabled if the old licensee code is 0x33
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee_code == 0x33
+    }
+
+    // The old licensee code 0x33 means the new licensee code is used instead
+    pub fn licensee_code(&self) -> String {
+        if self.old_licensee_code == 0x33 {
+            self.new_licensee_code.clone()
+        } else {
+            format!("{:02X}", self.old_licensee_code)
+        }
+    }
+
+    // Rom size in bytes, or None for unknown size codes
+    pub fn rom_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some((32 * 1024) << self.rom_size_code),
+            _ => None,
+        }
+    }
+
+    // Size of the cartridge's external ram in bytes
+    pub fn ram_size(&self) -> usize {
+        match self.ram_size_code {
+            0x01 => 2 * 1024,
+            0x02 => sizes::EXRAM,
+            0x03 => 4 * sizes::EXRAM,
+            0x04 => 16 * sizes::EXRAM,
+            0x05 => 8 * sizes::EXRAM,
+            _ => 0,
+        }
+    }
+
+    pub fn validate(&self, rom: &[u8]) -> Vec<HeaderMismatch> {
+        let mut mismatches = Vec::new();
+
+        let header_checksum = compute_header_checksum(rom);
+        if header_checksum != self.header_checksum {
+            mismatches.push(HeaderMismatch::HeaderChecksum {
+                expected: self.header_checksum,
+                actual: header_checksum,
+            });
+        }
+
+        let global_checksum = compute_global_checksum(rom);
+        if global_checksum != self.global_checksum {
+            mismatches.push(HeaderMismatch::GlobalChecksum {
+                expected: self.global_checksum,
+                actual: global_checksum,
+            });
+        }
+
+        if let Some(rom_size) = self.rom_size() {
+            if rom_size != rom.len() {
+                mismatches.push(HeaderMismatch::RomSize {
+                    expected: rom_size,
+                    actual: rom.len(),
+                });
+            }
+        }
+
+        mismatches
+    }
+}
+
+fn ascii_string(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|x| **x != 0)
+        .map(|x| {
+            if x.is_ascii_graphic() || *x == b' ' {
+                char::from(*x)
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+// Checked by the boot rom, a mismatch locks up real hardware
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |x, v| x.wrapping_sub(*v).wrapping_sub(1))
+}
+
+// Sum of every byte in the rom, except the two checksum bytes
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != GLOBAL_CHECKSUM && *i != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |x, (_, v)| x.wrapping_add(u16::from(*v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_validate() {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE..TITLE + 6].copy_from_slice(b"TETRIS");
+        rom[OLD_LICENSEE_CODE] = 0x01;
+        rom[RAM_SIZE] = 0x03;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let global_checksum = compute_global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM] = (global_checksum >> 8) as u8;
+        rom[GLOBAL_CHECKSUM + 1] = global_checksum as u8;
+
+        let header = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.licensee_code(), "01");
+        assert_eq!(header.rom_size(), Some(0x8000));
+        assert_eq!(header.ram_size(), 4 * sizes::EXRAM);
+        assert!(header.validate(&rom).is_empty());
+
+        rom[0x200] = 0xff;
+        let mismatches = header.validate(&rom);
+        assert_eq!(
+            mismatches,
+            vec![HeaderMismatch::GlobalChecksum {
+                expected: global_checksum,
+                actual: global_checksum.wrapping_add(0xff),
+            }]
+        );
+    }
+}
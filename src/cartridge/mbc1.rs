@@ -1,6 +1,8 @@
-use super::{Cartridge, ROM_BANK_SIZE};
+use super::{create_ram_banks, get_ram_banks, set_ram_banks, Cartridge, ROM_BANK_SIZE};
+use super::{load_ram_banks, save_ram_banks};
+use crate::error::Result;
 use crate::memory::locations::*;
-use crate::memory::sizes;
+use crate::save_state::{bad_state, StateReader, StateWriter};
 
 enum RomRamMode {
     RomBankingMode,
@@ -18,7 +20,7 @@ pub struct Mbc1 {
 }
 
 impl Mbc1 {
-    pub fn new(data: &[u8]) -> Mbc1 {
+    pub fn new(data: &[u8], ram_size: usize) -> Mbc1 {
         assert!(data.len() > ROM_BANK_SIZE);
 
         let (lower, upper) = data.split_at(ROM_BANK_SIZE);
@@ -29,13 +31,7 @@ impl Mbc1 {
             other_rom_banks.push(bank.to_vec());
         }
 
-        let ram_banks = {
-            let mut x = Vec::new();
-            for _ in 0..4 {
-                x.push(vec![0; sizes::EXRAM]);
-            }
-            x
-        };
+        let ram_banks = create_ram_banks(ram_size);
 
         Mbc1 {
             rom_bank_zero,
@@ -59,10 +55,12 @@ impl Cartridge for Mbc1 {
                 assert!(index - ROM_BANK_SIZE < bank.len());
                 bank[index - ROM_BANK_SIZE]
             }
-            EXRAM_START...EXRAM_END => {
-                let bank = &self.ram_banks[self.ram_bank_index];
-                bank[index - EXRAM_START]
-            }
+            EXRAM_START...EXRAM_END => self
+                .ram_banks
+                .get(self.ram_bank_index)
+                .and_then(|bank| bank.get(index - EXRAM_START))
+                .cloned()
+                .unwrap_or(0xff),
             _ => unreachable!(),
         }
     }
@@ -70,8 +68,10 @@ impl Cartridge for Mbc1 {
     fn set_u8(&mut self, index: usize, value: u8) {
         match index {
             EXRAM_START...EXRAM_END => {
-                let bank = &mut self.ram_banks[self.ram_bank_index];
-                bank[index - EXRAM_START] = value;
+                let bank = self.ram_banks.get_mut(self.ram_bank_index);
+                if let Some(x) = bank.and_then(|bank| bank.get_mut(index - EXRAM_START)) {
+                    *x = value;
+                }
             }
             0x0000...0x1fff => match value & 0x0f {
                 0x0a => self.ram_enabled = true,
@@ -118,10 +118,41 @@ impl Cartridge for Mbc1 {
     }
 
     fn get_ram(&self) -> Vec<u8> {
-        unimplemented!();
+        get_ram_banks(&self.ram_banks)
+    }
+
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()> {
+        set_ram_banks(&mut self.ram_banks, all_ram)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.rom_bank_index as u8);
+        writer.write_bool(self.ram_enabled);
+        writer.write_u8(self.ram_bank_index as u8);
+        writer.write_u8(match self.rom_ram_mode {
+            RomRamMode::RomBankingMode => 0,
+            RomRamMode::RamBankingMode => 1,
+        });
+        save_ram_banks(writer, &self.ram_banks);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let rom_bank_index = usize::from(reader.read_u8()?);
+        if rom_bank_index == 0 || rom_bank_index > self.other_rom_banks.len() {
+            return Err(bad_state("mbc1 rom bank out of range"));
+        }
+        self.rom_bank_index = rom_bank_index;
+        self.ram_enabled = reader.read_bool()?;
+        self.ram_bank_index = usize::from(reader.read_u8()?);
+        self.rom_ram_mode = match reader.read_u8()? {
+            0 => RomRamMode::RomBankingMode,
+            1 => RomRamMode::RamBankingMode,
+            _ => return Err(bad_state("bad mbc1 banking mode")),
+        };
+        load_ram_banks(reader, &mut self.ram_banks)
     }
 
-    fn set_ram(&mut self, _: &[u8]) {
-        unimplemented!();
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank_index
     }
 }
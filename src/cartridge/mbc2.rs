@@ -1,7 +1,11 @@
 use super::{Cartridge, ROM_BANK_SIZE};
+use crate::error::{Error, Result};
 use crate::memory::locations::*;
+use crate::save_state::{bad_state, StateReader, StateWriter};
 
-const RAMEND: usize = EXRAM_START + 512;
+// MBC2 has 512 4-bit values of built in ram, which are
+// echoed throughout the 0xa000 - 0xbfff range
+const RAM_SIZE: usize = 512;
 
 pub struct Mbc2 {
     rom_bank_zero: Vec<u8>,
@@ -23,7 +27,7 @@ impl Mbc2 {
             other_rom_banks.push(bank.to_vec());
         }
 
-        let ram = vec![0; 512];
+        let ram = vec![0; RAM_SIZE];
 
         Mbc2 {
             rom_bank_zero,
@@ -45,33 +49,113 @@ impl Cartridge for Mbc2 {
                 assert!(index - ROM_BANK_SIZE < bank.len());
                 bank[index - ROM_BANK_SIZE]
             }
-            EXRAM_START...RAMEND => self.ram[index - EXRAM_START] & 0xf,
+            EXRAM_START...EXRAM_END => self.ram[(index - EXRAM_START) % RAM_SIZE] & 0xf,
             _ => unreachable!(),
         }
     }
 
     fn set_u8(&mut self, index: usize, value: u8) {
         match index {
-            EXRAM_START...RAMEND => {
-                self.ram[index - EXRAM_START] = value & 0xf;
+            EXRAM_START...EXRAM_END => {
+                self.ram[(index - EXRAM_START) % RAM_SIZE] = value & 0xf;
             }
             0x0000...0x1fff => {
                 self.ram_enabled = index & 0x100 == 0;
             }
+            // Only with bit 8 of the address set. Bank 0 is
+            // always mapped low, so selecting it gives bank 1
             0x2000...0x3fff => {
-                if index & 0x100 != 1 {
-                    self.rom_bank_index = usize::from(value & 0xf);
+                if index & 0x100 != 0 {
+                    self.rom_bank_index = match value & 0xf {
+                        0x0 => 0x1,
+                        x => usize::from(x),
+                    };
                 }
             }
             _ => panic!("bad write index"),
         }
     }
 
+    // Saved as one byte per 4-bit value, low nibble only,
+    // which is the 512 byte layout other emulators use
     fn get_ram(&self) -> Vec<u8> {
-        unimplemented!();
+        self.ram.clone()
     }
 
-    fn set_ram(&mut self, _: &[u8]) {
-        unimplemented!();
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()> {
+        if all_ram.len() > RAM_SIZE {
+            return Err(Error::BadSaveSize {
+                expected: RAM_SIZE,
+                actual: all_ram.len(),
+            });
+        }
+
+        for (x, v) in self.ram.iter_mut().zip(all_ram.iter()) {
+            *x = v & 0xf;
+        }
+        Ok(())
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.rom_bank_index as u8);
+        writer.write_bool(self.ram_enabled);
+        writer.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let rom_bank_index = usize::from(reader.read_u8()?);
+        if rom_bank_index == 0 || rom_bank_index > self.other_rom_banks.len() {
+            return Err(bad_state("mbc2 rom bank out of range"));
+        }
+        self.rom_bank_index = rom_bank_index;
+        self.ram_enabled = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.ram)
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mbc2;
+    use crate::cartridge::{Cartridge, ROM_BANK_SIZE};
+
+    #[test]
+    fn ram_save_layout() {
+        let rom = vec![0; ROM_BANK_SIZE * 2];
+        let mut cart = Mbc2::new(&rom);
+        cart.set_u8(0xa000, 0xf3);
+        cart.set_u8(0xa1ff, 0x0c);
+        // The 512 values are echoed up to 0xbfff
+        assert_eq!(cart.get_u8(0xa200), 0x03);
+
+        let ram = cart.get_ram();
+        assert_eq!(ram.len(), 512);
+        assert_eq!(ram[0], 0x03);
+        assert_eq!(ram[511], 0x0c);
+
+        let mut loaded = Mbc2::new(&rom);
+        loaded.set_ram(&ram).unwrap();
+        assert_eq!(loaded.get_u8(0xa000), 0x03);
+        assert_eq!(loaded.get_u8(0xbfff), 0x0c);
+    }
+
+    #[test]
+    fn rom_bank_select() {
+        let mut rom = Vec::new();
+        for i in 0..4 {
+            rom.extend(vec![i as u8; ROM_BANK_SIZE]);
+        }
+        let mut cart = Mbc2::new(&rom);
+        assert_eq!(cart.get_u8(0x4000), 1);
+        cart.set_u8(0x2100, 3);
+        assert_eq!(cart.get_u8(0x4000), 3);
+        cart.set_u8(0x2100, 0);
+        assert_eq!(cart.get_u8(0x4000), 1);
+        // Writes with bit 8 clear don't change the bank
+        cart.set_u8(0x2000, 2);
+        assert_eq!(cart.get_u8(0x4000), 1);
     }
 }
@@ -1,16 +1,9 @@
-use super::{Cartridge, ROM_BANK_SIZE};
+use super::rtc::{self, Rtc, RtcClock};
+use super::{create_ram_banks, get_ram_banks, set_ram_banks, Cartridge, ROM_BANK_SIZE};
+use super::{load_ram_banks, save_ram_banks};
+use crate::error::{Error, Result};
 use crate::memory::locations::*;
-use crate::memory::sizes;
-use std::default::Default;
-
-#[derive(Default)]
-struct RtcRegisters {
-    seconds: u8,
-    minutes: u8,
-    hours: u8,
-    day_low_bits: u8,
-    day_high_bits: u8,
-}
+use crate::save_state::{bad_state, StateReader, StateWriter};
 
 pub struct Mbc3 {
     rom_bank_zero: Vec<u8>,
@@ -19,13 +12,13 @@ pub struct Mbc3 {
     ram_enabled: bool,
     ram_bank_index: usize,
     ram_banks: Vec<Vec<u8>>,
-    rtc_registers: RtcRegisters,
-    latch_clock_data_reg: u8,
-    clock_data_latch: bool,
+    // Only carts 0x0f and 0x10 have the real time clock
+    has_timer: bool,
+    rtc: Rtc,
 }
 
 impl Mbc3 {
-    pub fn new(data: &[u8]) -> Mbc3 {
+    pub fn new(data: &[u8], ram_size: usize, has_timer: bool) -> Mbc3 {
         assert!(data.len() > ROM_BANK_SIZE);
 
         let (lower, upper) = data.split_at(ROM_BANK_SIZE);
@@ -36,13 +29,7 @@ impl Mbc3 {
             other_rom_banks.push(bank.to_vec());
         }
 
-        let ram_banks = {
-            let mut x = Vec::new();
-            for _ in 0..4 {
-                x.push(vec![0; sizes::EXRAM]);
-            }
-            x
-        };
+        let ram_banks = create_ram_banks(ram_size);
 
         Mbc3 {
             rom_bank_zero,
@@ -51,11 +38,8 @@ impl Mbc3 {
             ram_enabled: false,
             ram_bank_index: 0,
             ram_banks,
-            rtc_registers: Default::default(),
-            // latch clock data reg need to start at
-            // non zero value for correct operation
-            latch_clock_data_reg: 1,
-            clock_data_latch: false,
+            has_timer,
+            rtc: Rtc::new(),
         }
     }
 }
@@ -71,15 +55,13 @@ impl Cartridge for Mbc3 {
                 bank[index - ROM_BANK_SIZE]
             }
             EXRAM_START...EXRAM_END => match self.ram_bank_index {
-                0x00...0x03 => {
-                    let bank = &self.ram_banks[self.ram_bank_index];
-                    bank[index - EXRAM_START]
-                }
-                0x08 => self.rtc_registers.seconds,
-                0x09 => self.rtc_registers.minutes,
-                0x0a => self.rtc_registers.hours,
-                0x0b => self.rtc_registers.day_low_bits,
-                0x0c => self.rtc_registers.day_high_bits,
+                0x00...0x03 => self
+                    .ram_banks
+                    .get(self.ram_bank_index)
+                    .and_then(|bank| bank.get(index - EXRAM_START))
+                    .cloned()
+                    .unwrap_or(0xff),
+                0x08..=0x0c if self.has_timer => self.rtc.get_u8(self.ram_bank_index),
                 _ => {
                     eprintln!("warning: bad ram bank selected!");
                     0
@@ -93,14 +75,12 @@ impl Cartridge for Mbc3 {
         match index {
             EXRAM_START...EXRAM_END => match self.ram_bank_index {
                 0x00...0x03 => {
-                    let bank = &mut self.ram_banks[self.ram_bank_index];
-                    bank[index - EXRAM_START] = value;
+                    let bank = self.ram_banks.get_mut(self.ram_bank_index);
+                    if let Some(x) = bank.and_then(|bank| bank.get_mut(index - EXRAM_START)) {
+                        *x = value;
+                    }
                 }
-                0x08 => self.rtc_registers.seconds = value,
-                0x09 => self.rtc_registers.minutes = value,
-                0x0a => self.rtc_registers.hours = value,
-                0x0b => self.rtc_registers.day_low_bits = value,
-                0x0c => self.rtc_registers.day_high_bits = value,
+                0x08..=0x0c if self.has_timer => self.rtc.set_u8(self.ram_bank_index, value),
                 _ => eprintln!("warning: bad ram bank selected!"),
             },
             0x0000...0x1fff => match value & 0x0f {
@@ -118,35 +98,115 @@ impl Cartridge for Mbc3 {
                 };
             }
             0x4000...0x5fff => match value {
-                0x00...0x03 => {
-                    self.ram_bank_index = usize::from(value & 0b11);
-                }
-                _ => unimplemented!("rtc register select {}", value),
+                0x00..=0x03 => self.ram_bank_index = usize::from(value),
+                0x08..=0x0c if self.has_timer => self.ram_bank_index = usize::from(value),
+                _ => eprintln!("warning: bad ram bank select {:#04x}", value),
             },
-            0x6000...0x7fff => {
-                // Writing 0 and then 1 to latch_clock_data_reg
-                // enables and disables the clock_data_latch
-                if self.latch_clock_data_reg == 0 && value == 1 {
-                    self.clock_data_latch = !self.clock_data_latch;
-                }
-                self.latch_clock_data_reg = value;
-            }
+            0x6000..=0x7fff if self.has_timer => self.rtc.write_latch(value),
+            0x6000..=0x7fff => (),
             _ => panic!("bad write index"),
         }
     }
 
+    // With a timer, the rtc state is appended to the ram in the
+    // 48 byte footer format used by other emulators
     fn get_ram(&self) -> Vec<u8> {
-        let mut all_ram = Vec::new();
-        for bank in self.ram_banks.iter() {
-            all_ram.extend(bank);
+        let mut all_ram = get_ram_banks(&self.ram_banks);
+        if self.has_timer {
+            all_ram.extend(self.rtc.get_save_footer());
         }
         all_ram
     }
 
-    fn set_ram(&mut self, all_ram: &[u8]) {
-        for (i, chunk) in all_ram.chunks(sizes::EXRAM).enumerate() {
-            self.ram_banks[i].clear();
-            self.ram_banks[i].extend(chunk);
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()> {
+        let ram_size = self.ram_banks.iter().map(Vec::len).sum();
+        let (ram, footer) = all_ram.split_at(all_ram.len().min(ram_size));
+
+        match footer.len() {
+            0 => (),
+            rtc::SAVE_FOOTER_SIZE | rtc::SAVE_FOOTER_SIZE_32BIT if self.has_timer => {
+                self.rtc.set_save_footer(footer)
+            }
+            _ => {
+                let footer_size = if self.has_timer {
+                    rtc::SAVE_FOOTER_SIZE
+                } else {
+                    0
+                };
+                return Err(Error::BadSaveSize {
+                    expected: ram_size + footer_size,
+                    actual: all_ram.len(),
+                });
+            }
+        }
+        set_ram_banks(&mut self.ram_banks, ram)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.rom_bank_index as u8);
+        writer.write_bool(self.ram_enabled);
+        writer.write_u8(self.ram_bank_index as u8);
+        save_ram_banks(writer, &self.ram_banks);
+        self.rtc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let rom_bank_index = usize::from(reader.read_u8()?);
+        if rom_bank_index == 0 || rom_bank_index > self.other_rom_banks.len() {
+            return Err(bad_state("mbc3 rom bank out of range"));
+        }
+        self.rom_bank_index = rom_bank_index;
+        self.ram_enabled = reader.read_bool()?;
+        self.ram_bank_index = usize::from(reader.read_u8()?);
+        load_ram_banks(reader, &mut self.ram_banks)?;
+        self.rtc.load_state(reader)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.has_timer {
+            self.rtc.tick(cycles);
         }
     }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc.set_clock(clock);
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mbc3;
+    use crate::cartridge::{rtc, Cartridge, ROM_BANK_SIZE};
+
+    #[test]
+    fn saves_without_timer() {
+        let rom = vec![0; ROM_BANK_SIZE * 2];
+        let mut cart = Mbc3::new(&rom, 0x2000, false);
+        cart.set_u8(0x0000, 0x0a);
+        cart.set_u8(0xa000, 0x42);
+        // There are no rtc registers to select
+        cart.set_u8(0x4000, 0x08);
+        assert_eq!(cart.get_u8(0xa000), 0x42);
+
+        let ram = cart.get_ram();
+        assert_eq!(ram.len(), 0x2000);
+        assert!(cart.set_ram(&ram).is_ok());
+        let mut footer = ram.clone();
+        footer.extend(&[0; rtc::SAVE_FOOTER_SIZE]);
+        assert!(cart.set_ram(&footer).is_err());
+    }
+
+    #[test]
+    fn saves_with_timer() {
+        let rom = vec![0; ROM_BANK_SIZE * 2];
+        let mut cart = Mbc3::new(&rom, 0x2000, true);
+        let ram = cart.get_ram();
+        assert_eq!(ram.len(), 0x2000 + rtc::SAVE_FOOTER_SIZE);
+        assert!(cart.set_ram(&ram).is_ok());
+        assert!(cart.set_ram(&ram[..0x2000]).is_ok());
+    }
 }
@@ -0,0 +1,178 @@
+use super::{create_ram_banks, get_ram_banks, set_ram_banks, Cartridge, ROM_BANK_SIZE};
+use super::{load_ram_banks, save_ram_banks};
+use crate::error::Result;
+use crate::memory::locations::*;
+use crate::save_state::{StateReader, StateWriter};
+
+pub struct Mbc5 {
+    rom_banks: Vec<Vec<u8>>,
+    rom_bank_index: usize,
+    ram_enabled: bool,
+    ram_bank_index: usize,
+    ram_banks: Vec<Vec<u8>>,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(data: &[u8], ram_size: usize, has_rumble: bool) -> Mbc5 {
+        assert!(data.len() > ROM_BANK_SIZE);
+
+        let mut rom_banks = Vec::new();
+        for bank in data.chunks(ROM_BANK_SIZE) {
+            rom_banks.push(bank.to_vec());
+        }
+
+        let ram_banks = create_ram_banks(ram_size);
+
+        Mbc5 {
+            rom_banks,
+            rom_bank_index: 1,
+            ram_enabled: false,
+            ram_bank_index: 0,
+            ram_banks,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn get_u8(&self, index: usize) -> u8 {
+        match index {
+            ROM_0_START..=ROM_0_END => self.rom_banks[0][index],
+            ROM_N_START..=ROM_N_END => {
+                // Unlike MBC1-3, bank 0 can be mapped into the switchable
+                // area, and out of range banks wrap around the rom size
+                let bank = &self.rom_banks[self.rom_bank_index % self.rom_banks.len()];
+                bank.get(index - ROM_N_START).cloned().unwrap_or(0xff)
+            }
+            EXRAM_START..=EXRAM_END => {
+                if !self.ram_enabled {
+                    return 0xff;
+                }
+                self.ram_banks
+                    .get(self.ram_bank_index)
+                    .and_then(|bank| bank.get(index - EXRAM_START))
+                    .cloned()
+                    .unwrap_or(0xff)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_u8(&mut self, index: usize, value: u8) {
+        match index {
+            EXRAM_START..=EXRAM_END => {
+                let bank = self.ram_banks.get_mut(self.ram_bank_index);
+                match bank.and_then(|bank| bank.get_mut(index - EXRAM_START)) {
+                    Some(x) if self.ram_enabled => *x = value,
+                    _ => (),
+                }
+            }
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            0x2000..=0x2fff => {
+                // Lower 8 bits of the 9 bit rom bank number
+                self.rom_bank_index = (self.rom_bank_index & 0x100) | usize::from(value);
+            }
+            0x3000..=0x3fff => {
+                // Bit 8 of the rom bank number
+                let high = usize::from(value & 0b1) << 8;
+                self.rom_bank_index = high | (self.rom_bank_index & 0xff);
+            }
+            0x4000..=0x5fff => {
+                if self.has_rumble {
+                    // Rumble carts wire bit 3 to the motor instead
+                    // of the ram bank select lines
+                    self.rumble = value & 0b1000 != 0;
+                    self.ram_bank_index = usize::from(value & 0b0111);
+                } else {
+                    self.ram_bank_index = usize::from(value & 0b1111);
+                }
+            }
+            0x6000..=0x7fff => (),
+            _ => panic!("bad write index"),
+        }
+    }
+
+    fn get_ram(&self) -> Vec<u8> {
+        get_ram_banks(&self.ram_banks)
+    }
+
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()> {
+        set_ram_banks(&mut self.ram_banks, all_ram)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.rom_bank_index as u16);
+        writer.write_bool(self.ram_enabled);
+        writer.write_u8(self.ram_bank_index as u8);
+        save_ram_banks(writer, &self.ram_banks);
+        writer.write_bool(self.rumble);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.rom_bank_index = usize::from(reader.read_u16()?);
+        self.ram_enabled = reader.read_bool()?;
+        self.ram_bank_index = usize::from(reader.read_u8()?);
+        load_ram_banks(reader, &mut self.ram_banks)?;
+        self.rumble = reader.read_bool()?;
+        Ok(())
+    }
+
+    fn is_rumbling(&self) -> bool {
+        self.rumble
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank_index % self.rom_banks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mbc5;
+    use crate::cartridge::{Cartridge, ROM_BANK_SIZE};
+    use crate::memory::sizes;
+
+    fn create_rom(banks: usize) -> Vec<u8> {
+        let mut rom = Vec::new();
+        for i in 0..banks {
+            rom.extend(vec![i as u8; ROM_BANK_SIZE]);
+        }
+        rom
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let rom = create_rom(512);
+        let mut cart = Mbc5::new(&rom, 0, false);
+        assert_eq!(cart.get_u8(0x4000), 1);
+
+        cart.set_u8(0x2000, 0x05);
+        assert_eq!(cart.get_u8(0x4000), 5);
+
+        // Bank 0x105
+        cart.set_u8(0x3000, 0x01);
+        assert_eq!(cart.get_u8(0x4000), 0x05);
+        assert_eq!(cart.rom_bank_index, 0x105);
+
+        // Bank 0 can be selected in the switchable area
+        cart.set_u8(0x3000, 0x00);
+        cart.set_u8(0x2000, 0x00);
+        assert_eq!(cart.get_u8(0x4000), 0);
+    }
+
+    #[test]
+    fn rumble() {
+        let rom = create_rom(4);
+        let mut cart = Mbc5::new(&rom, 4 * sizes::EXRAM, true);
+        cart.set_u8(0x0000, 0x0a);
+        cart.set_u8(0x4000, 0b1011);
+        assert!(cart.is_rumbling());
+        cart.set_u8(0xa000, 42);
+        cart.set_u8(0x4000, 0b0011);
+        assert!(!cart.is_rumbling());
+        assert_eq!(cart.get_u8(0xa000), 42);
+    }
+}
@@ -1,13 +1,20 @@
+mod header;
 mod mbc1;
 mod mbc2;
 mod mbc3;
+mod mbc5;
 mod rom_only;
+mod rtc;
+pub use self::header::{CartridgeHeader, HeaderMismatch};
 use self::mbc1::Mbc1;
 use self::mbc2::Mbc2;
 use self::mbc3::Mbc3;
+use self::mbc5::Mbc5;
 use self::rom_only::RomOnly;
-use crate::memory::locations::*;
-use std::fs;
+pub use self::rtc::RtcClock;
+use crate::error::{Error, Result};
+use crate::memory::sizes;
+use crate::save_state::{StateReader, StateWriter};
 
 const ROM_BANK_SIZE: usize = 0x4000;
 
@@ -15,17 +22,21 @@ enum CartType {
     RomOnly,
     Mbc1,
     Mbc2,
-    Mbc3,
+    Mbc3 { timer: bool },
+    Mbc5 { rumble: bool },
 }
 
 impl CartType {
-    fn try_from_u8(value: u8) -> Result<CartType, String> {
+    fn try_from_u8(value: u8) -> Result<CartType> {
         match value {
             0x00 => Ok(CartType::RomOnly),
             0x01 | 0x02 | 0x03 => Ok(CartType::Mbc1),
             0x05 | 0x06 => Ok(CartType::Mbc2),
-            0x0f...0x13 => Ok(CartType::Mbc3),
-            _ => Err(format!("Unknown cart type {:#04x}", value)),
+            0x0f | 0x10 => Ok(CartType::Mbc3 { timer: true }),
+            0x11..=0x13 => Ok(CartType::Mbc3 { timer: false }),
+            0x19..=0x1b => Ok(CartType::Mbc5 { rumble: false }),
+            0x1c..=0x1e => Ok(CartType::Mbc5 { rumble: true }),
+            _ => Err(Error::UnsupportedMapper(value)),
         }
     }
 }
@@ -34,28 +45,105 @@ pub trait Cartridge {
     fn get_u8(&self, index: usize) -> u8;
     fn set_u8(&mut self, index: usize, value: u8);
     fn get_ram(&self) -> Vec<u8>;
-    fn set_ram(&mut self, all_ram: &[u8]);
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()>;
+
+    // Mapper registers and ram for save states. The rom isn't
+    // saved, states are only loaded back into the same cartridge
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()>;
+
+    // Called after every instruction, for carts with hardware that runs on its own
+    fn tick(&mut self, _cycles: u64) {}
+
+    // Only carts with a real time clock (MBC3 0x0f and 0x10) use this
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    // Only carts with a rumble motor (MBC5 0x1c - 0x1e) ever report true
+    fn is_rumbling(&self) -> bool {
+        false
+    }
+
+    // The bank mapped into 0x4000 - 0x7fff
+    fn get_rom_bank(&self) -> usize {
+        1
+    }
 }
 
 impl Cartridge {
-    pub fn from_file(file_path: &str) -> Box<Cartridge> {
-        let full_rom = fs::read(file_path).unwrap();
-        if full_rom.len() < 0x150 {
-            panic!("ROM shorter than header length");
+    pub fn from_rom(full_rom: &[u8], header: &CartridgeHeader) -> Result<Box<Cartridge>> {
+        if full_rom.len() < ROM_BANK_SIZE * 2 {
+            return Err(Error::TruncatedRom(full_rom.len()));
         }
-        let cart_type = CartType::try_from_u8(full_rom[CARTRIDGE_TYPE]).unwrap();
-        match cart_type {
+
+        let cart_type = CartType::try_from_u8(header.cartridge_type)?;
+        let ram_size = header.ram_size();
+        let cartridge: Box<dyn Cartridge> = match cart_type {
             CartType::RomOnly => {
                 let mut rom = [0; ROM_BANK_SIZE * 2];
                 let data = &full_rom[..rom.len()];
                 rom.copy_from_slice(data);
                 Box::new(RomOnly { rom })
             }
-            CartType::Mbc1 => Box::new(Mbc1::new(&full_rom)),
-            CartType::Mbc2 => Box::new(Mbc2::new(&full_rom)),
-            CartType::Mbc3 => Box::new(Mbc3::new(&full_rom)),
-        }
+            CartType::Mbc1 => Box::new(Mbc1::new(full_rom, ram_size)),
+            CartType::Mbc2 => Box::new(Mbc2::new(full_rom)),
+            CartType::Mbc3 { timer } => Box::new(Mbc3::new(full_rom, ram_size, timer)),
+            CartType::Mbc5 { rumble } => Box::new(Mbc5::new(full_rom, ram_size, rumble)),
+        };
+        Ok(cartridge)
+    }
+}
+
+// Splits ram_size bytes of ram into 8KB banks. Carts with only
+// 2KB of ram get a single short bank, so saves are the right size
+fn create_ram_banks(ram_size: usize) -> Vec<Vec<u8>> {
+    let mut ram_banks = Vec::new();
+    let mut remaining = ram_size;
+    while remaining > 0 {
+        let bank_size = remaining.min(sizes::EXRAM);
+        ram_banks.push(vec![0; bank_size]);
+        remaining -= bank_size;
+    }
+    ram_banks
+}
+
+fn get_ram_banks(ram_banks: &[Vec<u8>]) -> Vec<u8> {
+    let mut all_ram = Vec::new();
+    for bank in ram_banks.iter() {
+        all_ram.extend(bank);
+    }
+    all_ram
+}
+
+// Saves shorter than the cart's ram only fill the banks they cover
+fn set_ram_banks(ram_banks: &mut [Vec<u8>], all_ram: &[u8]) -> Result<()> {
+    let ram_size = ram_banks.iter().map(Vec::len).sum();
+    if all_ram.len() > ram_size {
+        return Err(Error::BadSaveSize {
+            expected: ram_size,
+            actual: all_ram.len(),
+        });
+    }
+
+    let mut remaining = all_ram;
+    for bank in ram_banks.iter_mut() {
+        let (data, rest) = remaining.split_at(bank.len().min(remaining.len()));
+        bank[..data.len()].copy_from_slice(data);
+        remaining = rest;
+    }
+    Ok(())
+}
+
+fn save_ram_banks(writer: &mut StateWriter, ram_banks: &[Vec<u8>]) {
+    for bank in ram_banks.iter() {
+        writer.write_bytes(bank);
+    }
+}
+
+fn load_ram_banks(reader: &mut StateReader, ram_banks: &mut [Vec<u8>]) -> Result<()> {
+    for bank in ram_banks.iter_mut() {
+        reader.read_bytes_into(bank)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
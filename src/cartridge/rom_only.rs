@@ -1,4 +1,6 @@
 use super::{Cartridge, ROM_BANK_SIZE};
+use crate::error::{Error, Result};
+use crate::save_state::{StateReader, StateWriter};
 
 pub struct RomOnly {
     pub rom: [u8; ROM_BANK_SIZE * 2],
@@ -17,10 +19,23 @@ impl Cartridge for RomOnly {
     }
 
     fn get_ram(&self) -> Vec<u8> {
-        unimplemented!();
+        Vec::new()
     }
 
-    fn set_ram(&mut self, _: &[u8]) {
-        unimplemented!();
+    fn set_ram(&mut self, all_ram: &[u8]) -> Result<()> {
+        if all_ram.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::BadSaveSize {
+                expected: 0,
+                actual: all_ram.len(),
+            })
+        }
+    }
+
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<()> {
+        Ok(())
     }
 }
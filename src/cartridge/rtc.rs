@@ -0,0 +1,287 @@
+use crate::bit_ops::BitGetSet;
+use crate::cpu;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Size of the rtc footer appended to .sav files by other emulators.
+// Older versions wrote a 32 bit timestamp, making it 44 bytes
+pub const SAVE_FOOTER_SIZE: usize = 48;
+pub const SAVE_FOOTER_SIZE_32BIT: usize = 44;
+
+// Where the real time clock gets its idea of elapsed time from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcClock {
+    // Advance one second every CLOCK_SPEED emulated cycles
+    Cycles,
+    // Advance with the host's clock, including time the emulator is not running
+    WallClock,
+}
+
+#[derive(Default, Clone, Copy)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low_bits: u8,
+    // bit 0: day counter bit 8, bit 6: halt, bit 7: day counter carry
+    day_high_bits: u8,
+}
+
+impl RtcRegisters {
+    fn is_halted(&self) -> bool {
+        self.day_high_bits.get_bit(6)
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        if seconds == 0 {
+            return;
+        }
+
+        let days = u64::from(self.day_low_bits) | (u64::from(self.day_high_bits & 0b1) << 8);
+        let total = u64::from(self.seconds)
+            + u64::from(self.minutes) * 60
+            + u64::from(self.hours) * 60 * 60
+            + days * 60 * 60 * 24
+            + seconds;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / (60 * 60) % 24) as u8;
+
+        let days = total / (60 * 60 * 24);
+        if days > 0x1ff {
+            self.day_high_bits = self.day_high_bits.set_bit(7);
+        }
+        self.day_low_bits = days as u8;
+        self.day_high_bits = (self.day_high_bits & 0b1111_1110) | ((days >> 8) & 0b1) as u8;
+    }
+
+    fn get(&self, register: usize) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0a => self.hours,
+            0x0b => self.day_low_bits,
+            0x0c => self.day_high_bits,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set(&mut self, register: usize, value: u8) {
+        match register {
+            0x08 => self.seconds = value & 0b0011_1111,
+            0x09 => self.minutes = value & 0b0011_1111,
+            0x0a => self.hours = value & 0b0001_1111,
+            0x0b => self.day_low_bits = value,
+            0x0c => self.day_high_bits = value & 0b1100_0001,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_footer(&self, footer: &mut Vec<u8>) {
+        for i in 0x08..=0x0c {
+            footer.extend(&u32::from(self.get(i)).to_le_bytes());
+        }
+    }
+
+    fn read_footer(data: &[u8]) -> RtcRegisters {
+        let mut registers = RtcRegisters::default();
+        for (i, chunk) in (0x08..=0x0c).zip(data.chunks(4)) {
+            registers.set(i, chunk[0]);
+        }
+        registers
+    }
+}
+
+pub struct Rtc {
+    clock: RtcClock,
+    registers: RtcRegisters,
+    latched: RtcRegisters,
+    // Writing 0 and then 1 to the latch register
+    // copies the running clock into the latched registers
+    latch_clock_data_reg: u8,
+    last_cycles: u64,
+    sub_second_cycles: u64,
+    // Unix time at which registers were last brought
+    // up to date, only used by RtcClock::WallClock
+    last_sync: u64,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            clock: RtcClock::Cycles,
+            registers: Default::default(),
+            latched: Default::default(),
+            // latch clock data reg need to start at
+            // non zero value for correct operation
+            latch_clock_data_reg: 1,
+            last_cycles: 0,
+            sub_second_cycles: 0,
+            last_sync: unix_time(),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.sub_second_cycles = 0;
+        self.last_sync = unix_time();
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        let elapsed = cycles.saturating_sub(self.last_cycles);
+        self.last_cycles = cycles;
+
+        if self.clock != RtcClock::Cycles || self.registers.is_halted() {
+            return;
+        }
+
+        self.sub_second_cycles += elapsed;
+        if self.sub_second_cycles >= cpu::CLOCK_SPEED {
+            let seconds = self.sub_second_cycles / cpu::CLOCK_SPEED;
+            self.sub_second_cycles %= cpu::CLOCK_SPEED;
+            self.registers.advance(seconds);
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_clock_data_reg == 0 && value == 1 {
+            self.latched = self.current();
+        }
+        self.latch_clock_data_reg = value;
+    }
+
+    // Reads always see the latched copy of the clock
+    pub fn get_u8(&self, register: usize) -> u8 {
+        self.latched.get(register)
+    }
+
+    pub fn set_u8(&mut self, register: usize, value: u8) {
+        self.sync();
+        if register == 0x08 {
+            // Writing seconds resets the clock's divider
+            self.sub_second_cycles = 0;
+        }
+        self.registers.set(register, value);
+    }
+
+    pub fn get_save_footer(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(SAVE_FOOTER_SIZE);
+        self.current().write_footer(&mut footer);
+        self.latched.write_footer(&mut footer);
+        footer.extend(&unix_time().to_le_bytes());
+        footer
+    }
+
+    // Call set_clock before loading, so a wall clock rtc
+    // catches up on the time since the save was written
+    pub fn set_save_footer(&mut self, footer: &[u8]) {
+        assert!(footer.len() == SAVE_FOOTER_SIZE || footer.len() == SAVE_FOOTER_SIZE_32BIT);
+        self.registers = RtcRegisters::read_footer(&footer[0..20]);
+        self.latched = RtcRegisters::read_footer(&footer[20..40]);
+        self.last_sync = if footer.len() == SAVE_FOOTER_SIZE {
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(&footer[40..48]);
+            u64::from_le_bytes(timestamp)
+        } else {
+            let mut timestamp = [0; 4];
+            timestamp.copy_from_slice(&footer[40..44]);
+            u64::from(u32::from_le_bytes(timestamp))
+        };
+        self.sub_second_cycles = 0;
+    }
+
+    // The clock source is a host setting, so isn't part of the state
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let mut registers = Vec::new();
+        self.registers.write_footer(&mut registers);
+        self.latched.write_footer(&mut registers);
+        writer.write_bytes(&registers);
+        writer.write_u8(self.latch_clock_data_reg);
+        writer.write_u64(self.last_cycles);
+        writer.write_u64(self.sub_second_cycles);
+        writer.write_u64(self.last_sync);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut registers = [0; 40];
+        reader.read_bytes_into(&mut registers)?;
+        self.registers = RtcRegisters::read_footer(&registers[0..20]);
+        self.latched = RtcRegisters::read_footer(&registers[20..40]);
+        self.latch_clock_data_reg = reader.read_u8()?;
+        self.last_cycles = reader.read_u64()?;
+        self.sub_second_cycles = reader.read_u64()?;
+        self.last_sync = reader.read_u64()?;
+        Ok(())
+    }
+
+    // The running clock including any wall clock time not yet added to registers
+    fn current(&self) -> RtcRegisters {
+        let mut registers = self.registers;
+        if self.clock == RtcClock::WallClock && !registers.is_halted() {
+            registers.advance(unix_time().saturating_sub(self.last_sync));
+        }
+        registers
+    }
+
+    fn sync(&mut self) {
+        if self.clock == RtcClock::WallClock {
+            self.registers = self.current();
+            self.last_sync = unix_time();
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rtc, RtcRegisters};
+    use crate::cpu::CLOCK_SPEED;
+
+    #[test]
+    fn day_carry() {
+        let mut registers = RtcRegisters::default();
+        registers.set(0x0b, 0xff);
+        registers.set(0x0c, 0x01);
+        registers.set(0x0a, 23);
+        registers.set(0x09, 59);
+        registers.set(0x08, 59);
+        registers.advance(1);
+        assert_eq!(registers.get(0x08), 0);
+        assert_eq!(registers.get(0x0a), 0);
+        assert_eq!(registers.get(0x0b), 0);
+        assert_eq!(registers.get(0x0c), 0b1000_0000);
+    }
+
+    #[test]
+    fn latch_and_halt() {
+        let mut rtc = Rtc::new();
+        rtc.tick(CLOCK_SPEED * 61);
+        assert_eq!(rtc.get_u8(0x08), 0);
+
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        assert_eq!(rtc.get_u8(0x08), 1);
+        assert_eq!(rtc.get_u8(0x09), 1);
+
+        rtc.set_u8(0x0c, 0b0100_0000);
+        rtc.tick(CLOCK_SPEED * 100);
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        assert_eq!(rtc.get_u8(0x08), 1);
+
+        let footer = rtc.get_save_footer();
+        let mut loaded = Rtc::new();
+        loaded.set_save_footer(&footer);
+        assert_eq!(loaded.get_u8(0x09), 1);
+        assert_eq!(loaded.get_u8(0x0c), 0b0100_0000);
+    }
+}
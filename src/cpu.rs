@@ -1,6 +1,8 @@
 use super::bit_ops::BitGetSet;
+use super::error::Result;
 use super::memory::{io_regs, Memory};
 use super::registers::Registers;
+use super::save_state::{bad_state, StateReader, StateWriter};
 
 pub const CLOCK_SPEED: u64 = 4_194_304;
 
@@ -8,7 +10,9 @@ enum HaltState {
     None,
     Mode1, // IME = 1
     Mode2, // IME = 0, IE & IF & 0x1F = 0
-           // Mode3, // IME = 0, IE & IF & 0x1F != 0
+    // Mode3, // IME = 0, IE & IF & 0x1F != 0
+    // Low power mode, the clock stops until a button is pressed
+    Stop,
 }
 
 #[derive(Clone, Copy)]
@@ -16,6 +20,8 @@ enum Interrupt {
     VBlank,
     Stat,
     Timer,
+    Serial,
+    Joypad,
 }
 
 impl Interrupt {
@@ -24,6 +30,8 @@ impl Interrupt {
             Interrupt::VBlank => 0x40,
             Interrupt::Stat => 0x48,
             Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
         }
     }
 
@@ -33,6 +41,8 @@ impl Interrupt {
             Interrupt::VBlank => flag.reset_bit(0),
             Interrupt::Stat => flag.reset_bit(1),
             Interrupt::Timer => flag.reset_bit(2),
+            Interrupt::Serial => flag.reset_bit(3),
+            Interrupt::Joypad => flag.reset_bit(4),
         };
         memory.set_io(io_regs::IF, new_flag);
     }
@@ -67,6 +77,10 @@ impl Cpu {
             self.try_interrupt(Interrupt::Stat, memory);
         } else if interrupts.get_bit(2) {
             self.try_interrupt(Interrupt::Timer, memory);
+        } else if interrupts.get_bit(3) {
+            self.try_interrupt(Interrupt::Serial, memory);
+        } else if interrupts.get_bit(4) {
+            self.try_interrupt(Interrupt::Joypad, memory);
         }
     }
 
@@ -84,6 +98,8 @@ impl Cpu {
             HaltState::Mode2 => {
                 self.halt_state = HaltState::None;
             }
+            // Only a button press ends STOP mode
+            HaltState::Stop => (),
         }
     }
 
@@ -93,6 +109,16 @@ impl Cpu {
         self.registers.pc = address;
     }
 
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.halt_state, HaltState::Stop)
+    }
+
+    pub fn wake_from_stop(&mut self) {
+        if self.is_stopped() {
+            self.halt_state = HaltState::None;
+        }
+    }
+
     pub fn get_cycles(&self) -> u64 {
         self.cycles
     }
@@ -101,9 +127,67 @@ impl Cpu {
         &self.registers
     }
 
-    pub fn tick(&mut self, memory: &mut Memory, tracing: bool) {
+    pub fn get_registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    // True while waiting in HALT or STOP instead of running instructions
+    pub fn is_halted(&self) -> bool {
+        !matches!(self.halt_state, HaltState::None)
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let regs = &self.registers;
+        for x in [
+            regs.a, regs.b, regs.c, regs.d, regs.e, regs.f, regs.h, regs.l,
+        ]
+        .iter()
+        {
+            writer.write_u8(*x);
+        }
+        writer.write_u16(regs.sp);
+        writer.write_u16(regs.pc);
+        writer.write_u64(self.instruction_counter as u64);
+        writer.write_bool(self.interrupts_enabled);
+        writer.write_u64(self.cycles);
+        writer.write_u8(match self.halt_state {
+            HaltState::None => 0,
+            HaltState::Mode1 => 1,
+            HaltState::Mode2 => 2,
+            HaltState::Stop => 3,
+        });
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let regs = &mut self.registers;
+        regs.a = reader.read_u8()?;
+        regs.b = reader.read_u8()?;
+        regs.c = reader.read_u8()?;
+        regs.d = reader.read_u8()?;
+        regs.e = reader.read_u8()?;
+        regs.f = reader.read_u8()?;
+        regs.h = reader.read_u8()?;
+        regs.l = reader.read_u8()?;
+        regs.sp = reader.read_u16()?;
+        regs.pc = reader.read_u16()?;
+        self.instruction_counter = reader.read_u64()? as usize;
+        self.interrupts_enabled = reader.read_bool()?;
+        self.cycles = reader.read_u64()?;
+        self.halt_state = match reader.read_u8()? {
+            0 => HaltState::None,
+            1 => HaltState::Mode1,
+            2 => HaltState::Mode2,
+            3 => HaltState::Stop,
+            _ => return Err(bad_state("bad cpu halt state")),
+        };
+        Ok(())
+    }
+
+    pub fn tick(&mut self, memory: &mut Memory) {
         match self.halt_state {
             HaltState::None => (),
+            // The clock isn't running
+            HaltState::Stop => return,
             _ => {
                 self.cycles += 4;
                 return;
@@ -111,377 +195,11 @@ impl Cpu {
         }
 
         self.instruction_counter += 1;
-
-        if tracing {
-            let registers = self.registers.clone();
-            let mnemonic = self.get_opcode_mnemonic(memory);
-            let opcode = memory.get_u8(self.registers.pc);
-            let pc = self.registers.pc;
-
-            self.fetch_and_execute(memory);
-
-            macro_rules! diff_regs {
-                ($a:expr, $b:expr, $diffs:ident, $fmt_str:tt) => {
-                    if $a != $b {
-                        $diffs.push_str(&format!($fmt_str, $b));
-                        $diffs.push_str(" ");
-                    }
-                };
-            }
-
-            let mut diffs = String::new();
-            diff_regs!(registers.a, self.registers.a, diffs, "A={:#04x}");
-            diff_regs!(registers.b, self.registers.b, diffs, "B={:#04x}");
-            diff_regs!(registers.c, self.registers.c, diffs, "C={:#04x}");
-            diff_regs!(registers.d, self.registers.d, diffs, "D={:#04x}");
-            diff_regs!(registers.e, self.registers.e, diffs, "E={:#04x}");
-            diff_regs!(registers.f, self.registers.f, diffs, "F={:#04x}");
-            diff_regs!(registers.h, self.registers.h, diffs, "H={:#04x}");
-            diff_regs!(registers.l, self.registers.l, diffs, "L={:#04x}");
-            diff_regs!(registers.sp, self.registers.sp, diffs, "SP={:#06x}");
-
-            match registers.pc {
-                // Ignore screen update
-                0x02b2...0x02b6 => (),
-                0x287c...0x2880 => (),
-                _ => {
-                    println!(
-                        "{:#06x}  {:02x}  {:020}  #  {}",
-                        pc, opcode, mnemonic, diffs
-                    );
-                }
-            }
-        } else {
-            self.fetch_and_execute(memory);
-        }
-    }
-
-    fn get_opcode_mnemonic(&self, memory: &Memory) -> String {
-        let opcode = memory.get_u8(self.registers.pc);
-        let regs = &self.registers;
-
-        match opcode {
-            0x00 => "nop".to_string(),
-            0x01 => format!("ld BC, {:#06x}", self.load_imm_u16(memory)),
-            0x11 => format!("ld DE, {:#06x}", self.load_imm_u16(memory)),
-            0x21 => format!("ld HL, {:#06x}", self.load_imm_u16(memory)),
-            0x31 => format!("ld SP, {:#06x}", self.load_imm_u16(memory)),
-            0x20 => format!("jr NZ, {}", self.load_imm_u8(memory) as i8),
-            0x28 => format!("jr Z, {}", self.load_imm_u8(memory) as i8),
-            0x30 => format!("jr NC, {}", self.load_imm_u8(memory) as i8),
-            0x38 => format!("jr C, {}", self.load_imm_u8(memory) as i8),
-            0x06 => format!("ld B, {:#04x}", self.load_imm_u8(memory)),
-            0x0e => format!("ld C, {:#04x}", self.load_imm_u8(memory)),
-            0x16 => format!("ld D, {:#04x}", self.load_imm_u8(memory)),
-            0x1e => format!("ld E, {:#04x}", self.load_imm_u8(memory)),
-            0x26 => format!("ld H, {:#04x}", self.load_imm_u8(memory)),
-            0x2e => format!("ld L, {:#04x}", self.load_imm_u8(memory)),
-            0x3c => format!("inc A({:#04x})", regs.a),
-            0x04 => format!("inc B({:#04x})", regs.b),
-            0x0c => format!("inc C({:#04x})", regs.c),
-            0x14 => format!("inc D({:#04x})", regs.d),
-            0x1c => format!("inc E({:#04x})", regs.e),
-            0x24 => format!("inc H({:#04x})", regs.h),
-            0x2c => format!("inc L({:#04x})", regs.l),
-            0x34 => format!("inc (HL({:#04x}))", regs.get_hl()),
-            0x47 => format!("ld B, A({:#04x})", regs.a),
-            0x4f => format!("ld C, A({:#04x})", regs.a),
-            0x57 => format!("ld D, A({:#04x})", regs.a),
-            0x5f => format!("ld E, A({:#04x})", regs.a),
-            0x67 => format!("ld H, A({:#04x})", regs.a),
-            0x6f => format!("ld L, A({:#04x})", regs.a),
-            0x02 => format!("ld (BC), {:#04x}", regs.a),
-            0x12 => format!("ld (DE), {:#04x}", regs.a),
-            0x77 => format!("ld (HL), {:#04x}", regs.a),
-            0xea => format!("ld ({:#06x}), {:#04x}", self.load_imm_u16(memory), regs.a),
-            0xe0 => format!(
-                "ldh ({:#04x}), {:#04}",
-                0xff00 + u16::from(self.load_imm_u8(memory)),
-                regs.a
-            ),
-            0x18 => format!("jr {:#04x}", self.load_imm_u8(memory) as i8),
-            0xc3 => format!("jp {:#06x}", self.load_imm_u16(memory)),
-            0x2a => format!("ldi A, (HL({:#06x}))", regs.get_hl()),
-            0x32 => format!("ldd (HL{:#06x}), A{:#04x}", regs.get_hl(), regs.a),
-            0x0a => format!("ld A, (BC({:#06x}))", regs.get_bc()),
-            0x1a => format!("ld A, (DE({:#06x}))", regs.get_de()),
-            0x3e => format!("ld A, {:#04x}", self.load_imm_u8(memory)),
-            0x78 => format!("ld A, B({:#04x})", regs.b),
-            0x79 => format!("ld A, C({:#04x})", regs.c),
-            0x7a => format!("ld A, D({:#04x})", regs.d),
-            0x7b => format!("ld A, E({:#04x})", regs.e),
-            0x7c => format!("ld A, H({:#04x})", regs.h),
-            0x7d => format!("ld A, L({:#04x})", regs.l),
-            0x7e => format!("ld A, (HL({:#06x}))", regs.get_hl()),
-            0x7f => format!("ld A, A({:#04x})", regs.a),
-            0xfa => format!("ld A, ({:#06x})", self.load_imm_u16(memory)),
-            0xe2 => format!("ld (C), A({:#04x})", regs.a),
-            0xcd => format!("call {:#06x}", self.load_imm_u16(memory)),
-            0xf5 => format!("push AF({:#06x})", regs.get_af()),
-            0xc5 => format!("push BC({:#06x})", regs.get_bc()),
-            0xd5 => format!("push DE({:#06x})", regs.get_de()),
-            0xe5 => format!("push HL({:#06x})", regs.get_hl()),
-            0xf1 => format!("pop AF({:#06x})", regs.get_af()),
-            0xc1 => format!("pop BC({:#06x})", regs.get_bc()),
-            0xd1 => format!("pop DE({:#06x})", regs.get_de()),
-            0xe1 => format!("pop HL({:#06x})", regs.get_hl()),
-            0x17 => "rla".to_string(),
-            0x3d => "dec A".to_string(),
-            0x05 => "dec B".to_string(),
-            0x0d => "dec C".to_string(),
-            0x15 => "dec D".to_string(),
-            0x1d => "dec E".to_string(),
-            0x25 => "dec H".to_string(),
-            0x2d => "dec L".to_string(),
-            0x35 => "dec (HL)".to_string(),
-            0x22 => format!("ldi (HL({:#06x}), A({:#04x})", regs.get_hl(), regs.a),
-            0x03 => "inc BC".to_string(),
-            0x13 => "inc DE".to_string(),
-            0x23 => "inc HL".to_string(),
-            0x33 => "inc SP".to_string(),
-            0xc9 => "ret".to_string(),
-            0xb8 => format!("cp A({:#04x}) B({:#04x})", regs.a, regs.b),
-            0xb9 => format!("cp A({:#04x}) C({:#04x})", regs.a, regs.c),
-            0xba => format!("cp A({:#04x}) D({:#04x})", regs.a, regs.d),
-            0xbb => format!("cp A({:#04x}) E({:#04x})", regs.a, regs.e),
-            0xbc => format!("cp A({:#04x}) H({:#04x})", regs.a, regs.h),
-            0xbd => format!("cp A({:#04x}) L({:#04x})", regs.a, regs.l),
-            0xbe => format!("cp A({:#04x}) (HL({:#06x}))", regs.a, regs.get_hl()),
-            0xbf => format!("cp A({:#04x}) A({:#04x})", regs.a, regs.a),
-            0xfe => format!("cp A({:#04x}) {:#04x}", regs.a, self.load_imm_u8(memory)),
-            0xf0 => format!(
-                "ldh A, ({:#06x})",
-                0xff00 + u16::from(self.load_imm_u8(memory))
-            ),
-            0xf3 => "di".to_string(),
-            0xfb => "ei".to_string(),
-            0x1f => "rra".to_string(),
-            0xe9 => format!("jp (HL({:#06x}))", regs.get_hl()),
-            0x90 => format!("sub A, B({:#04x})", regs.b),
-            0x91 => format!("sub A, C({:#04x})", regs.c),
-            0x92 => format!("sub A, D({:#04x})", regs.d),
-            0x93 => format!("sub A, E({:#04x})", regs.e),
-            0x94 => format!("sub A, H({:#04x})", regs.h),
-            0x95 => format!("sub A, L({:#04x})", regs.l),
-            0x96 => format!("sub A, (HL({:#06x}))", regs.get_hl()),
-            0x97 => format!("sub A, A({:#04x})", regs.a),
-            0xd6 => format!("sub A, {:#04x}", self.load_imm_u8(memory)),
-            0x80 => format!("add A, B({:#04x})", regs.b),
-            0x81 => format!("add A, C({:#04x})", regs.c),
-            0x82 => format!("add A, D({:#04x})", regs.d),
-            0x83 => format!("add A, E({:#04x})", regs.e),
-            0x84 => format!("add A, H({:#04x})", regs.h),
-            0x85 => format!("add A, L({:#04x})", regs.l),
-            0x86 => format!("add A, (HL({:#06x}))", regs.get_hl()),
-            0x87 => format!("add A, A({:#04x})", regs.a),
-            0xc6 => format!("add A, {:#04x}", self.load_imm_u8(memory)),
-            0xb7 => format!("or A, A({:#04x})", regs.a),
-            0xb0 => format!("or A, B({:#04x})", regs.b),
-            0xb1 => format!("or A, C({:#04x})", regs.c),
-            0xb2 => format!("or A, D({:#04x})", regs.d),
-            0xb3 => format!("or A, E({:#04x})", regs.e),
-            0xb4 => format!("or A, H({:#04x})", regs.h),
-            0xb5 => format!("or A, L({:#04x})", regs.l),
-            0xb6 => format!("or A, (HL({:#06x}))", regs.get_hl()),
-            0xf6 => format!("or A, {:#04x}", self.load_imm_u8(memory)),
-            0xa7 => format!("and A, A({:#04x})", regs.a),
-            0xa0 => format!("and A, B({:#04x})", regs.b),
-            0xa1 => format!("and A, C({:#04x})", regs.c),
-            0xa2 => format!("and A, D({:#04x})", regs.d),
-            0xa3 => format!("and A, E({:#04x})", regs.e),
-            0xa4 => format!("and A, H({:#04x})", regs.h),
-            0xa5 => format!("and A, L({:#04x})", regs.l),
-            0xa6 => format!("and A, (HL({:#06x}))", regs.get_hl()),
-            0xe6 => format!("and A, {:#04x}", self.load_imm_u8(memory)),
-            0xaf => format!("xor A, A({:#04x})", regs.a),
-            0xa8 => format!("xor A, B({:#04x})", regs.b),
-            0xa9 => format!("xor A, C({:#04x})", regs.c),
-            0xaa => format!("xor A, D({:#04x})", regs.d),
-            0xab => format!("xor A, E({:#04x})", regs.e),
-            0xac => format!("xor A, H({:#04x})", regs.h),
-            0xad => format!("xor A, L({:#04x})", regs.l),
-            0xae => format!("xor A, (HL({:#06x}))", regs.get_hl()),
-            0xee => format!("xor A, {:#04x}", self.load_imm_u8(memory)),
-            0xc4 => format!("call NZ, {:#06x}", self.load_imm_u16(memory)),
-            0xcc => format!("call Z, {:#06x}", self.load_imm_u16(memory)),
-            0xd4 => format!("call NC, {:#06x}", self.load_imm_u16(memory)),
-            0xdc => format!("call C, {:#06x}", self.load_imm_u16(memory)),
-            0x8f => format!("adc A, A({:#04x})", regs.a),
-            0x88 => format!("adc A, B({:#04x})", regs.b),
-            0x89 => format!("adc A, C({:#04x})", regs.c),
-            0x8a => format!("adc A, D({:#04x})", regs.d),
-            0x8b => format!("adc A, E({:#04x})", regs.e),
-            0x8c => format!("adc A, H({:#04x})", regs.h),
-            0x8d => format!("adc A, L({:#04x})", regs.l),
-            0x8e => format!("adc A, (HL({:#06x}))", regs.get_hl()),
-            0xce => format!("adc A, {:#04x}", self.load_imm_u8(memory)),
-            0xc0 => "ret NZ".to_string(),
-            0xc8 => "ret Z".to_string(),
-            0xd0 => "ret NC".to_string(),
-            0xd8 => "ret C".to_string(),
-            0x09 => format!("add HL, BC({:#06x})", regs.get_bc()),
-            0x19 => format!("add HL, DE({:#06x})", regs.get_de()),
-            0x29 => format!("add HL, HL({:#06x})", regs.get_hl()),
-            0x39 => format!("ADD HL, SP({:#06x})", regs.sp),
-            0xc2 => format!("jp NZ, {:#06x}", self.load_imm_u16(memory)),
-            0xca => format!("jp Z, {:#06x}", self.load_imm_u16(memory)),
-            0xd2 => format!("jp NC, {:#06x}", self.load_imm_u16(memory)),
-            0xda => format!("JP C, {:#06x}", self.load_imm_u16(memory)),
-            // LD r1,r2
-            0x40 => format!("ld B, B({:#04x})", regs.b),
-            0x41 => format!("ld B, C({:#04x})", regs.c),
-            0x42 => format!("ld B, D({:#04x})", regs.d),
-            0x43 => format!("ld B, E({:#04x})", regs.e),
-            0x44 => format!("ld B, H({:#04x})", regs.h),
-            0x45 => format!("ld B, L({:#04x})", regs.l),
-            0x46 => format!("ld B, (HL({:#06x}))", regs.get_hl()),
-            0x48 => format!("ld C, B({:#04x})", regs.b),
-            0x49 => format!("ld C, C({:#04x})", regs.c),
-            0x4a => format!("ld C, D({:#04x})", regs.d),
-            0x4b => format!("ld C, E({:#04x})", regs.e),
-            0x4c => format!("ld C, H({:#04x})", regs.h),
-            0x4d => format!("ld C, L({:#04x})", regs.l),
-            0x4e => format!("ld C, (HL({:#06x}))", regs.get_hl()),
-            0x50 => format!("ld D, B({:#04x})", regs.b),
-            0x51 => format!("ld D, C({:#04x})", regs.c),
-            0x52 => format!("ld D, D({:#04x})", regs.d),
-            0x53 => format!("ld D, E({:#04x})", regs.e),
-            0x54 => format!("ld D, H({:#04x})", regs.h),
-            0x55 => format!("ld D, L({:#04x})", regs.l),
-            0x56 => format!("ld D, (HL({:#06x}))", regs.get_hl()),
-            0x58 => format!("ld E, B({:#04x})", regs.b),
-            0x59 => format!("ld E, C({:#04x})", regs.c),
-            0x5a => format!("ld E, D({:#04x})", regs.d),
-            0x5b => format!("ld E, E({:#04x})", regs.e),
-            0x5c => format!("ld E, H({:#04x})", regs.h),
-            0x5d => format!("ld E, L({:#04x})", regs.l),
-            0x5e => format!("ld E, (HL({:#06x}))", regs.get_hl()),
-            0x60 => format!("ld H, B({:#04x})", regs.b),
-            0x61 => format!("ld H, C({:#04x})", regs.c),
-            0x62 => format!("ld H, D({:#04x})", regs.d),
-            0x63 => format!("ld H, E({:#04x})", regs.e),
-            0x64 => format!("ld H, H({:#04x})", regs.h),
-            0x65 => format!("ld H, L({:#04x})", regs.l),
-            0x66 => format!("ld H, (HL({:#06x}))", regs.get_hl()),
-            0x68 => format!("ld L, B({:#04x})", regs.b),
-            0x69 => format!("ld L, C({:#04x})", regs.c),
-            0x6a => format!("ld L, D({:#04x})", regs.d),
-            0x6b => format!("ld L, E({:#04x})", regs.e),
-            0x6c => format!("ld L, H({:#04x})", regs.h),
-            0x6d => format!("ld L, L({:#04x})", regs.l),
-            0x6e => format!("ld L, (HL({:#06x}))", regs.get_hl()),
-            // LD (nn),SP
-            0x08 => format!(
-                "ld ({:#06x}), SP({:#06x})",
-                self.load_imm_u16(memory),
-                regs.sp
-            ),
-            // LD SP,HL
-            0xf9 => format!("ld SP({:#06x}), HL({:#06x})", regs.sp, regs.get_hl()),
-            // LDD A,(HL)
-            0x3a => format!("ldd A({:#04x}), HL({:#06x})", regs.a, regs.get_hl()),
-            // DEC nn
-            0x0b => format!("dec BC({:#06x})", regs.get_bc()),
-            0x1b => format!("dec DE({:#06x})", regs.get_de()),
-            0x2b => format!("dec HL({:#06x})", regs.get_hl()),
-            0x3b => format!("dec SP({:#06x})", regs.sp),
-            // RST n
-            0xc7 => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xcf => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xd7 => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xdf => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xe7 => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xef => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xf7 => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            0xff => format!("rst {:#04x}", self.load_imm_u8(memory)),
-            // SBC A,n
-            0x9f => format!("sbc A({:#04x}),A({:#04x})", regs.a, regs.a),
-            0x98 => format!("sbc A({:#04x}),B({:#04x})", regs.a, regs.b),
-            0x99 => format!("sbc A({:#04x}),C({:#04x})", regs.a, regs.c),
-            0x9a => format!("sbc A({:#04x}),D({:#04x})", regs.a, regs.d),
-            0x9b => format!("sbc A({:#04x}),E({:#04x})", regs.a, regs.e),
-            0x9c => format!("sbc A({:#04x}),H({:#04x})", regs.a, regs.h),
-            0x9d => format!("sbc A({:#04x}),L({:#04x})", regs.a, regs.l),
-            0x9e => format!("sbc A({:#04x}),HL({:#06x})", regs.a, regs.get_hl()),
-            // RLCA
-            0x07 => format!("rlca A({:#04x})", self.registers.a),
-            // RRCA
-            0x0f => format!("rrca A({:#04x})", self.registers.a),
-            // OTHER
-            0x37 => "scf".to_string(),
-            0xe8 => format!("add SP({:#06x}) {:#04x}", regs.sp, self.load_imm_u8(memory)),
-            0x76 => "halt".to_string(),
-            0xcb => self.get_cb_opcode_mnemonic(memory),
-            _ => "__".to_string(),
-        }
-    }
-
-    fn get_cb_opcode_mnemonic(&self, memory: &Memory) -> String {
-        let opcode = memory.get_u8(self.registers.pc + 1);
-
-        match opcode {
-            0x40...0x7f => "BIT".to_string(),
-            // RL n
-            0x17 => format!("rl A({:#04x})", self.registers.a),
-            0x10 => format!("rl B({:#04x})", self.registers.b),
-            0x11 => format!("rl C({:#04x})", self.registers.c),
-            0x12 => format!("rl D({:#04x})", self.registers.d),
-            0x13 => format!("rl E({:#04x})", self.registers.e),
-            0x14 => format!("rl H({:#04x})", self.registers.h),
-            0x15 => format!("rl L({:#04x})", self.registers.l),
-            0x16 => format!("rl (HL({:#06x}))", self.registers.get_hl()),
-            // RR n
-            0x1f => format!("rr A({:#04x})", self.registers.a),
-            0x18 => format!("rr B({:#04x})", self.registers.b),
-            0x19 => format!("rr C({:#04x})", self.registers.c),
-            0x1a => format!("rr D({:#04x})", self.registers.d),
-            0x1b => format!("rr E({:#04x})", self.registers.e),
-            0x1c => format!("rr H({:#04x})", self.registers.h),
-            0x1d => format!("rr L({:#04x})", self.registers.l),
-            0x1e => format!("rr (HL({:#06x}))", self.registers.get_hl()),
-            // SRL n
-            0x3f => format!("srl A({:#04x})", self.registers.a),
-            0x38 => format!("srl B({:#04x})", self.registers.b),
-            0x39 => format!("srl C({:#04x})", self.registers.c),
-            0x3a => format!("srl D({:#04x})", self.registers.d),
-            0x3b => format!("srl E({:#04x})", self.registers.e),
-            0x3c => format!("srl H({:#04x})", self.registers.h),
-            0x3d => format!("srl L({:#04x})", self.registers.l),
-            0x3e => format!("srl (HL({:#06x}))", self.registers.get_hl()),
-            // SWAP n
-            0x37 => format!("swap A({:#04x})", self.registers.a),
-            0x30 => format!("swap B({:#04x})", self.registers.b),
-            0x31 => format!("swap C({:#04x})", self.registers.c),
-            0x32 => format!("swap D({:#04x})", self.registers.d),
-            0x33 => format!("swap E({:#04x})", self.registers.e),
-            0x34 => format!("swap H({:#04x})", self.registers.h),
-            0x35 => format!("swap L({:#04x})", self.registers.l),
-            0x36 => format!("swap (HL({:#04x}))", self.registers.get_hl()),
-            // RLC n
-            0x07 => format!("rlc A({:#04x})", self.registers.a),
-            0x00 => format!("rlc B({:#04x})", self.registers.b),
-            0x01 => format!("rlc C({:#04x})", self.registers.c),
-            0x02 => format!("rlc D({:#04x})", self.registers.d),
-            0x03 => format!("rlc E({:#04x})", self.registers.e),
-            0x04 => format!("rlc H({:#04x})", self.registers.h),
-            0x05 => format!("rlc L({:#04x})", self.registers.l),
-            0x06 => format!("rlc (HL({:#04x}))", self.registers.get_hl()),
-            // RRC n
-            0x0f => format!("rrc A({:#04x})", self.registers.a),
-            0x08 => format!("rrc B({:#04x})", self.registers.b),
-            0x09 => format!("rrc C({:#04x})", self.registers.c),
-            0x0a => format!("rrc D({:#04x})", self.registers.d),
-            0x0b => format!("rrc E({:#04x})", self.registers.e),
-            0x0c => format!("rrc H({:#04x})", self.registers.h),
-            0x0d => format!("rrc L({:#04x})", self.registers.l),
-            0x0e => format!("rrc (HL({:#04x}))", self.registers.get_hl()),
-            0x2f => "cpl".to_string(),
-            _ => "CB__".to_string(),
-        }
+        self.fetch_and_execute(memory);
     }
 
     fn fetch_and_execute(&mut self, memory: &mut Memory) {
-        let opcode = memory.get_u8(self.registers.pc);
+        let opcode = memory.fetch_u8(self.registers.pc);
 
         match opcode {
             0x00 => self.nop(),
@@ -585,7 +303,7 @@ impl Cpu {
             0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
                 self.rst_n(opcode, memory);
             }
-            0x10 => self.stop(),
+            0x10 => self.stop(memory),
             0x76 => self.halt(memory),
             _ => panic!("Instruction 0x{:02x} not implemented", opcode),
         }
@@ -593,7 +311,7 @@ impl Cpu {
 
     fn fetch_and_execute_cb(&mut self, memory: &mut Memory) {
         self.registers.pc += 1;
-        let opcode = memory.get_u8(self.registers.pc);
+        let opcode = memory.fetch_u8(self.registers.pc);
 
         match opcode {
             0x40...0x7f => self.bit_b_r(opcode, memory),
@@ -646,12 +364,12 @@ impl Cpu {
     }
 
     fn load_imm_u8(&self, memory: &Memory) -> u8 {
-        memory.get_u8(self.registers.pc + 1)
+        memory.fetch_u8(self.registers.pc + 1)
     }
 
     fn load_imm_u16(&self, memory: &Memory) -> u16 {
-        let low = memory.get_u8(self.registers.pc + 1);
-        let high = memory.get_u8(self.registers.pc + 2);
+        let low = memory.fetch_u8(self.registers.pc + 1);
+        let high = memory.fetch_u8(self.registers.pc + 2);
         as_u16(low, high)
     }
 
@@ -764,9 +482,11 @@ impl Cpu {
         }
     }
 
-    fn stop(&mut self) {
-        eprintln!("warning: ignoring over stop instruction");
+    fn stop(&mut self, memory: &mut Memory) {
+        memory.reset_div();
+        self.halt_state = HaltState::Stop;
         self.registers.pc += 2;
+        self.cycles += 4;
     }
 
     fn rrc_n(&mut self, opcode: u8, memory: &mut Memory) {
@@ -0,0 +1,436 @@
+use super::{Access, Break, Debugger, Register, StopReason};
+use crate::error::Result;
+use crate::lcd::FRAME_CYCLES;
+use crate::App;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+// The registers in the order of the g packet, each 16 bits little endian
+const REGISTERS: [Register; 6] = [
+    Register::AF,
+    Register::BC,
+    Register::DE,
+    Register::HL,
+    Register::SP,
+    Register::PC,
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="gb_emu.sm83">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Stop replies for SIGTRAP and SIGINT
+const STOPPED: &str = "S05";
+const INTERRUPTED: &str = "S02";
+
+// Ctrl-c in gdb is sent as this byte, outside of a packet
+const INTERRUPT: u8 = 0x03;
+
+enum Packet {
+    Data(String),
+    Interrupt,
+}
+
+// Lets gdb debug the emulator over the remote serial protocol. Breakpoints
+// are kept by the Debugger rather than written into memory, so they work in
+// rom. Breakpoint addresses above 0xffff are banked, with the bank in the
+// upper bits, so 0x34000 is 0x4000 in bank 3. Watchpoints are supported too
+pub struct GdbStub {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    acks: bool,
+    // Debugger break ids, by the Z packet type, address and kind
+    breaks: HashMap<(u8, u32, u32), usize>,
+}
+
+impl GdbStub {
+    // Wait for gdb to connect, with target remote
+    pub fn listen<A: ToSocketAddrs>(address: A) -> Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        GdbStub::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> Result<GdbStub> {
+        let (stream, _) = listener.accept()?;
+        GdbStub::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Result<GdbStub> {
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            acks: true,
+            breaks: HashMap::new(),
+        })
+    }
+
+    // Serve gdb until it detaches, kills the program or disconnects. The
+    // emulator only runs while gdb has it continuing or stepping. If the
+    // app stops, gdb is told the program exited
+    pub fn run<T: App>(&mut self, debugger: &mut Debugger, app: &mut T) -> Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(Packet::Data(x)) => x,
+                // Already stopped
+                Some(Packet::Interrupt) => continue,
+                None => break,
+            };
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.write_packet("OK")?;
+                    break;
+                }
+                Some(b'k') => break,
+                // Acks stop after the OK
+                Some(b'Q') if packet == "QStartNoAckMode" => {
+                    self.write_packet("OK")?;
+                    self.acks = false;
+                    continue;
+                }
+                Some(b'c') | Some(b's') => self.resume(debugger, app, &packet)?,
+                _ => self.handle_packet(debugger, &packet),
+            };
+            self.write_packet(&reply)?;
+            if reply.starts_with('W') {
+                break;
+            }
+        }
+        self.remove_breaks(debugger);
+        Ok(())
+    }
+
+    fn remove_breaks(&mut self, debugger: &mut Debugger) {
+        for (_, id) in self.breaks.drain() {
+            debugger.remove_break(id);
+        }
+    }
+
+    // Everything except running, returns the reply
+    fn handle_packet(&mut self, debugger: &mut Debugger, packet: &str) -> String {
+        let (command, args) = match packet.get(..1) {
+            Some(x) => (x, &packet[1..]),
+            None => return String::new(),
+        };
+        let reply = match command {
+            "?" => Some(STOPPED.to_string()),
+            "g" => Some(
+                REGISTERS
+                    .iter()
+                    .map(|x| to_hex(&debugger.get_register(*x).to_le_bytes()))
+                    .collect(),
+            ),
+            "G" => from_hex(args)
+                .filter(|x| x.len() == REGISTERS.len() * 2)
+                .map(|bytes| {
+                    for (register, value) in REGISTERS.iter().zip(bytes.chunks(2)) {
+                        debugger.set_register(*register, u16::from_le_bytes([value[0], value[1]]));
+                    }
+                    "OK".to_string()
+                }),
+            "p" => parse_hex(args)
+                .and_then(|x| REGISTERS.get(x as usize))
+                .map(|x| to_hex(&debugger.get_register(*x).to_le_bytes())),
+            "P" => split2(args, '=').and_then(|(index, value)| {
+                let register = REGISTERS.get(parse_hex(index)? as usize)?;
+                let value = from_hex(value).filter(|x| x.len() == 2)?;
+                debugger.set_register(*register, u16::from_le_bytes([value[0], value[1]]));
+                Some("OK".to_string())
+            }),
+            "m" => parse_range(args).map(|(address, length)| {
+                to_hex(&debugger.read_memory_range(address, length as usize))
+            }),
+            "M" => split2(args, ':').and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let bytes = from_hex(data).filter(|x| x.len() == length as usize)?;
+                for (i, x) in bytes.iter().enumerate() {
+                    debugger.write_memory(address.wrapping_add(i as u16), *x);
+                }
+                Some("OK".to_string())
+            }),
+            "Z" => self.add_break(debugger, args),
+            "z" => self.remove_break(debugger, args),
+            "H" => Some("OK".to_string()),
+            "q" | "Q" => query(packet),
+            _ => Some(String::new()),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    // Z and z packets are type,address,kind. Kind is the length of watchpoints
+    fn parse_break(args: &str) -> Option<(u8, u32, u32)> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?.parse().ok()?;
+        let address = parse_hex(parts.next()?)?;
+        let length = parse_hex(parts.next()?.split(';').next()?)?;
+        Some((kind, address, length))
+    }
+
+    fn add_break(&mut self, debugger: &mut Debugger, args: &str) -> Option<String> {
+        let key = GdbStub::parse_break(args)?;
+        let (kind, address, length) = key;
+        if self.breaks.contains_key(&key) {
+            return Some("OK".to_string());
+        }
+        let item = match kind {
+            // Software and hardware breakpoints are the same here
+            0 | 1 => Break::Breakpoint {
+                address: address as u16,
+                bank: if address > 0xffff {
+                    Some(address as usize >> 16)
+                } else {
+                    None
+                },
+                condition: None,
+            },
+            2..=4 => {
+                if address > 0xffff || length == 0 || address + length > 0x10000 {
+                    return Some("E01".to_string());
+                }
+                let access = match kind {
+                    2 => Access::Write,
+                    3 => Access::Read,
+                    _ => Access::ReadWrite,
+                };
+                Break::Watchpoint {
+                    range: address as u16..=(address + length - 1) as u16,
+                    access,
+                }
+            }
+            // An empty reply means the type isn't supported
+            _ => return Some(String::new()),
+        };
+        self.breaks.insert(key, debugger.add_break(item));
+        Some("OK".to_string())
+    }
+
+    fn remove_break(&mut self, debugger: &mut Debugger, args: &str) -> Option<String> {
+        let key = GdbStub::parse_break(args)?;
+        if let Some(id) = self.breaks.remove(&key) {
+            debugger.remove_break(id);
+        }
+        Some("OK".to_string())
+    }
+
+    // Runs a step or until a break, gdb interrupts or the app stops.
+    // Returns the stop reply. The s and c packets can have an address to
+    // resume from
+    fn resume<T: App>(
+        &mut self,
+        debugger: &mut Debugger,
+        app: &mut T,
+        packet: &str,
+    ) -> Result<String> {
+        if packet.len() > 1 {
+            match parse_hex(&packet[1..]) {
+                Some(x) if x <= 0xffff => debugger.set_register(Register::PC, x as u16),
+                _ => return Ok("E01".to_string()),
+            }
+        }
+        let reason = if packet.starts_with('s') {
+            debugger.step(app)
+        } else {
+            // Run a frame at a time, checking for an interrupt in between
+            let mut resuming = false;
+            loop {
+                let reason = debugger.continue_for(app, FRAME_CYCLES, resuming);
+                if reason != StopReason::Step {
+                    break reason;
+                }
+                if self.check_interrupt()? {
+                    return Ok(INTERRUPTED.to_string());
+                }
+                resuming = true;
+            }
+        };
+
+        let reply = match reason {
+            StopReason::Watchpoint {
+                address,
+                access: Access::Read,
+                ..
+            } => format!("T05rwatch:{:x};", address),
+            StopReason::Watchpoint {
+                address,
+                access: Access::Write,
+                ..
+            } => format!("T05watch:{:x};", address),
+            StopReason::AppStopped => "W00".to_string(),
+            _ => STOPPED.to_string(),
+        };
+        Ok(reply)
+    }
+
+    // True if gdb has sent an interrupt, without waiting for one
+    fn check_interrupt(&mut self) -> Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+        let result = match self.reader.fill_buf() {
+            Ok(x) => Ok(x.first() == Some(&INTERRUPT)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.reader.get_ref().set_nonblocking(false)?;
+        let interrupted = result?;
+        if interrupted {
+            self.reader.consume(1);
+        }
+        Ok(interrupted)
+    }
+
+    // A packet is $data#checksum, and acknowledged with + or - to resend
+    // until gdb asks for no acks. Returns None if gdb disconnects
+    fn read_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            let mut byte = [0];
+            loop {
+                if self.read_byte(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'$' => break,
+                    INTERRUPT => return Ok(Some(Packet::Interrupt)),
+                    // Acks for our packets, and anything between packets
+                    _ => (),
+                }
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            for x in checksum.iter_mut() {
+                if self.read_byte(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                *x = byte[0];
+            }
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                == Some(get_checksum(&data));
+            if self.acks {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Packet::Data(
+                    String::from_utf8_lossy(&data).into_owned(),
+                )));
+            }
+        }
+    }
+
+    fn read_byte(&mut self, byte: &mut [u8; 1]) -> io::Result<usize> {
+        loop {
+            match io::Read::read(&mut self.reader, byte) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                x => return x,
+            }
+        }
+    }
+
+    // Replies are sent without waiting for the ack, which
+    // is skipped over when the next packet is read
+    fn write_packet(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${}#{:02x}", data, get_checksum(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        Ok(())
+    }
+}
+
+// Replies to q and Q packets, None for an error
+fn query(packet: &str) -> Option<String> {
+    if packet.starts_with("qSupported") {
+        return Some("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string());
+    }
+    if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let (offset, length) = split2(args, ',')?;
+        let offset = (parse_hex(offset)? as usize).min(TARGET_XML.len());
+        let end = (offset + parse_hex(length)? as usize).min(TARGET_XML.len());
+        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+        return Some(format!("{}{}", marker, &TARGET_XML[offset..end]));
+    }
+    let reply = match packet {
+        "qAttached" => "1",
+        "qC" => "QC1",
+        "qfThreadInfo" => "m1",
+        "qsThreadInfo" => "l",
+        _ => "",
+    };
+    Some(reply.to_string())
+}
+
+fn get_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |x, y| x.wrapping_add(*y))
+}
+
+fn split2(text: &str, separator: char) -> Option<(&str, &str)> {
+    let i = text.find(separator)?;
+    Some((&text[..i], &text[i + 1..]))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// address,length within the 16 bit address space
+fn parse_range(text: &str) -> Option<(u16, u32)> {
+    let (address, length) = split2(text, ',')?;
+    let address = parse_hex(address)?;
+    let length = parse_hex(length)?;
+    if address > 0xffff || length > 0x10000 {
+        return None;
+    }
+    Some((address as u16, length))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 == 1 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex(&[0x01, 0xab]), "01ab");
+        assert_eq!(from_hex("01ab"), Some(vec![0x01, 0xab]));
+        assert_eq!(from_hex("01a"), None);
+        assert_eq!(get_checksum(b"OK"), 0x9a);
+    }
+
+    #[test]
+    fn target_xml_in_parts() {
+        let first = query("qXfer:features:read:target.xml:0,10").unwrap();
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+        let rest = query(&format!(
+            "qXfer:features:read:target.xml:10,{:x}",
+            TARGET_XML.len()
+        ))
+        .unwrap();
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x10..]));
+    }
+}
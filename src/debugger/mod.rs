@@ -0,0 +1,495 @@
+mod gdb;
+mod repl;
+pub use self::gdb::GdbStub;
+use crate::{App, Command, Emulator};
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_lowercase().as_str() {
+            "a" => Register::A,
+            "f" => Register::F,
+            "b" => Register::B,
+            "c" => Register::C,
+            "d" => Register::D,
+            "e" => Register::E,
+            "h" => Register::H,
+            "l" => Register::L,
+            "af" => Register::AF,
+            "bc" => Register::BC,
+            "de" => Register::DE,
+            "hl" => Register::HL,
+            "sp" => Register::SP,
+            "pc" => Register::PC,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Register::A => "a",
+            Register::F => "f",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+            Register::E => "e",
+            Register::H => "h",
+            Register::L => "l",
+            Register::AF => "af",
+            Register::BC => "bc",
+            Register::DE => "de",
+            Register::HL => "hl",
+            Register::SP => "sp",
+            Register::PC => "pc",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn get_symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn compare(self, a: u16, b: u16) -> bool {
+        match self {
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+        }
+    }
+}
+
+// A register compared with a value, like a == 0x10
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn new(register: Register, comparison: Comparison, value: u16) -> Condition {
+        Condition {
+            register,
+            comparison,
+            value,
+        }
+    }
+
+    fn is_met(&self, emulator: &Emulator) -> bool {
+        let value = get_register(emulator, self.register);
+        self.comparison.compare(value, self.value)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+    // Running an instruction that starts in the range
+    Execute,
+}
+
+impl Access {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+            Access::Execute => false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Break {
+    // Stops before the instruction at address is run, if the condition
+    // is met. Addresses in 0x4000 - 0x7fff can be limited to one rom bank
+    Breakpoint {
+        address: u16,
+        bank: Option<usize>,
+        condition: Option<Condition>,
+    },
+    // Reads and writes stop after the instruction that made them,
+    // execution stops before the instruction is run
+    Watchpoint {
+        range: RangeInclusive<u16>,
+        access: Access,
+    },
+    // Stops after the instruction that makes the condition true
+    Condition(Condition),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    // The step, step over or step out finished, or continue_for ran out of cycles
+    Step,
+    Breakpoint(usize),
+    // The access is Read, Write or Execute. For execution the
+    // value is the opcode
+    Watchpoint {
+        id: usize,
+        address: u16,
+        value: u8,
+        access: Access,
+    },
+    Condition(usize),
+    // The app returned Command::Stop at the end of a frame
+    AppStopped,
+}
+
+// Runs an emulator an instruction at a time, stopping at breakpoints,
+// watchpoints and conditions. Breaks are numbered from 1, in the order
+// they are added
+pub struct Debugger {
+    emulator: Emulator,
+    breaks: Vec<(usize, Break)>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new(emulator: Emulator) -> Debugger {
+        Debugger {
+            emulator,
+            breaks: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn into_emulator(self) -> Emulator {
+        self.emulator
+    }
+
+    pub fn get_emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn get_emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    // Returns the id of the break, for remove_break
+    pub fn add_break(&mut self, item: Break) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breaks.push((id, item));
+        id
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16, bank: Option<usize>) -> usize {
+        self.add_break(Break::Breakpoint {
+            address,
+            bank,
+            condition: None,
+        })
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access) -> usize {
+        self.add_break(Break::Watchpoint { range, access })
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) -> usize {
+        self.add_break(Break::Condition(condition))
+    }
+
+    // Returns false if there is no break with the id
+    pub fn remove_break(&mut self, id: usize) -> bool {
+        let count = self.breaks.len();
+        self.breaks.retain(|x| x.0 != id);
+        self.breaks.len() != count
+    }
+
+    pub fn get_breaks(&self) -> &[(usize, Break)] {
+        &self.breaks
+    }
+
+    // Run one instruction. Breaks before it are ignored, so this
+    // always makes progress after stopping at a breakpoint
+    pub fn step<T: App>(&mut self, app: &mut T) -> StopReason {
+        self.run_until(app, true, |_, opcode| opcode.is_some())
+    }
+
+    // Like step, but a call or rst runs until it returns
+    pub fn step_over<T: App>(&mut self, app: &mut T) -> StopReason {
+        let pc = self.emulator.cpu.get_registers().pc;
+        let sp = self.emulator.cpu.get_registers().sp;
+        let length = match self.read_memory(pc) {
+            0xcd | 0xc4 | 0xcc | 0xd4 | 0xdc => 3,
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => 1,
+            _ => return self.step(app),
+        };
+        let return_address = pc.wrapping_add(length);
+        self.run_until(app, true, |emulator, opcode| {
+            let registers = emulator.cpu.get_registers();
+            opcode.is_some() && registers.pc == return_address && registers.sp >= sp
+        })
+    }
+
+    // Run until the current function returns
+    pub fn step_out<T: App>(&mut self, app: &mut T) -> StopReason {
+        let sp = self.emulator.cpu.get_registers().sp;
+        self.run_until(app, true, |emulator, opcode| {
+            let is_return = matches!(opcode, Some(0xc9 | 0xd9 | 0xc0 | 0xc8 | 0xd0 | 0xd8));
+            is_return && emulator.cpu.get_registers().sp > sp
+        })
+    }
+
+    // Run until a break is hit or the app stops
+    pub fn continue_running<T: App>(&mut self, app: &mut T) -> StopReason {
+        self.run_until(app, true, |_, _| false)
+    }
+
+    // Like continue_running, but returns Step after running for at least
+    // cycles, counting time in STOP mode. Pass resuming to check the breaks before the first
+    // instruction, when carrying on from a call that ran out of cycles
+    pub fn continue_for<T: App>(&mut self, app: &mut T, cycles: u64, resuming: bool) -> StopReason {
+        let end = self.emulator.get_elapsed_cycles().saturating_add(cycles);
+        self.run_until(app, !resuming, |emulator, _| {
+            emulator.get_elapsed_cycles() >= end
+        })
+    }
+
+    pub fn get_register(&self, register: Register) -> u16 {
+        get_register(&self.emulator, register)
+    }
+
+    // The low 4 bits of f are always 0
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        let registers = self.emulator.cpu.get_registers_mut();
+        let byte = value as u8;
+        match register {
+            Register::A => registers.a = byte,
+            Register::F => registers.f = byte & 0xf0,
+            Register::B => registers.b = byte,
+            Register::C => registers.c = byte,
+            Register::D => registers.d = byte,
+            Register::E => registers.e = byte,
+            Register::H => registers.h = byte,
+            Register::L => registers.l = byte,
+            Register::AF => registers.set_af(value),
+            Register::BC => registers.set_bc(value),
+            Register::DE => registers.set_de(value),
+            Register::HL => registers.set_hl(value),
+            Register::SP => registers.sp = value,
+            Register::PC => registers.pc = value,
+        }
+    }
+
+    // Reads don't trigger watchpoints. The unusable area
+    // at 0xfea0 - 0xfeff reads as 0xff
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.emulator.memory.peek_u8(address)
+    }
+
+    // Wraps around at the end of memory
+    pub fn read_memory_range(&self, start: u16, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|i| self.read_memory(start.wrapping_add(i as u16)))
+            .collect()
+    }
+
+    // Writes as the cpu would, so writes to rom go to the cartridge's
+    // mapper registers
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        self.emulator.memory.set_u8(address, value);
+    }
+
+    // The rom bank mapped into 0x4000 - 0x7fff
+    pub fn get_rom_bank(&self) -> usize {
+        self.emulator.memory.get_cartridge().get_rom_bank()
+    }
+
+    // The rom bank an address is in, 0 for anything outside the switchable area
+    pub fn get_bank_at(&self, address: u16) -> usize {
+        match address {
+            0x4000..=0x7fff => self.get_rom_bank(),
+            _ => 0,
+        }
+    }
+
+    // Runs until done returns true or a break is hit. done is called after
+    // every tick, with the opcode run or None if the cpu was halted.
+    // Memory accesses are only recorded while there are read or write watchpoints
+    fn run_until<T, F>(&mut self, app: &mut T, skip_first: bool, done: F) -> StopReason
+    where
+        T: App,
+        F: FnMut(&Emulator, Option<u8>) -> bool,
+    {
+        let watching = self.breaks.iter().any(|(_, x)| match x {
+            Break::Watchpoint { access, .. } => *access != Access::Execute,
+            _ => false,
+        });
+        self.emulator.memory.set_recording_accesses(watching);
+        let reason = self.run_loop(app, skip_first, done);
+        self.emulator.memory.set_recording_accesses(false);
+        reason
+    }
+
+    fn run_loop<T, F>(&mut self, app: &mut T, skip_first: bool, mut done: F) -> StopReason
+    where
+        T: App,
+        F: FnMut(&Emulator, Option<u8>) -> bool,
+    {
+        self.emulator.update_sample_rate(app);
+        let mut first = skip_first;
+        loop {
+            // Frames are finished before the next instruction, so a
+            // break on the last instruction of a frame stops first
+            if self.emulator.lcd.is_vblank() {
+                if let Command::Stop = self.emulator.finish_frame(app) {
+                    return StopReason::AppStopped;
+                }
+            }
+
+            // Nothing runs while halted, so breaks wait for the cpu to wake
+            let halted = self.emulator.cpu.is_halted();
+            if !halted {
+                if !first {
+                    if let Some(reason) = self.check_before() {
+                        return reason;
+                    }
+                }
+                first = false;
+            }
+
+            let opcode = self.read_memory(self.emulator.cpu.get_registers().pc);
+            let conditions: Vec<bool> = self
+                .breaks
+                .iter()
+                .map(|(_, x)| match x {
+                    Break::Condition(condition) => condition.is_met(&self.emulator),
+                    _ => false,
+                })
+                .collect();
+
+            self.emulator.memory.take_accesses();
+            self.emulator.tick(app);
+
+            if let Some(reason) = self.check_after(&conditions) {
+                return reason;
+            }
+            let opcode = if halted { None } else { Some(opcode) };
+            if done(&self.emulator, opcode) {
+                return StopReason::Step;
+            }
+        }
+    }
+
+    fn check_before(&self) -> Option<StopReason> {
+        let pc = self.emulator.cpu.get_registers().pc;
+        for (id, item) in self.breaks.iter() {
+            match item {
+                Break::Breakpoint {
+                    address,
+                    bank,
+                    condition,
+                } if *address == pc
+                    && bank.iter().all(|&x| self.get_bank_at(pc) == x)
+                    && condition.iter().all(|x| x.is_met(&self.emulator)) =>
+                {
+                    return Some(StopReason::Breakpoint(*id));
+                }
+                Break::Watchpoint {
+                    range,
+                    access: Access::Execute,
+                } if range.contains(&pc) => {
+                    return Some(StopReason::Watchpoint {
+                        id: *id,
+                        address: pc,
+                        value: self.read_memory(pc),
+                        access: Access::Execute,
+                    });
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    // conditions has whether each condition was met before the instruction
+    fn check_after(&mut self, conditions: &[bool]) -> Option<StopReason> {
+        let accesses = self.emulator.memory.take_accesses();
+        for ((id, item), was_met) in self.breaks.iter().zip(conditions.iter()) {
+            match item {
+                Break::Watchpoint { range, access } => {
+                    let hit = accesses
+                        .iter()
+                        .find(|x| range.contains(&x.address) && access.matches(x.write));
+                    if let Some(x) = hit {
+                        return Some(StopReason::Watchpoint {
+                            id: *id,
+                            address: x.address,
+                            value: x.value,
+                            access: if x.write { Access::Write } else { Access::Read },
+                        });
+                    }
+                }
+                Break::Condition(condition) if !was_met && condition.is_met(&self.emulator) => {
+                    return Some(StopReason::Condition(*id));
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+fn get_register(emulator: &Emulator, register: Register) -> u16 {
+    let registers = emulator.cpu.get_registers();
+    match register {
+        Register::A => u16::from(registers.a),
+        Register::F => u16::from(registers.f),
+        Register::B => u16::from(registers.b),
+        Register::C => u16::from(registers.c),
+        Register::D => u16::from(registers.d),
+        Register::E => u16::from(registers.e),
+        Register::H => u16::from(registers.h),
+        Register::L => u16::from(registers.l),
+        Register::AF => registers.get_af(),
+        Register::BC => registers.get_bc(),
+        Register::DE => registers.get_de(),
+        Register::HL => registers.get_hl(),
+        Register::SP => registers.sp,
+        Register::PC => registers.pc,
+    }
+}
@@ -0,0 +1,421 @@
+use super::{Access, Break, Comparison, Condition, Debugger, Register, StopReason};
+use crate::{disasm, App};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "commands, numbers are hex:
+  break [bank:]<addr> [if <cond>]   stop before running addr, b for short
+  watch <addr>[-<addr>] [r|w|rw|x]  stop on reads, writes or execution
+                                    in a range, writes by default
+  cond <cond>                       stop once a condition becomes true,
+                                    like a==10 or hl>=c000
+  delete <id>                       remove a break, d for short
+  list                              list the breaks, l for short
+  step [n]                          run n instructions, s for short
+  next                              step over calls and rsts, n for short
+  finish                            run until the current function returns
+  continue                          run until a break, c for short
+  regs                              show the registers, r for short
+  set <reg> <value>                 change a register
+  x <addr> [length]                 show memory, 16 bytes by default
+  dis [addr] [count]                disassemble from addr or pc, 10
+                                    instructions by default
+  write <addr> <value>              change memory
+  quit                              stop debugging, q for short";
+
+impl Debugger {
+    // Reads commands from input until quit or the end of the input, an
+    // empty line repeats the last command. See help for the commands
+    pub fn run_repl<T, R, W>(&mut self, app: &mut T, input: R, mut output: W) -> io::Result<()>
+    where
+        T: App,
+        R: BufRead,
+        W: Write,
+    {
+        self.write_location(&mut output)?;
+        let mut last = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(gb) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(x) => x?,
+                None => break,
+            };
+            let line = match line.trim() {
+                "" => last.clone(),
+                x => x.to_string(),
+            };
+            last = line.clone();
+
+            let mut words = line.split_whitespace();
+            let command = match words.next() {
+                Some(x) => x,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+            match self.run_command(command, &args, app, &mut output) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(CommandError::Io(e)) => return Err(e),
+                Err(CommandError::Bad(message)) => writeln!(output, "{}", message)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Returns false to quit
+    fn run_command<T: App, W: Write>(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        app: &mut T,
+        output: &mut W,
+    ) -> Result<bool, CommandError> {
+        match command {
+            "b" | "break" => {
+                let (address, bank) = parse_location(arg(args, 0)?)?;
+                let condition = match args.get(1) {
+                    Some(&"if") => Some(parse_condition(&args[2..].concat())?),
+                    Some(x) => return Err(bad(format!("unexpected {}", x))),
+                    None => None,
+                };
+                let item = Break::Breakpoint {
+                    address,
+                    bank,
+                    condition,
+                };
+                let id = self.add_break(item.clone());
+                writeln!(output, "{}: {}", id, format_break(&item))?;
+            }
+            "w" | "watch" => {
+                let range = arg(args, 0)?;
+                let mut parts = range.splitn(2, '-');
+                let start = parse_number(parts.next().unwrap_or(""))?;
+                let end = match parts.next() {
+                    Some(x) => parse_number(x)?,
+                    None => start,
+                };
+                if end < start {
+                    return Err(bad(format!("bad range {}", range)));
+                }
+                let access = match args.get(1).copied() {
+                    None | Some("w") => Access::Write,
+                    Some("r") => Access::Read,
+                    Some("rw") => Access::ReadWrite,
+                    Some("x") => Access::Execute,
+                    Some(x) => return Err(bad(format!("unknown access {}", x))),
+                };
+                let item = Break::Watchpoint {
+                    range: start..=end,
+                    access,
+                };
+                let id = self.add_break(item.clone());
+                writeln!(output, "{}: {}", id, format_break(&item))?;
+            }
+            "cond" => {
+                let item = Break::Condition(parse_condition(&args.concat())?);
+                let id = self.add_break(item.clone());
+                writeln!(output, "{}: {}", id, format_break(&item))?;
+            }
+            "d" | "delete" => {
+                let id = arg(args, 0)?;
+                let removed = matches!(id.parse(), Ok(x) if self.remove_break(x));
+                if !removed {
+                    return Err(bad(format!("no break {}", id)));
+                }
+            }
+            "l" | "list" => {
+                if self.breaks.is_empty() {
+                    writeln!(output, "no breaks")?;
+                }
+                for (id, item) in self.breaks.iter() {
+                    writeln!(output, "{}: {}", id, format_break(item))?;
+                }
+            }
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(x) => x
+                        .parse()
+                        .map_err(|_| bad(format!("{} is not a number", x)))?,
+                    None => 1,
+                };
+                let mut reason = StopReason::Step;
+                for _ in 0..count {
+                    reason = self.step(app);
+                    if reason != StopReason::Step {
+                        break;
+                    }
+                }
+                self.write_stop(reason, output)?;
+            }
+            "n" | "next" => {
+                let reason = self.step_over(app);
+                self.write_stop(reason, output)?;
+            }
+            "finish" => {
+                let reason = self.step_out(app);
+                self.write_stop(reason, output)?;
+            }
+            "c" | "continue" => {
+                let reason = self.continue_running(app);
+                self.write_stop(reason, output)?;
+            }
+            "r" | "regs" => self.write_registers(output)?,
+            "set" => {
+                let name = arg(args, 0)?;
+                let register = Register::from_name(name)
+                    .ok_or_else(|| bad(format!("unknown register {}", name)))?;
+                let value = parse_number(arg(args, 1)?)?;
+                self.set_register(register, value);
+            }
+            "x" => {
+                let start = parse_number(arg(args, 0)?)?;
+                let length = match args.get(1) {
+                    Some(x) => parse_number(x)?,
+                    None => 16,
+                };
+                let bytes = self.read_memory_range(start, usize::from(length));
+                for (i, line) in bytes.chunks(16).enumerate() {
+                    let address = start.wrapping_add(i as u16 * 16);
+                    let hex: Vec<String> = line.iter().map(|x| format!("{:02x}", x)).collect();
+                    writeln!(output, "{:04x}: {}", address, hex.join(" "))?;
+                }
+            }
+            "dis" => {
+                let start = match args.first() {
+                    Some(x) => parse_number(x)?,
+                    None => self.get_register(Register::PC),
+                };
+                let count = match args.get(1) {
+                    Some(x) => parse_number(x)?,
+                    None => 10,
+                };
+                self.write_instructions(start, usize::from(count), output)?;
+            }
+            "write" => {
+                let address = parse_number(arg(args, 0)?)?;
+                let value = parse_number(arg(args, 1)?)?;
+                if value > 0xff {
+                    return Err(bad(format!("{:x} doesn't fit in a byte", value)));
+                }
+                self.write_memory(address, value as u8);
+            }
+            "h" | "help" => writeln!(output, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => return Err(bad(format!("unknown command {}, try help", command))),
+        }
+        Ok(true)
+    }
+
+    fn write_stop<W: Write>(&self, reason: StopReason, output: &mut W) -> io::Result<()> {
+        match reason {
+            StopReason::Step => (),
+            StopReason::Breakpoint(id) => writeln!(output, "hit breakpoint {}", id)?,
+            StopReason::Watchpoint {
+                id,
+                address,
+                value,
+                access,
+            } => {
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Execute => "execute",
+                    _ => "write",
+                };
+                writeln!(
+                    output,
+                    "hit watchpoint {}, {} {:02x} at {:04x}",
+                    id, access, value, address
+                )?;
+            }
+            StopReason::Condition(id) => writeln!(output, "hit condition {}", id)?,
+            StopReason::AppStopped => writeln!(output, "app stopped")?,
+        }
+        self.write_location(output)
+    }
+
+    fn write_location<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let pc = self.get_register(Register::PC);
+        self.write_instructions(pc, 1, output)
+    }
+
+    // Each line is the bank, address, bytes and the instruction
+    fn write_instructions<W: Write>(
+        &self,
+        mut address: u16,
+        count: usize,
+        output: &mut W,
+    ) -> io::Result<()> {
+        for _ in 0..count {
+            let bytes = self.read_memory_range(address, 3);
+            let instruction = disasm::decode(&bytes, address).unwrap();
+            let length = usize::from(instruction.length);
+            let hex: Vec<String> = bytes[..length]
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect();
+            writeln!(
+                output,
+                "{:02x}:{:04x}  {:<8}  {}",
+                self.get_bank_at(address),
+                address,
+                hex.join(" "),
+                instruction
+            )?;
+            address = address.wrapping_add(length as u16);
+        }
+        Ok(())
+    }
+
+    fn write_registers<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let f = self.get_register(Register::F);
+        let flags: String = ["z", "n", "h", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, x)| if f & (0x80 >> i) != 0 { *x } else { "-" })
+            .collect();
+        writeln!(
+            output,
+            "af={:04x} bc={:04x} de={:04x} hl={:04x} sp={:04x} pc={:04x} {}",
+            self.get_register(Register::AF),
+            self.get_register(Register::BC),
+            self.get_register(Register::DE),
+            self.get_register(Register::HL),
+            self.get_register(Register::SP),
+            self.get_register(Register::PC),
+            flags
+        )
+    }
+}
+
+enum CommandError {
+    Io(io::Error),
+    // Bad input, which is reported before reading the next command
+    Bad(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> CommandError {
+        CommandError::Io(e)
+    }
+}
+
+fn bad(message: String) -> CommandError {
+    CommandError::Bad(message)
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str, CommandError> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| bad("missing argument, try help".to_string()))
+}
+
+// Hex, with an optional 0x or $
+fn parse_number(text: &str) -> Result<u16, CommandError> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| bad(format!("{} is not a hex number", text)))
+}
+
+// An address, with the bank first like 01:4000
+fn parse_location(text: &str) -> Result<(u16, Option<usize>), CommandError> {
+    match text.find(':') {
+        Some(i) => {
+            let bank = parse_number(&text[..i])?;
+            Ok((parse_number(&text[i + 1..])?, Some(usize::from(bank))))
+        }
+        None => Ok((parse_number(text)?, None)),
+    }
+}
+
+// Like a==10, without spaces
+fn parse_condition(text: &str) -> Result<Condition, CommandError> {
+    let comparisons = [
+        Comparison::Equal,
+        Comparison::NotEqual,
+        Comparison::LessOrEqual,
+        Comparison::GreaterOrEqual,
+        Comparison::Less,
+        Comparison::Greater,
+    ];
+    for comparison in comparisons.iter() {
+        if let Some(i) = text.find(comparison.get_symbol()) {
+            let name = &text[..i];
+            let register = Register::from_name(name)
+                .ok_or_else(|| bad(format!("unknown register {}", name)))?;
+            let value = parse_number(&text[i + comparison.get_symbol().len()..])?;
+            return Ok(Condition::new(register, *comparison, value));
+        }
+    }
+    Err(bad(format!("bad condition {}", text)))
+}
+
+fn format_condition(condition: &Condition) -> String {
+    format!(
+        "{}{}{:x}",
+        condition.register.get_name(),
+        condition.comparison.get_symbol(),
+        condition.value
+    )
+}
+
+fn format_break(item: &Break) -> String {
+    match item {
+        Break::Breakpoint {
+            address,
+            bank,
+            condition,
+        } => {
+            let mut text = match bank {
+                Some(x) => format!("break {:02x}:{:04x}", x, address),
+                None => format!("break {:04x}", address),
+            };
+            if let Some(x) = condition {
+                text.push_str(&format!(" if {}", format_condition(x)));
+            }
+            text
+        }
+        Break::Watchpoint { range, access } => {
+            let access = match access {
+                Access::Read => "r",
+                Access::Write => "w",
+                Access::ReadWrite => "rw",
+                Access::Execute => "x",
+            };
+            format!("watch {:04x}-{:04x} {}", range.start(), range.end(), access)
+        }
+        Break::Condition(x) => format!("cond {}", format_condition(x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        assert_eq!(parse_location("0150").ok(), Some((0x150, None)));
+        assert_eq!(parse_location("$c000").ok(), Some((0xc000, None)));
+        assert_eq!(parse_location("2:0x4010").ok(), Some((0x4010, Some(2))));
+        assert!(parse_location("zz").is_err());
+    }
+
+    #[test]
+    fn conditions() {
+        let condition = parse_condition("hl>=c000").ok().unwrap();
+        assert_eq!(
+            condition,
+            Condition::new(Register::HL, Comparison::GreaterOrEqual, 0xc000)
+        );
+        let condition = parse_condition("A==10").ok().unwrap();
+        assert_eq!(
+            condition,
+            Condition::new(Register::A, Comparison::Equal, 0x10)
+        );
+        assert_eq!(format_condition(&condition), "a==10");
+        assert!(parse_condition("q<1").is_err());
+        assert!(parse_condition("a").is_err());
+    }
+}
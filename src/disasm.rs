@@ -0,0 +1,424 @@
+// Decodes SM83 machine code into instructions, which format as RGBDS
+// assembly. Nothing here needs a running cpu, so whole banks can be
+// disassembled straight from the rom
+use crate::debugger::Register;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JumpCondition {
+    NotZero,
+    Zero,
+    NotCarry,
+    Carry,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    Register(Register),
+    // [bc], [de] or [hl]
+    Indirect(Register),
+    // [hl+] and [hl-]
+    IndirectIncrement,
+    IndirectDecrement,
+    // [$ff00 + c], written [c] with ldh
+    HighC,
+    Immediate8(u8),
+    Immediate16(u16),
+    // The offset of add sp, e8
+    SignedImmediate(i8),
+    // sp + e8, in ld hl, sp + e8
+    SpOffset(i8),
+    // [n16]
+    Address(u16),
+    // [$ff00 + n8], with ldh
+    HighAddress(u8),
+    Condition(JumpCondition),
+    // Where a jump, call or rst goes, with jr offsets already added
+    Target(u16),
+    // The bit of bit, res and set
+    Bit(u8),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    // For cb prefixed instructions, the byte after the prefix
+    pub opcode: u8,
+    pub prefixed: bool,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: u8,
+    // Clock cycles, at 4194304 Hz. For conditional instructions
+    // this is when the condition fails
+    pub cycles: u8,
+    // Cycles when the condition of a conditional instruction passes
+    pub cycles_taken: Option<u8>,
+}
+
+impl Instruction {
+    // Where a jump, call or rst goes. jp hl and returns
+    // have no fixed target
+    pub fn get_target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|x| match x {
+            Operand::Target(x) => Some(*x),
+            _ => None,
+        })
+    }
+
+    // False for bytes that aren't an instruction, which format as db
+    pub fn is_valid(&self) -> bool {
+        self.mnemonic != "db"
+    }
+}
+
+impl fmt::Display for JumpCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            JumpCondition::NotZero => "nz",
+            JumpCondition::Zero => "z",
+            JumpCondition::NotCarry => "nc",
+            JumpCondition::Carry => "c",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(x) => write!(f, "{}", x.get_name()),
+            Operand::Indirect(x) => write!(f, "[{}]", x.get_name()),
+            Operand::IndirectIncrement => write!(f, "[hl+]"),
+            Operand::IndirectDecrement => write!(f, "[hl-]"),
+            Operand::HighC => write!(f, "[c]"),
+            Operand::Immediate8(x) => write!(f, "${:02x}", x),
+            Operand::Immediate16(x) => write!(f, "${:04x}", x),
+            Operand::SignedImmediate(x) => write!(f, "{}", x),
+            Operand::SpOffset(x) if *x < 0 => write!(f, "sp - {}", -i16::from(*x)),
+            Operand::SpOffset(x) => write!(f, "sp + {}", x),
+            Operand::Address(x) => write!(f, "[${:04x}]", x),
+            Operand::HighAddress(x) => write!(f, "[$ff{:02x}]", x),
+            Operand::Condition(x) => write!(f, "{}", x),
+            Operand::Target(x) => write!(f, "${:04x}", x),
+            Operand::Bit(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+        Ok(())
+    }
+}
+
+// Decodes the instruction at the start of bytes, which is at address.
+// Returns None if bytes is empty. Unused opcodes, and instructions cut
+// off by the end of bytes, decode as a one byte db
+pub fn decode(bytes: &[u8], address: u16) -> Option<Instruction> {
+    let opcode = *bytes.first()?;
+    let instruction = if opcode == 0xcb {
+        bytes.get(1).map(|x| decode_prefixed(*x, address))
+    } else {
+        decode_unprefixed(bytes, address)
+    };
+    match instruction {
+        Some(x) if x.is_valid() && usize::from(x.length) <= bytes.len() => Some(x),
+        _ => Some(data_byte(opcode, address)),
+    }
+}
+
+// Decodes every instruction in bytes, which start at address
+pub fn disassemble(bytes: &[u8], address: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while let Some(x) = decode(&bytes[offset..], address.wrapping_add(offset as u16)) {
+        offset += usize::from(x.length);
+        instructions.push(x);
+    }
+    instructions
+}
+
+const R8: [Register; 8] = [
+    Register::B,
+    Register::C,
+    Register::D,
+    Register::E,
+    Register::H,
+    Register::L,
+    Register::HL,
+    Register::A,
+];
+const R16: [Register; 4] = [Register::BC, Register::DE, Register::HL, Register::SP];
+// The register pairs of push and pop
+const R16_STACK: [Register; 4] = [Register::BC, Register::DE, Register::HL, Register::AF];
+const CONDITIONS: [JumpCondition; 4] = [
+    JumpCondition::NotZero,
+    JumpCondition::Zero,
+    JumpCondition::NotCarry,
+    JumpCondition::Carry,
+];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROTATES: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const ACCUMULATOR: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+
+// Register index 6 is [hl]
+fn r8(index: u8) -> Operand {
+    match index {
+        6 => Operand::Indirect(Register::HL),
+        x => Operand::Register(R8[usize::from(x)]),
+    }
+}
+
+fn data_byte(byte: u8, address: u16) -> Instruction {
+    Instruction {
+        address,
+        opcode: byte,
+        prefixed: false,
+        mnemonic: "db",
+        operands: vec![Operand::Immediate8(byte)],
+        length: 1,
+        cycles: 0,
+        cycles_taken: None,
+    }
+}
+
+// Opcodes are split into fields as xxyyyzzz, with y split into ppq
+fn decode_unprefixed(bytes: &[u8], address: u16) -> Option<Instruction> {
+    use self::Operand::{Address, HighAddress, HighC, Immediate16, Immediate8, Indirect};
+    use self::Operand::{IndirectDecrement, IndirectIncrement, SignedImmediate, SpOffset, Target};
+    let register = Operand::Register;
+    let opcode = bytes[0];
+    let n8 = bytes.get(1).cloned().unwrap_or(0);
+    let n16 = u16::from(n8) | u16::from(bytes.get(2).cloned().unwrap_or(0)) << 8;
+    let relative = address.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0b111, opcode & 0b111);
+    let (p, q) = (usize::from(y >> 1), y & 1);
+    let a = register(Register::A);
+    let hl = register(Register::HL);
+    let condition = Operand::Condition(CONDITIONS[usize::from(y & 0b11)]);
+    // Cycles of instructions that can use [hl] instead of a register
+    let with_hl = |operand: u8, cycles: u8, hl_cycles: u8| {
+        if operand == 6 {
+            hl_cycles
+        } else {
+            cycles
+        }
+    };
+
+    let (mnemonic, operands, length, cycles, cycles_taken) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("nop", vec![], 1, 4, None),
+            1 => (
+                "ld",
+                vec![Address(n16), register(Register::SP)],
+                3,
+                20,
+                None,
+            ),
+            // stop is followed by a byte that is ignored
+            2 => ("stop", vec![], 2, 4, None),
+            3 => ("jr", vec![Target(relative)], 2, 12, None),
+            _ => ("jr", vec![condition, Target(relative)], 2, 8, Some(12)),
+        },
+        (0, 1) if q == 0 => ("ld", vec![register(R16[p]), Immediate16(n16)], 3, 12, None),
+        (0, 1) => ("add", vec![hl, register(R16[p])], 1, 8, None),
+        (0, 2) => {
+            let memory = match p {
+                0 => Indirect(Register::BC),
+                1 => Indirect(Register::DE),
+                2 => IndirectIncrement,
+                _ => IndirectDecrement,
+            };
+            let operands = if q == 0 {
+                vec![memory, a]
+            } else {
+                vec![a, memory]
+            };
+            ("ld", operands, 1, 8, None)
+        }
+        (0, 3) if q == 0 => ("inc", vec![register(R16[p])], 1, 8, None),
+        (0, 3) => ("dec", vec![register(R16[p])], 1, 8, None),
+        (0, 4) => ("inc", vec![r8(y)], 1, with_hl(y, 4, 12), None),
+        (0, 5) => ("dec", vec![r8(y)], 1, with_hl(y, 4, 12), None),
+        (0, 6) => (
+            "ld",
+            vec![r8(y), Immediate8(n8)],
+            2,
+            with_hl(y, 8, 12),
+            None,
+        ),
+        (0, _) => (ACCUMULATOR[usize::from(y)], vec![], 1, 4, None),
+        (1, _) if opcode == 0x76 => ("halt", vec![], 1, 4, None),
+        (1, _) => {
+            let cycles = if y == 6 || z == 6 { 8 } else { 4 };
+            ("ld", vec![r8(y), r8(z)], 1, cycles, None)
+        }
+        (2, _) => (
+            ALU[usize::from(y)],
+            vec![a, r8(z)],
+            1,
+            with_hl(z, 4, 8),
+            None,
+        ),
+        (_, 0) => match y {
+            0..=3 => ("ret", vec![condition], 1, 8, Some(20)),
+            4 => ("ldh", vec![HighAddress(n8), a], 2, 12, None),
+            5 => (
+                "add",
+                vec![register(Register::SP), SignedImmediate(n8 as i8)],
+                2,
+                16,
+                None,
+            ),
+            6 => ("ldh", vec![a, HighAddress(n8)], 2, 12, None),
+            _ => ("ld", vec![hl, SpOffset(n8 as i8)], 2, 12, None),
+        },
+        (_, 1) if q == 0 => ("pop", vec![register(R16_STACK[p])], 1, 12, None),
+        (_, 1) => match p {
+            0 => ("ret", vec![], 1, 16, None),
+            1 => ("reti", vec![], 1, 16, None),
+            2 => ("jp", vec![hl], 1, 4, None),
+            _ => ("ld", vec![register(Register::SP), hl], 1, 8, None),
+        },
+        (_, 2) => match y {
+            0..=3 => ("jp", vec![condition, Target(n16)], 3, 12, Some(16)),
+            4 => ("ldh", vec![HighC, a], 1, 8, None),
+            5 => ("ld", vec![Address(n16), a], 3, 16, None),
+            6 => ("ldh", vec![a, HighC], 1, 8, None),
+            _ => ("ld", vec![a, Address(n16)], 3, 16, None),
+        },
+        (_, 3) => match y {
+            0 => ("jp", vec![Target(n16)], 3, 16, None),
+            6 => ("di", vec![], 1, 4, None),
+            7 => ("ei", vec![], 1, 4, None),
+            // 1 is the cb prefix, the rest are unused
+            _ => return None,
+        },
+        (_, 4) if y < 4 => ("call", vec![condition, Target(n16)], 3, 12, Some(24)),
+        (_, 5) if q == 0 => ("push", vec![register(R16_STACK[p])], 1, 16, None),
+        (_, 5) if p == 0 => ("call", vec![Target(n16)], 3, 24, None),
+        (_, 6) => (ALU[usize::from(y)], vec![a, Immediate8(n8)], 2, 8, None),
+        (_, 7) => ("rst", vec![Target(u16::from(y) * 8)], 1, 16, None),
+        _ => return None,
+    };
+
+    Some(Instruction {
+        address,
+        opcode,
+        prefixed: false,
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        cycles_taken,
+    })
+}
+
+fn decode_prefixed(opcode: u8, address: u16) -> Instruction {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0b111, opcode & 0b111);
+    let (mnemonic, mut operands) = match x {
+        0 => (ROTATES[usize::from(y)], vec![]),
+        1 => ("bit", vec![Operand::Bit(y)]),
+        2 => ("res", vec![Operand::Bit(y)]),
+        _ => ("set", vec![Operand::Bit(y)]),
+    };
+    operands.push(r8(z));
+    // bit only reads [hl], the others write it back
+    let cycles = match (x, z) {
+        (1, 6) => 12,
+        (_, 6) => 16,
+        _ => 8,
+    };
+    Instruction {
+        address,
+        opcode,
+        prefixed: true,
+        mnemonic,
+        operands,
+        length: 2,
+        cycles,
+        cycles_taken: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(bytes: &[u8]) -> String {
+        decode(bytes, 0x150).unwrap().to_string()
+    }
+
+    #[test]
+    fn rgbds_syntax() {
+        assert_eq!(format(&[0x00]), "nop");
+        assert_eq!(format(&[0x3e, 0x91]), "ld a, $91");
+        assert_eq!(format(&[0xe0, 0x40]), "ldh [$ff40], a");
+        assert_eq!(format(&[0xf2]), "ldh a, [c]");
+        assert_eq!(format(&[0x2a]), "ld a, [hl+]");
+        assert_eq!(format(&[0x32]), "ld [hl-], a");
+        assert_eq!(format(&[0x08, 0x00, 0xc0]), "ld [$c000], sp");
+        assert_eq!(format(&[0x36, 0x12]), "ld [hl], $12");
+        assert_eq!(format(&[0xb8]), "cp a, b");
+        assert_eq!(format(&[0xf8, 0xfe]), "ld hl, sp - 2");
+        assert_eq!(format(&[0xe8, 0x05]), "add sp, 5");
+        assert_eq!(format(&[0xc5]), "push bc");
+        assert_eq!(format(&[0xf1]), "pop af");
+        assert_eq!(format(&[0xe9]), "jp hl");
+        assert_eq!(format(&[0xcb, 0x7e]), "bit 7, [hl]");
+        assert_eq!(format(&[0xcb, 0x37]), "swap a");
+        assert_eq!(format(&[0xd3]), "db $d3");
+    }
+
+    #[test]
+    fn targets_and_cycles() {
+        let jr = decode(&[0x20, 0xfe], 0x150).unwrap();
+        assert_eq!(jr.to_string(), "jr nz, $0150");
+        assert_eq!(jr.get_target(), Some(0x150));
+        assert_eq!((jr.cycles, jr.cycles_taken), (8, Some(12)));
+
+        let call = decode(&[0xcd, 0x00, 0x40], 0x150).unwrap();
+        assert_eq!(call.to_string(), "call $4000");
+        assert_eq!((call.length, call.cycles), (3, 24));
+
+        let rst = decode(&[0xff], 0x150).unwrap();
+        assert_eq!(rst.to_string(), "rst $0038");
+        assert_eq!(rst.get_target(), Some(0x38));
+
+        let set = decode(&[0xcb, 0xc6], 0).unwrap();
+        assert!(set.prefixed);
+        assert_eq!((set.opcode, set.cycles), (0xc6, 16));
+    }
+
+    #[test]
+    fn every_opcode() {
+        let invalid = [
+            0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+        ];
+        for opcode in 0..=0xff {
+            let x = decode(&[opcode, 0, 0], 0).unwrap();
+            assert_eq!(x.is_valid(), !invalid.contains(&opcode), "{:02x}", opcode);
+            assert!(x.cycles > 0 || !x.is_valid());
+            let prefixed = decode(&[0xcb, opcode], 0).unwrap();
+            assert!(prefixed.is_valid() && prefixed.length == 2);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let x = decode(&[0xc3, 0x50], 0x7ffe).unwrap();
+        assert_eq!(x.to_string(), "db $c3");
+        assert!(decode(&[], 0).is_none());
+
+        let all = disassemble(&[0x00, 0x3e, 0x01, 0xcb], 0x100);
+        let text: Vec<String> = all.iter().map(|x| x.to_string()).collect();
+        assert_eq!(text, ["nop", "ld a, $01", "db $cb"]);
+        assert_eq!(all[1].address, 0x101);
+    }
+}
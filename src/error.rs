@@ -0,0 +1,58 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The cartridge type byte in the rom header
+    UnsupportedMapper(u8),
+    // Length of a rom too short to hold its header and first two banks
+    TruncatedRom(usize),
+    BadBootRomSize(usize),
+    BadSaveSize { expected: usize, actual: usize },
+    BadSaveState(String),
+    BadMovie(String),
+    // The other end of a TCP link cable broke the protocol
+    BadLinkMessage(String),
+    // A line of a reference trace log that couldn't be read
+    BadTraceLog(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::UnsupportedMapper(x) => write!(f, "unsupported cartridge type {:#04x}", x),
+            Error::TruncatedRom(x) => write!(f, "rom is truncated, only {} bytes long", x),
+            Error::BadBootRomSize(x) => write!(f, "boot rom should be 256 bytes, not {}", x),
+            Error::BadSaveSize { expected, actual } => write!(
+                f,
+                "save is {} bytes, but the cartridge expects {} bytes",
+                actual, expected
+            ),
+            Error::BadSaveState(x) => write!(f, "bad save state: {}", x),
+            Error::BadMovie(x) => write!(f, "bad movie: {}", x),
+            Error::BadLinkMessage(x) => write!(f, "bad link cable message: {}", x),
+            Error::BadTraceLog(x) => write!(f, "bad trace log: {}", x),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -4,7 +4,13 @@ mod renderer;
 use self::mode_updater::ModeUpdater;
 use self::renderer::Renderer;
 use super::App;
+use crate::error::Result;
 use crate::memory::VideoMemory;
+use crate::save_state::{StateReader, StateWriter};
+
+pub const FRAME_CYCLES: u64 = 70224;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
 pub struct LCD {
     update_time: u64,
@@ -14,6 +20,12 @@ pub struct LCD {
     vblank_flag: bool,
     mode_updater: ModeUpdater,
     renderer: Renderer,
+    // Lines are drawn into frame_buffer, in the Renderer::draw_line
+    // format, which is copied to completed_pixels when vblank starts.
+    // completed_frame has the same frame as shades for the app
+    frame_buffer: Vec<u8>,
+    completed_pixels: Vec<u8>,
+    completed_frame: Vec<u8>,
 }
 
 impl LCD {
@@ -26,6 +38,9 @@ impl LCD {
             vblank_flag: false,
             mode_updater: Default::default(),
             renderer: Renderer::new(),
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            completed_pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            completed_frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
@@ -33,10 +48,45 @@ impl LCD {
         self.vblank_flag
     }
 
+    // The last full frame, as shades from 0 black to 3 white
+    pub fn get_completed_frame(&self) -> &[u8] {
+        &self.completed_frame
+    }
+
+    // The last full frame with the palette of each pixel, for Palettes::to_rgba
+    pub fn get_completed_pixels(&self) -> &[u8] {
+        &self.completed_pixels
+    }
+
+    // Ends a frame without the lcd running, for when the clock is stopped
+    pub fn set_vblank(&mut self) {
+        self.vblank_flag = true;
+    }
+
     pub fn reset_vblank(&mut self) {
         self.vblank_flag = false;
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.update_time);
+        writer.write_bool(self.enabled);
+        writer.write_u64(self.frame);
+        writer.write_u8(self.next_ly);
+        writer.write_bool(self.vblank_flag);
+        self.mode_updater.save_state(writer);
+        self.renderer.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.update_time = reader.read_u64()?;
+        self.enabled = reader.read_bool()?;
+        self.frame = reader.read_u64()?;
+        self.next_ly = reader.read_u8()?;
+        self.vblank_flag = reader.read_bool()?;
+        self.mode_updater.load_state(reader)?;
+        self.renderer.load_state(reader)
+    }
+
     pub fn tick<T: App>(&mut self, vram: &mut VideoMemory, cycles: u64, app: &mut T) {
         let enabled = vram.check_enabled();
         if enabled && !self.enabled {
@@ -58,8 +108,23 @@ impl LCD {
             }
 
             if ly < 144 {
-                self.renderer.draw_line(vram, app);
+                let start = usize::from(ly) * SCREEN_WIDTH;
+                let line = &mut self.frame_buffer[start..start + SCREEN_WIDTH];
+                self.renderer.draw_line(vram, line);
+                let mut shades = [0; SCREEN_WIDTH];
+                for (x, pixel) in shades.iter_mut().zip(line.iter()) {
+                    *x = pixel_to_shade(*pixel);
+                }
+                app.draw_line(&shades, ly);
             } else if ly == 144 {
+                self.completed_pixels.copy_from_slice(&self.frame_buffer);
+                for (x, pixel) in self
+                    .completed_frame
+                    .iter_mut()
+                    .zip(self.frame_buffer.iter())
+                {
+                    *x = pixel_to_shade(*pixel);
+                }
                 self.vblank_flag = true;
             }
 
@@ -85,5 +150,10 @@ impl LCD {
     }
 }
 
+// Apps get shades from 0 black to 3 white
+fn pixel_to_shade(pixel: u8) -> u8 {
+    3 - (pixel & 0b11)
+}
+
 #[cfg(test)]
 mod test;
@@ -1,4 +1,6 @@
+use crate::error::Result;
 use crate::memory::VideoMemory;
+use crate::save_state::{StateReader, StateWriter};
 
 #[derive(Default)]
 pub struct ModeUpdater {
@@ -18,6 +20,17 @@ impl ModeUpdater {
         }
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.state);
+        writer.write_u64(self.update_time);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.state = reader.read_u8()?;
+        self.update_time = reader.read_u64()?;
+        Ok(())
+    }
+
     fn update_mode(&mut self, vram: &mut VideoMemory) {
         // Ad-hoc state machine
         match self.state {
@@ -1,7 +1,8 @@
 use super::pixel_iterator::PixelIterator;
 use crate::bit_ops::BitGetSet;
+use crate::error::Result;
 use crate::memory::{locations::*, VideoMemory};
-use crate::App;
+use crate::save_state::{StateReader, StateWriter};
 
 pub struct Renderer {
     background_screen_buffer: [u8; 256 * 256],
@@ -20,6 +21,31 @@ impl Renderer {
         }
     }
 
+    // The background caches are part of the state, as they are
+    // only redrawn at the start of each frame
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.background_screen_buffer);
+        for x in self.background_tile_map_cache.iter() {
+            writer.write_u16(*x);
+        }
+        for x in self.background_tile_write_cache.iter() {
+            writer.write_u64(*x);
+        }
+        writer.write_u64(self.frame_count);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.read_bytes_into(&mut self.background_screen_buffer)?;
+        for x in self.background_tile_map_cache.iter_mut() {
+            *x = reader.read_u16()?;
+        }
+        for x in self.background_tile_write_cache.iter_mut() {
+            *x = reader.read_u64()?;
+        }
+        self.frame_count = reader.read_u64()?;
+        Ok(())
+    }
+
     pub fn draw_background(&mut self, vram: &mut VideoMemory) {
         self.frame_count += 1;
 
@@ -75,7 +101,10 @@ impl Renderer {
         }
     }
 
-    pub fn draw_line<T: App>(&self, vram: &VideoMemory, app: &mut T) {
+    // Draws the current line into output. Each pixel is its palette
+    // (0 for BGP, 1 for OBP0, 2 for OBP1) * 4 plus its shade from
+    // that palette, from 0 lightest to 3 darkest
+    pub fn draw_line(&self, vram: &VideoMemory, output: &mut [u8]) {
         let mut line = [0; 160];
 
         self.draw_bg_line(vram, &mut line);
@@ -83,15 +112,12 @@ impl Renderer {
         draw_sprites(vram, &mut line);
 
         let bgp = vram.regs.bgp;
-        let obp1 = vram.get_obp(0);
-        let obp2 = vram.get_obp(2);
-        let palette = create_combined_palette(bgp, obp1, obp2);
-        for x in line.iter_mut() {
-            *x = palette[*x as usize];
+        let obp0 = vram.get_obp(0);
+        let obp1 = vram.get_obp(1);
+        let palette = create_combined_palette(bgp, obp0, obp1);
+        for (x, y) in output.iter_mut().zip(line.iter()) {
+            *x = palette[*y as usize];
         }
-
-        let ly = vram.regs.ly;
-        app.draw_line(&line, ly);
     }
 
     fn draw_bg_line(&self, vram: &VideoMemory, line: &mut [u8; 160]) {
@@ -104,21 +130,14 @@ impl Renderer {
     }
 }
 
-fn create_combined_palette(bgp: u8, obp1: u8, obp2: u8) -> [u8; 12] {
-    [
-        3 - (bgp & 0b11),
-        3 - ((bgp >> 2) & 0b11),
-        3 - ((bgp >> 4) & 0b11),
-        3 - ((bgp >> 6) & 0b11),
-        3 - (obp1 & 0b11),
-        3 - ((obp1 >> 2) & 0b11),
-        3 - ((obp1 >> 4) & 0b11),
-        3 - ((obp1 >> 6) & 0b11),
-        3 - (obp2 & 0b11),
-        3 - ((obp2 >> 2) & 0b11),
-        3 - ((obp2 >> 4) & 0b11),
-        3 - ((obp2 >> 6) & 0b11),
-    ]
+fn create_combined_palette(bgp: u8, obp0: u8, obp1: u8) -> [u8; 12] {
+    let mut palette = [0; 12];
+    for (i, register) in [bgp, obp0, obp1].iter().enumerate() {
+        for colour in 0..4 {
+            palette[i * 4 + colour] = (i * 4) as u8 + ((register >> (colour * 2)) & 0b11);
+        }
+    }
+    palette
 }
 
 fn get_window_tile_index(x: u16, y: u16, vram: &VideoMemory) -> u16 {
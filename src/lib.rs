@@ -2,26 +2,73 @@
 extern crate lazy_static;
 #[macro_use]
 mod warn_macros;
+mod apu;
 mod bit_ops;
 mod cartridge;
 mod cpu;
+mod debugger;
+pub mod disasm;
+mod error;
 mod lcd;
+mod link_cable;
 mod memory;
+mod movie;
 mod opcode_table;
+mod palette;
+mod printer;
 mod registers;
+mod rewind;
+mod save_state;
+mod tcp_link;
+mod terminal;
 mod timer;
+mod trace_diff;
+mod tracer;
 use crate::cartridge::Cartridge;
+pub use crate::cartridge::RtcClock;
+pub use crate::cartridge::{CartridgeHeader, HeaderMismatch};
 use crate::cpu::Cpu;
-use crate::lcd::LCD;
+pub use crate::debugger::{
+    Access, Break, Comparison, Condition, Debugger, GdbStub, Register, StopReason,
+};
+pub use crate::error::{Error, Result};
+use crate::lcd::{FRAME_CYCLES, LCD};
+pub use crate::lcd::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use crate::link_cable::LinkCable;
 pub use crate::memory::JoyPad;
 use crate::memory::Memory;
-use crate::registers::Registers;
+use crate::movie::MovieMode;
+pub use crate::movie::{Movie, MovieStart};
+pub use crate::palette::{Palette, Palettes, Rgba};
+pub use crate::printer::{Printer, PRINTER_WIDTH};
+pub use crate::registers::Registers;
+use crate::rewind::RewindBuffer;
+use crate::save_state::{bad_state, StateReader, StateWriter};
+pub use crate::tcp_link::TcpLink;
+pub use crate::terminal::{ColourMode, TerminalApp};
 use crate::timer::Timer;
+pub use crate::trace_diff::{
+    Divergence, EmulatorSource, LogSource, Mismatch, TraceDiff, TraceSource, TraceStep,
+};
+pub use crate::tracer::{TraceFormat, Tracer};
 use std::fs;
+use std::io;
+
+const BOOT_ROM_SIZE: usize = 0x100;
 
 pub trait App {
     fn draw_line(&mut self, line_buffer: &[u8], line_index: u8);
     fn update(&mut self, joypad: &mut JoyPad) -> Command;
+
+    // The rate to generate audio at, checked every frame, from 1 Hz to
+    // the cpu's 4194304 Hz clock. Apps that return None get no audio
+    fn audio_sample_rate(&self) -> Option<u32> {
+        None
+    }
+
+    // Called every frame with interleaved left and right
+    // samples from -1.0 to 1.0, if audio_sample_rate is set
+    fn play_audio(&mut self, _samples: &[f32]) {}
 }
 
 pub enum Command {
@@ -29,20 +76,60 @@ pub enum Command {
     Stop,
 }
 
+// Used to run frames without an app, the joypad is
+// set through Emulator::get_joypad instead
+struct Headless;
+
+impl App for Headless {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        Command::Continue
+    }
+}
+
 pub struct Emulator {
     cpu: Cpu,
+    header: CartridgeHeader,
     lcd: LCD,
     memory: Memory,
     timer: Timer,
-    tracing: bool,
+    tracer: Option<Tracer>,
+    rewind: Option<RewindBuffer>,
+    movie: Option<MovieMode>,
+    movie_desync: Option<u64>,
+    printer: Option<Printer>,
+    // Cycles the cpu has been in STOP mode this frame
+    stopped_cycles: u64,
+    // Every cycle spent in STOP mode, which the cpu doesn't count
+    total_stopped_cycles: u64,
+    frame_count: u64,
+    palettes: Palettes,
 }
 
 impl Emulator {
-    pub fn new(boot_rom_path: Option<&str>, cartridge_rom_path: &str) -> Emulator {
-        let cpu = Cpu::new();
-        let cartridge = Cartridge::from_file(cartridge_rom_path);
+    pub fn new(boot_rom_path: Option<&str>, cartridge_rom_path: &str) -> Result<Emulator> {
+        let rom = fs::read(cartridge_rom_path)?;
         let boot_rom = match boot_rom_path {
-            Some(x) => fs::read(x).unwrap(),
+            Some(x) => Some(fs::read(x)?),
+            None => None,
+        };
+        Emulator::from_bytes(rom, boot_rom)
+    }
+
+    // Create an emulator from a rom already in memory. Without a
+    // boot rom, a stub that just jumps to the cartridge is used
+    pub fn from_bytes(rom: Vec<u8>, boot_rom: Option<Vec<u8>>) -> Result<Emulator> {
+        let cpu = Cpu::new();
+        let header = CartridgeHeader::from_rom(&rom)?;
+        let cartridge = Cartridge::from_rom(&rom, &header)?;
+        let boot_rom = match boot_rom {
+            Some(x) => {
+                if x.len() != BOOT_ROM_SIZE {
+                    return Err(Error::BadBootRomSize(x.len()));
+                }
+                x
+            }
             None => {
                 let x = include_bytes!("../resources/dummy_boot_rom.gb");
                 x.to_vec()
@@ -51,36 +138,128 @@ impl Emulator {
         let memory = Memory::new(boot_rom, cartridge);
         let lcd = LCD::new();
 
-        Emulator {
+        Ok(Emulator {
             cpu,
+            header,
             lcd,
             memory,
             timer: Timer::new(),
-            tracing: false,
-        }
+            tracer: None,
+            rewind: None,
+            movie: None,
+            movie_desync: None,
+            printer: None,
+            stopped_cycles: 0,
+            total_stopped_cycles: 0,
+            frame_count: 0,
+            palettes: Default::default(),
+        })
     }
 
     pub fn run<T: App>(&mut self, app: &mut T) {
+        self.update_sample_rate(app);
         loop {
             while !self.lcd.is_vblank() {
                 self.tick(app);
             }
-            self.lcd.reset_vblank();
-            let joypad = self.memory.get_joypad();
-            match app.update(joypad) {
-                Command::Stop => break,
-                Command::Continue => (),
+            if let Command::Stop = self.finish_frame(app) {
+                break;
             }
         }
     }
 
+    // Run until the next frame is complete
+    pub fn run_frame(&mut self) {
+        self.run_frame_until(u64::MAX);
+    }
+
+    // Run until the next frame is complete, or until the cycle count
+    // reaches max_cycles. Returns true if the frame was completed
+    pub fn run_frame_until(&mut self, max_cycles: u64) -> bool {
+        let mut app = Headless;
+        while !self.lcd.is_vblank() {
+            if self.cpu.get_cycles() >= max_cycles {
+                return false;
+            }
+            self.tick(&mut app);
+        }
+        self.finish_frame(&mut app);
+        true
+    }
+
+    // Cycles run since power on, at 4194304 Hz
+    pub fn get_cycles(&self) -> u64 {
+        self.cpu.get_cycles()
+    }
+
+    // The last complete frame, SCREEN_WIDTH by SCREEN_HEIGHT,
+    // as shades from 0 black to 3 white
+    pub fn get_frame_buffer(&self) -> &[u8] {
+        self.lcd.get_completed_frame()
+    }
+
+    // The last complete frame as RGBA, coloured with the palettes
+    pub fn get_frame_rgba(&self) -> Vec<u8> {
+        self.palettes.to_rgba(self.lcd.get_completed_pixels())
+    }
+
+    // The colours used by get_frame_rgba, greyscale by default
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = palettes;
+    }
+
+    pub fn get_palettes(&self) -> &Palettes {
+        &self.palettes
+    }
+
+    // Frames completed since the emulator was created
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn get_joypad(&mut self) -> &mut JoyPad {
+        self.memory.get_joypad()
+    }
+
+    // Everything done between frames, once vblank is reached.
+    // Returns the app's command
+    fn finish_frame<T: App>(&mut self, app: &mut T) -> Command {
+        self.frame_count += 1;
+        self.lcd.reset_vblank();
+        self.send_audio(app);
+        self.capture_rewind_state();
+        self.check_movie_hash();
+        let command = app.update(self.memory.get_joypad());
+        self.update_movie_joypad();
+        command
+    }
+
     pub fn tick<T: App>(&mut self, app: &mut T) {
+        if self.memory.tick_joypad() {
+            self.cpu.wake_from_stop();
+        }
+        if self.cpu.is_stopped() {
+            self.tick_stopped();
+            return;
+        }
+
         {
             let vram = self.memory.get_video_memory();
             self.lcd.tick(vram, self.cpu.get_cycles(), app);
         }
-        self.cpu.tick(&mut self.memory, self.tracing);
+        if let Some(tracer) = &mut self.tracer {
+            if !self.cpu.is_halted() {
+                let cycles = self.cpu.get_cycles();
+                tracer.trace(self.cpu.get_registers(), &self.memory, cycles);
+            }
+        }
+        self.cpu.tick(&mut self.memory);
         self.timer.tick(&mut self.memory, self.cpu.get_cycles());
+        self.memory.tick_serial(self.cpu.get_cycles());
+        self.tick_printer();
+        let elapsed = self.get_elapsed_cycles();
+        self.memory.get_cartridge_mut().tick(elapsed);
+        self.memory.get_apu_mut().tick(self.cpu.get_cycles());
         self.cpu.check_interrupts(&mut self.memory);
     }
 
@@ -88,31 +267,376 @@ impl Emulator {
         self.memory.is_boot_rom_enabled()
     }
 
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
     pub fn get_registers(&self) -> &Registers {
         self.cpu.get_registers()
     }
 
+    // Print the disassembly of each instruction to stdout
     pub fn set_tracing(&mut self, state: bool) {
-        self.tracing = state;
+        self.tracer = if state {
+            let mut tracer = Tracer::new(io::stdout());
+            tracer.set_format(TraceFormat::Disassembly);
+            Some(tracer)
+        } else {
+            None
+        };
     }
 
+    // Trace instructions before they run, or stop tracing with None.
+    // Returns the previous tracer
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // The bytes sent over the serial port, up to the last 64KB.
+    // They aren't saved in save states
     pub fn get_serial_data(&self) -> &[u8] {
         self.memory.get_serial_data()
     }
 
+    // Plug a Game Boy Printer into the serial port
+    pub fn attach_printer(&mut self) {
+        self.printer = Some(Printer::new());
+        self.memory.set_serial_linked(true);
+    }
+
+    // A link cable plugged in instead is left alone
+    pub fn detach_printer(&mut self) -> Option<Printer> {
+        let printer = self.printer.take();
+        if printer.is_some() {
+            self.memory.set_serial_linked(false);
+        }
+        printer
+    }
+
+    pub fn get_printer(&self) -> Option<&Printer> {
+        self.printer.as_ref()
+    }
+
+    pub fn get_printer_mut(&mut self) -> Option<&mut Printer> {
+        self.printer.as_mut()
+    }
+
+    // Like get_cycles, but still counting in STOP mode. Used to keep
+    // time with things outside the emulator, which don't stop
+    fn get_elapsed_cycles(&self) -> u64 {
+        self.cpu.get_cycles() + self.total_stopped_cycles
+    }
+
+    // Only the cartridge's real time clock runs in STOP mode, but frames
+    // still end at the usual rate so the app can press a button
+    fn tick_stopped(&mut self) {
+        self.stopped_cycles += 4;
+        self.total_stopped_cycles += 4;
+        let elapsed = self.get_elapsed_cycles();
+        self.memory.get_cartridge_mut().tick(elapsed);
+        if self.stopped_cycles >= FRAME_CYCLES {
+            self.stopped_cycles -= FRAME_CYCLES;
+            self.lcd.set_vblank();
+        }
+    }
+
+    // The printer answers each byte as soon as the game has shifted it out
+    fn tick_printer(&mut self) {
+        if let Some(printer) = &mut self.printer {
+            if self.memory.is_serial_transfer_due() {
+                let received = printer.exchange(self.memory.get_serial_byte());
+                self.memory.complete_serial_transfer(received);
+            }
+        }
+    }
+
+    // Choose what drives the cartridge's real time clock, if it has one.
+    // Set this before load_cartridge_ram so a WallClock rtc catches up
+    // on the time since the save was written
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.memory.get_cartridge_mut().set_rtc_clock(clock);
+    }
+
+    // True while the cartridge's rumble motor is switched on
+    pub fn is_rumbling(&self) -> bool {
+        self.memory.get_cartridge().is_rumbling()
+    }
+
     pub fn read_memory(&self, index: u16) -> u8 {
         self.memory.get_u8(index)
     }
 
-    pub fn save_cartridge_ram(&self, path: &str) {
+    // Snapshot the whole machine. The state can only be loaded
+    // back into an emulator running the same rom. An attached printer's
+    // paper isn't included, only the packet it is receiving
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u16(self.header.global_checksum);
+        self.cpu.save_state(&mut writer);
+        self.memory.save_state(&mut writer);
+        self.lcd.save_state(&mut writer);
+        self.timer.save_state(&mut writer);
+        writer.write_u64(self.frame_count);
+        writer.write_u64(self.stopped_cycles);
+        writer.write_u64(self.total_stopped_cycles);
+        writer.write_bool(self.printer.is_some());
+        if let Some(printer) = &self.printer {
+            printer.save_state(&mut writer);
+        }
+        writer.into_bytes()
+    }
+
+    // On error the emulator is left as it was before the call
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let backup = self.save_state();
+        let result = self.read_state(data);
+        if result.is_err() {
+            self.read_state(&backup)
+                .expect("failed to restore state after a bad load");
+        }
+        result
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(data)?;
+        if reader.read_u16()? != self.header.global_checksum {
+            return Err(bad_state("save state is for a different rom"));
+        }
+        self.cpu.load_state(&mut reader)?;
+        self.memory.load_state(&mut reader)?;
+        self.lcd.load_state(&mut reader)?;
+        self.timer.load_state(&mut reader)?;
+        self.frame_count = reader.read_u64()?;
+        self.stopped_cycles = reader.read_u64()?;
+        self.total_stopped_cycles = reader.read_u64()?;
+        // Loading doesn't attach or detach the printer
+        match (reader.read_bool()?, &mut self.printer) {
+            (true, Some(printer)) => printer.load_state(&mut reader)?,
+            (true, None) => Printer::new().load_state(&mut reader)?,
+            (false, Some(printer)) => printer.reset(),
+            (false, None) => (),
+        }
+        reader.finish()
+    }
+
+    // Returns false if the app doesn't want audio
+    fn update_sample_rate<T: App>(&mut self, app: &T) -> bool {
+        let sample_rate = match app.audio_sample_rate() {
+            Some(x) => x,
+            None => return false,
+        };
+        let apu = self.memory.get_apu_mut();
+        if apu.get_sample_rate() != Some(sample_rate) {
+            apu.set_sample_rate(Some(sample_rate));
+        }
+        true
+    }
+
+    fn send_audio<T: App>(&mut self, app: &mut T) {
+        if self.update_sample_rate(app) {
+            app.play_audio(&self.memory.get_apu_mut().take_samples());
+        }
+    }
+
+    // Start generating stereo samples at the given rate, or stop with None.
+    // The rate has the same limits as App::audio_sample_rate. Apps that set
+    // App::audio_sample_rate don't need to call this
+    pub fn set_audio_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.memory.get_apu_mut().set_sample_rate(sample_rate);
+    }
+
+    // Interleaved left and right samples generated since the last call
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.memory.get_apu_mut().take_samples()
+    }
+
+    // Record audio to a 16 bit stereo WAV file, at the current sample
+    // rate or 44100 Hz if there isn't one yet. With separate_channels,
+    // each channel is also recorded to a mono file, with _ch1 - _ch4
+    // added to the file name. The files aren't complete until
+    // stop_audio_recording is called or the emulator is dropped.
+    // Recording stops if writing fails or a file reaches the 4GB
+    // WAV limit, and stop_audio_recording returns the error
+    pub fn start_audio_recording(&mut self, path: &str, separate_channels: bool) -> Result<()> {
+        self.memory
+            .get_apu_mut()
+            .start_recording(path, separate_channels)
+    }
+
+    pub fn stop_audio_recording(&mut self) -> Result<()> {
+        self.memory.get_apu_mut().stop_recording()
+    }
+
+    pub fn is_audio_recording(&self) -> bool {
+        self.memory.get_apu().is_recording()
+    }
+
+    // Capture a state every `interval` frames while run is going, for
+    // rewinding. Older states are dropped to keep the buffer within
+    // memory_budget bytes
+    pub fn enable_rewind(&mut self, interval: u64, memory_budget: usize) {
+        self.rewind = Some(RewindBuffer::new(interval, memory_budget));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    // Number of frames that can currently be rewound
+    pub fn get_rewind_frames(&self) -> u64 {
+        self.rewind
+            .as_ref()
+            .map_or(0, RewindBuffer::get_available_frames)
+    }
+
+    // Go back to the newest captured state at least `frames` frames ago, or
+    // the oldest one if there isn't one that old. Returns how many frames
+    // were actually rewound. Nothing is rewound while a movie is recording,
+    // as the movie would still have the inputs of the rewound frames
+    pub fn rewind(&mut self, frames: u64) -> u64 {
+        if matches!(self.movie, Some(MovieMode::Recording(_))) {
+            return 0;
+        }
+        let rewound = self.rewind.as_mut().and_then(|x| x.rewind(frames));
+        match rewound {
+            Some((state, frames)) => {
+                self.load_state(&state)
+                    .expect("failed to load rewind state");
+                frames
+            }
+            None => 0,
+        }
+    }
+
+    fn capture_rewind_state(&mut self) {
+        let capture = match &mut self.rewind {
+            Some(x) => x.end_frame(),
+            None => false,
+        };
+        if capture {
+            let state = self.save_state();
+            self.rewind.as_mut().unwrap().push(state);
+        }
+    }
+
+    // Record the joypad state of every frame run from now on. A movie
+    // started before the first tick starts from power on, otherwise
+    // it starts from a save state
+    pub fn start_movie_recording(&mut self) {
+        let start = if self.cpu.get_cycles() == 0 {
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(self.save_state())
+        };
+        let movie = Movie::new(self.header.global_checksum, start);
+        self.movie = Some(MovieMode::Recording(movie));
+    }
+
+    pub fn stop_movie_recording(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieMode::Recording(movie)) => Some(movie),
+            x => {
+                self.movie = x;
+                None
+            }
+        }
+    }
+
+    // Replay a movie, replacing the app's input until it runs out of
+    // frames. Power on movies need an emulator that hasn't run yet
+    pub fn play_movie(&mut self, movie: Movie) -> Result<()> {
+        if movie.get_global_checksum() != self.header.global_checksum {
+            return Err(Error::BadMovie("movie is for a different rom".to_string()));
+        }
+        match movie.get_start() {
+            MovieStart::PowerOn => {
+                if self.cpu.get_cycles() != 0 {
+                    return Err(Error::BadMovie(
+                        "power on movies must be played from power on".to_string(),
+                    ));
+                }
+            }
+            MovieStart::SaveState(state) => self.load_state(state)?,
+        }
+        self.movie = Some(MovieMode::Playing { movie, frame: 0 });
+        self.movie_desync = None;
+        Ok(())
+    }
+
+    pub fn is_movie_playing(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Playing { .. }))
+    }
+
+    // The first frame where playback no longer matched the recording
+    pub fn get_movie_desync(&self) -> Option<u64> {
+        self.movie_desync
+    }
+
+    fn check_movie_hash(&mut self) {
+        if self.movie.is_none() {
+            return;
+        }
+        let hash = self.hash_memory();
+        let desync = self.movie.as_mut().unwrap().check_hash(hash);
+        if self.movie_desync.is_none() {
+            self.movie_desync = desync;
+        }
+    }
+
+    fn update_movie_joypad(&mut self) {
+        if let Some(movie) = &mut self.movie {
+            if !movie.update_joypad(self.memory.get_joypad()) {
+                self.movie = None;
+            }
+        }
+    }
+
+    // Registers, video ram, work ram, oam and high ram. Io registers and
+    // cartridge ram are left out, as they can hold host dependent values
+    fn hash_memory(&self) -> u64 {
+        let regs = self.cpu.get_registers();
+        let registers = [
+            regs.a,
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.f,
+            regs.h,
+            regs.l,
+            (regs.sp >> 8) as u8,
+            regs.sp as u8,
+            (regs.pc >> 8) as u8,
+            regs.pc as u8,
+        ];
+        let ranges = [
+            0x8000..0xa000,
+            0xc000..0xe000,
+            0xfe00..0xfea0,
+            0xff80..0xffff,
+        ];
+        let memory = ranges
+            .iter()
+            .cloned()
+            .flatten()
+            .map(|i| self.memory.get_u8(i));
+        movie::hash_bytes(registers.iter().cloned().chain(memory))
+    }
+
+    pub fn save_cartridge_ram(&self, path: &str) -> Result<()> {
         let cartridge = self.memory.get_cartridge();
         let cart_ram = cartridge.get_ram();
-        fs::write(path, &cart_ram).unwrap();
+        fs::write(path, &cart_ram)?;
+        Ok(())
     }
 
-    pub fn load_cartridge_ram(&mut self, path: &str) {
+    pub fn load_cartridge_ram(&mut self, path: &str) -> Result<()> {
         let cartridge = self.memory.get_cartridge_mut();
-        let data = fs::read(path).unwrap();
-        cartridge.set_ram(&data);
+        let data = fs::read(path)?;
+        cartridge.set_ram(&data)
     }
 }
@@ -0,0 +1,92 @@
+use crate::{App, Command, Emulator};
+
+// Two Game Boys connected by a link cable. The side using the internal
+// clock drives each transfer, and once all 8 bits have been shifted the
+// SB registers are swapped. If the other side hasn't started a transfer
+// on the external clock, the master shifts in 0xff instead
+pub struct LinkCable {
+    first: Emulator,
+    second: Emulator,
+}
+
+impl LinkCable {
+    pub fn new(first: Emulator, second: Emulator) -> LinkCable {
+        let mut cable = LinkCable { first, second };
+        cable.first.memory.set_serial_linked(true);
+        cable.second.memory.set_serial_linked(true);
+        cable
+    }
+
+    // Unplug the cable, getting both emulators back
+    pub fn disconnect(mut self) -> (Emulator, Emulator) {
+        self.first.memory.set_serial_linked(false);
+        self.second.memory.set_serial_linked(false);
+        (self.first, self.second)
+    }
+
+    pub fn get_first(&self) -> &Emulator {
+        &self.first
+    }
+
+    pub fn get_first_mut(&mut self) -> &mut Emulator {
+        &mut self.first
+    }
+
+    pub fn get_second(&self) -> &Emulator {
+        &self.second
+    }
+
+    pub fn get_second_mut(&mut self) -> &mut Emulator {
+        &mut self.second
+    }
+
+    // Run both emulators in lockstep until either app returns Stop.
+    // The emulator that is behind is always ticked next, so neither
+    // gets more than an instruction ahead of the other
+    pub fn run<A: App, B: App>(&mut self, first_app: &mut A, second_app: &mut B) {
+        self.first.update_sample_rate(first_app);
+        self.second.update_sample_rate(second_app);
+        loop {
+            if let Command::Stop = self.tick(first_app, second_app) {
+                break;
+            }
+        }
+    }
+
+    // Tick whichever emulator is behind, then exchange any
+    // completed transfers. Frames are finished as in Emulator::run
+    pub fn tick<A: App, B: App>(&mut self, first_app: &mut A, second_app: &mut B) -> Command {
+        // Time in STOP mode counts, so a stopped side doesn't hold the other back
+        let command = if self.first.get_elapsed_cycles() <= self.second.get_elapsed_cycles() {
+            step(&mut self.first, first_app)
+        } else {
+            step(&mut self.second, second_app)
+        };
+        transfer(&mut self.first, &mut self.second);
+        transfer(&mut self.second, &mut self.first);
+        command
+    }
+}
+
+fn step<T: App>(emulator: &mut Emulator, app: &mut T) -> Command {
+    emulator.tick(app);
+    if emulator.lcd.is_vblank() {
+        emulator.finish_frame(app)
+    } else {
+        Command::Continue
+    }
+}
+
+// Complete the master's transfer if its clock has shifted all 8 bits
+fn transfer(master: &mut Emulator, slave: &mut Emulator) {
+    if !master.memory.is_serial_transfer_due() {
+        return;
+    }
+    let sent = master.memory.get_serial_byte();
+    let received = if slave.memory.is_serial_waiting_for_clock() {
+        slave.memory.complete_serial_transfer(sent)
+    } else {
+        0xff
+    };
+    master.memory.complete_serial_transfer(received);
+}
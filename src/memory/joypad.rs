@@ -1,11 +1,15 @@
 use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
 
 pub struct JoyPad {
     buttons: u8,
     directions: u8,
-    // 0x20 for for buttons, 0x10 for directions
-    // 0x30 and 0x00 ???
+    // 0x10 for buttons, 0x20 for directions, 0x00 for
+    // both and 0x30 for neither
     selection: u8,
+    // Set when a selected line goes from high to low
+    interrupt: bool,
 }
 
 fn set_bit(x: u8, bit: u8, state: bool) -> u8 {
@@ -18,35 +22,82 @@ fn set_bit(x: u8, bit: u8, state: bool) -> u8 {
 
 impl JoyPad {
     pub fn set_a(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.buttons = set_bit(self.buttons, 0, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_b(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.buttons = set_bit(self.buttons, 1, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_select(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.buttons = set_bit(self.buttons, 2, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_start(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.buttons = set_bit(self.buttons, 3, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_right(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.directions = set_bit(self.directions, 0, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_left(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.directions = set_bit(self.directions, 1, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_up(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.directions = set_bit(self.directions, 2, !state);
+        self.check_lines(lines);
     }
 
     pub fn set_down(&mut self, state: bool) {
+        let lines = self.get_lines();
         self.directions = set_bit(self.directions, 3, !state);
+        self.check_lines(lines);
+    }
+
+    // Every input as one byte, with a set bit for each pressed input.
+    // Bits 0 - 3 are A, B, Select, Start and 4 - 7 are Right, Left, Up, Down
+    pub fn get_state(&self) -> u8 {
+        !((self.directions << 4) | (self.buttons & 0x0f))
+    }
+
+    pub fn set_state(&mut self, state: u8) {
+        let lines = self.get_lines();
+        self.buttons = !state & 0x0f;
+        self.directions = !state >> 4;
+        self.check_lines(lines);
+    }
+
+    // P10 - P13, low for a pressed input on a selected line
+    fn get_lines(&self) -> u8 {
+        self.get_u8() & 0x0f
+    }
+
+    fn check_lines(&mut self, old_lines: u8) {
+        if old_lines & !self.get_lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+
+    // True if a line has gone low since the last call
+    pub(super) fn take_interrupt(&mut self) -> bool {
+        let interrupt = self.interrupt;
+        self.interrupt = false;
+        interrupt
     }
 
     pub(super) fn new() -> JoyPad {
@@ -54,11 +105,29 @@ impl JoyPad {
             buttons: 0x0f,
             directions: 0x0f,
             selection: 0x30,
+            interrupt: false,
         }
     }
 
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.buttons);
+        writer.write_u8(self.directions);
+        writer.write_u8(self.selection);
+        writer.write_bool(self.interrupt);
+    }
+
+    pub(super) fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.buttons = reader.read_u8()?;
+        self.directions = reader.read_u8()?;
+        self.selection = reader.read_u8()?;
+        self.interrupt = reader.read_bool()?;
+        Ok(())
+    }
+
     pub(super) fn set_u8(&mut self, value: u8) {
+        let lines = self.get_lines();
         self.selection = value & 0b0011_0000;
+        self.check_lines(lines);
     }
 
     pub(super) fn get_u8(&self) -> u8 {
@@ -69,6 +138,9 @@ impl JoyPad {
             0b1100_1111 & self.buttons
         } else if p15 && !p14 {
             0b1100_1111 & self.directions
+        } else if !p15 && !p14 {
+            // Both are selected, so a press on either pulls a line low
+            0b1100_0000 | (self.buttons & self.directions)
         } else {
             0b1100_1111
         }
@@ -1,4 +1,17 @@
+pub const TITLE: usize = 0x134;
+pub const MANUFACTURER_CODE: usize = 0x13f;
+pub const CGB_FLAG: usize = 0x143;
+pub const NEW_LICENSEE_CODE: usize = 0x144;
+pub const SGB_FLAG: usize = 0x146;
 pub const CARTRIDGE_TYPE: usize = 0x147;
+pub const ROM_SIZE: usize = 0x148;
+pub const RAM_SIZE: usize = 0x149;
+pub const DESTINATION_CODE: usize = 0x14a;
+pub const OLD_LICENSEE_CODE: usize = 0x14b;
+pub const MASK_ROM_VERSION: usize = 0x14c;
+pub const HEADER_CHECKSUM: usize = 0x14d;
+pub const GLOBAL_CHECKSUM: usize = 0x14e;
+pub const HEADER_END: usize = 0x14f;
 
 pub const ROM_0_START: usize = 0x0000;
 pub const ROM_0_END: usize = 0x3fff;
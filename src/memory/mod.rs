@@ -1,15 +1,28 @@
 pub mod io_regs;
 pub mod joypad;
 pub mod locations;
+mod serial;
 pub mod sizes;
 mod video_memory;
 pub use self::joypad::JoyPad;
 use self::locations::*;
+use self::serial::Serial;
 pub use self::video_memory::VideoMemory;
+use crate::apu::Apu;
 use crate::bit_ops::BitGetSet;
 use crate::cartridge::Cartridge;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+use std::cell::RefCell;
 use std::collections::HashSet;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
 pub struct Memory {
     boot_rom: Vec<u8>,
     boot_rom_enabled: bool,
@@ -19,9 +32,15 @@ pub struct Memory {
     io: [u8; sizes::IO],
     hram: [u8; sizes::HRAM],
     interrupt_enable_register: u8,
-    serial_data: Vec<u8>,
+    serial: Serial,
     joypad: JoyPad,
     interrupt_flag: u8,
+    apu: Apu,
+    // Set by writes to DIV, until the timer restarts its divider
+    div_reset: bool,
+    // Reads and writes, only recorded while a debugger is watching memory.
+    // Reads are recorded through &self, so the list is in a RefCell
+    accesses: Option<RefCell<Vec<MemoryAccess>>>,
 }
 
 impl Memory {
@@ -35,9 +54,38 @@ impl Memory {
             wram: [0; sizes::WRAM],
             io: [0; sizes::IO],
             interrupt_enable_register: 0,
-            serial_data: Vec::new(),
+            serial: Serial::new(),
             joypad: JoyPad::new(),
             interrupt_flag: 0,
+            apu: Apu::new(),
+            div_reset: false,
+            accesses: None,
+        }
+    }
+
+    pub fn set_recording_accesses(&mut self, recording: bool) {
+        self.accesses = if recording {
+            Some(RefCell::new(Vec::new()))
+        } else {
+            None
+        };
+    }
+
+    // The accesses recorded since the last call
+    pub fn take_accesses(&mut self) -> Vec<MemoryAccess> {
+        match &mut self.accesses {
+            Some(x) => std::mem::take(x.get_mut()),
+            None => Vec::new(),
+        }
+    }
+
+    fn record_access(&self, address: u16, value: u8, write: bool) {
+        if let Some(accesses) = &self.accesses {
+            accesses.borrow_mut().push(MemoryAccess {
+                address,
+                value,
+                write,
+            });
         }
     }
 
@@ -53,18 +101,108 @@ impl Memory {
         &mut *self.cartridge
     }
 
+    pub fn get_apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
     pub fn get_joypad(&mut self) -> &mut JoyPad {
         &mut self.joypad
     }
 
     pub fn get_serial_data(&self) -> &[u8] {
-        &self.serial_data
+        self.serial.get_sent_data()
+    }
+
+    pub fn tick_serial(&mut self, cycles: u64) {
+        if self.serial.tick(cycles) {
+            self.interrupt_flag = self.interrupt_flag.set_bit(3);
+        }
+    }
+
+    // Returns true if a button was pressed on a selected line,
+    // which requests the joypad interrupt and ends STOP mode
+    pub fn tick_joypad(&mut self) -> bool {
+        if self.joypad.take_interrupt() {
+            self.interrupt_flag = self.interrupt_flag.set_bit(4);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Any write to DIV clears it, as does STOP
+    pub fn reset_div(&mut self) {
+        self.io[io_regs::DIV - IO_START] = 0;
+        self.div_reset = true;
+    }
+
+    // True if DIV was reset since the last call
+    pub fn take_div_reset(&mut self) -> bool {
+        let reset = self.div_reset;
+        self.div_reset = false;
+        reset
+    }
+
+    pub fn set_serial_linked(&mut self, linked: bool) {
+        self.serial.set_linked(linked);
+    }
+
+    pub fn get_serial_byte(&self) -> u8 {
+        self.serial.get_data()
+    }
+
+    pub fn is_serial_transfer_due(&self) -> bool {
+        self.serial.is_transfer_due()
+    }
+
+    pub fn is_serial_waiting_for_clock(&self) -> bool {
+        self.serial.is_waiting_for_clock()
+    }
+
+    // Finish a transfer with the byte from the other side,
+    // returning the byte sent to it
+    pub fn complete_serial_transfer(&mut self, received: u8) -> u8 {
+        self.interrupt_flag = self.interrupt_flag.set_bit(3);
+        self.serial.complete_transfer(received)
     }
 
     pub fn is_boot_rom_enabled(&self) -> bool {
         self.boot_rom_enabled
     }
 
+    // The boot rom itself isn't saved, only whether it is mapped in
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.boot_rom_enabled);
+        self.cartridge.save_state(writer);
+        self.vram.save_state(writer);
+        writer.write_bytes(&self.wram);
+        writer.write_bytes(&self.io);
+        writer.write_bytes(&self.hram);
+        writer.write_u8(self.interrupt_enable_register);
+        self.serial.save_state(writer);
+        self.joypad.save_state(writer);
+        writer.write_u8(self.interrupt_flag);
+        self.apu.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.boot_rom_enabled = reader.read_bool()?;
+        self.cartridge.load_state(reader)?;
+        self.vram.load_state(reader)?;
+        reader.read_bytes_into(&mut self.wram)?;
+        reader.read_bytes_into(&mut self.io)?;
+        reader.read_bytes_into(&mut self.hram)?;
+        self.interrupt_enable_register = reader.read_u8()?;
+        self.serial.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        self.interrupt_flag = reader.read_u8()?;
+        self.apu.load_state(reader)
+    }
+
     fn dma_transfer(&mut self, source: u8) {
         let start_address = u16::from(source) * 0x100;
         for i in 0..sizes::OAM {
@@ -77,6 +215,8 @@ impl Memory {
         match index {
             io_regs::IE => self.interrupt_enable_register,
             io_regs::JOYP => self.joypad.get_u8(),
+            io_regs::SB => self.serial.get_data(),
+            io_regs::SC => self.serial.get_control(),
             io_regs::LCDC => self.vram.regs.lcdc,
             io_regs::LY => self.vram.regs.ly,
             io_regs::LYC => self.vram.regs.lyc,
@@ -99,6 +239,7 @@ impl Memory {
                 x
             }
             io_regs::DIV | io_regs::TIMA | io_regs::TMA | io_regs::TAC => self.io[index - IO_START],
+            io_regs::NR10..=io_regs::WAVE_PATTERN_RAM_END => self.apu.get_u8(index),
             _ => {
                 eprintln_once_per_key!(
                     index,
@@ -116,7 +257,8 @@ impl Memory {
             io_regs::JOYP => self.joypad.set_u8(value),
             io_regs::DMA => self.dma_transfer(value),
             io_regs::IE => self.interrupt_enable_register = value,
-            io_regs::SB => self.serial_data.push(value),
+            io_regs::SB => self.serial.set_data(value),
+            io_regs::SC => self.serial.set_control(value),
             io_regs::BOOT_ROM_DISABLE => self.boot_rom_enabled = false,
             io_regs::STAT => {
                 let stat = self.vram.regs.stat;
@@ -150,6 +292,7 @@ impl Memory {
             io_regs::DIV | io_regs::TIMA | io_regs::TMA | io_regs::TAC => {
                 self.io[index - IO_START] = value;
             }
+            io_regs::NR10..=io_regs::WAVE_PATTERN_RAM_END => self.apu.set_u8(index, value),
             _ => {
                 eprintln_once_per_key!(
                     index,
@@ -164,6 +307,7 @@ impl Memory {
     }
 
     pub fn set_u8(&mut self, index: u16, value: u8) {
+        self.record_access(index, value, true);
         let index = index as usize;
         match index {
             ROM_0_START...ROM_0_END => self.cartridge.set_u8(index, value),
@@ -177,16 +321,13 @@ impl Memory {
             OAM_START...OAM_END => {
                 self.vram[index] = value;
             }
+            io_regs::DIV => self.reset_div(),
             IO_START...IO_END => self.set_io(index, value),
             HRAM_START...HRAM_END => self.hram[index - HRAM_START] = value,
             INTERRUPT_ENABLE_REG => {
                 self.interrupt_enable_register = value;
                 if value.get_bit(1) {
                     eprintln_once!("warning: Lcd STAT interrupt only partially implemented");
-                } else if value.get_bit(3) {
-                    eprintln_once!("warning: Serial interrupt not implemented");
-                } else if value.get_bit(4) {
-                    eprintln_once!("warning: Joypad interrupt not implemented");
                 }
             }
             _ => (),
@@ -194,6 +335,13 @@ impl Memory {
     }
 
     pub fn get_u8(&self, index: u16) -> u8 {
+        let value = self.fetch_u8(index);
+        self.record_access(index, value, false);
+        value
+    }
+
+    // Reads without recording the access, for instruction fetches
+    pub fn fetch_u8(&self, index: u16) -> u8 {
         let index = index as usize;
         match index {
             x if self.is_valid_boot_rom_index(x) => self.boot_rom[x],
@@ -214,6 +362,15 @@ impl Memory {
         }
     }
 
+    // Like fetch_u8, but the unusable area after OAM reads 0xff instead of
+    // panicking, for reads from outside the cpu like the debugger and tracer
+    pub fn peek_u8(&self, index: u16) -> u8 {
+        match index {
+            0xfea0..=0xfeff => 0xff,
+            _ => self.fetch_u8(index),
+        }
+    }
+
     fn is_valid_boot_rom_index(&self, index: usize) -> bool {
         self.boot_rom_enabled && index < self.boot_rom.len()
     }
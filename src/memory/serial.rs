@@ -0,0 +1,196 @@
+use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
+
+// 8 bits shifted out at 8192 Hz
+const TRANSFER_CYCLES: u64 = 4096;
+// The most bytes of sent data kept. Once full, the older half is dropped
+const MAX_SENT_DATA: usize = 64 * 1024;
+
+pub struct Serial {
+    // SB, the byte being shifted out, replaced by the byte shifted in
+    data: u8,
+    // SC, bit 7 starts a transfer and is cleared when it completes,
+    // bit 0 selects the internal clock
+    control: u8,
+    remaining_cycles: u64,
+    last_cycles: u64,
+    // The bytes sent recently, for test roms which print over serial.
+    // Not part of save states, as it only grows
+    sent_data: Vec<u8>,
+    // Connected to another Game Boy, which finishes internal clock
+    // transfers instead of them completing with 0xff
+    linked: bool,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            remaining_cycles: 0,
+            last_cycles: 0,
+            sent_data: Vec::new(),
+            linked: false,
+        }
+    }
+
+    pub fn get_sent_data(&self) -> &[u8] {
+        &self.sent_data
+    }
+
+    pub fn is_transferring(&self) -> bool {
+        self.control.get_bit(7)
+    }
+
+    pub fn is_internal_clock(&self) -> bool {
+        self.control.get_bit(0)
+    }
+
+    pub fn set_linked(&mut self, linked: bool) {
+        self.linked = linked;
+    }
+
+    // An internal clock transfer has shifted all 8 bits, and is
+    // waiting for the link to exchange bytes with the other side
+    pub fn is_transfer_due(&self) -> bool {
+        self.is_transferring() && self.is_internal_clock() && self.remaining_cycles == 0
+    }
+
+    // A transfer on the external clock, ready for the other side to clock it
+    pub fn is_waiting_for_clock(&self) -> bool {
+        self.is_transferring() && !self.is_internal_clock()
+    }
+
+    pub fn get_data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn set_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    // Unused bits read as 1
+    pub fn get_control(&self) -> u8 {
+        self.control | 0b0111_1110
+    }
+
+    pub fn set_control(&mut self, value: u8) {
+        self.control = value & 0b1000_0001;
+        if self.is_transferring() {
+            self.remaining_cycles = TRANSFER_CYCLES;
+        }
+    }
+
+    // Returns true when a transfer on the internal clock completes. With
+    // nothing connected, the byte shifted in is 0xff. When linked, the
+    // transfer is left due for the link to complete. Transfers on the
+    // external clock wait for the other side to finish them
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        let elapsed = cycles.saturating_sub(self.last_cycles);
+        self.last_cycles = cycles;
+
+        if !self.is_transferring() || !self.is_internal_clock() {
+            return false;
+        }
+        if elapsed < self.remaining_cycles {
+            self.remaining_cycles -= elapsed;
+            return false;
+        }
+        self.remaining_cycles = 0;
+        if self.linked {
+            return false;
+        }
+        self.complete_transfer(0xff);
+        true
+    }
+
+    // Swap SB with the other side's byte, returning the byte sent
+    pub fn complete_transfer(&mut self, received: u8) -> u8 {
+        let sent = self.data;
+        if self.sent_data.len() == MAX_SENT_DATA {
+            self.sent_data.drain(..MAX_SENT_DATA / 2);
+        }
+        self.sent_data.push(sent);
+        self.data = received;
+        self.control = self.control.reset_bit(7);
+        self.remaining_cycles = 0;
+        sent
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_u8(self.control);
+        writer.write_u64(self.remaining_cycles);
+        writer.write_u64(self.last_cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.data = reader.read_u8()?;
+        self.control = reader.read_u8()? & 0b1000_0001;
+        self.remaining_cycles = reader.read_u64()?;
+        self.last_cycles = reader.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_clock_transfer() {
+        let mut serial = Serial::new();
+        serial.set_data(0x42);
+        serial.set_control(0x81);
+        assert_eq!(serial.get_control(), 0xff);
+
+        assert!(!serial.tick(TRANSFER_CYCLES - 1));
+        assert!(serial.is_transferring());
+        assert!(serial.tick(TRANSFER_CYCLES));
+        assert!(!serial.is_transferring());
+        assert_eq!(serial.get_data(), 0xff);
+        assert_eq!(serial.get_sent_data(), &[0x42]);
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let mut serial = Serial::new();
+        serial.set_data(0x42);
+        serial.set_control(0x80);
+        assert!(!serial.tick(TRANSFER_CYCLES * 10));
+        assert!(serial.is_transferring());
+
+        assert_eq!(serial.complete_transfer(0x24), 0x42);
+        assert_eq!(serial.get_data(), 0x24);
+        assert!(!serial.is_transferring());
+    }
+
+    #[test]
+    fn linked_transfer_waits_for_link() {
+        let mut serial = Serial::new();
+        serial.set_linked(true);
+        serial.set_data(0x42);
+        serial.set_control(0x81);
+        assert!(!serial.tick(TRANSFER_CYCLES - 1));
+        assert!(!serial.is_transfer_due());
+        assert!(!serial.tick(TRANSFER_CYCLES * 2));
+        assert!(serial.is_transfer_due());
+
+        assert_eq!(serial.complete_transfer(0x24), 0x42);
+        assert_eq!(serial.get_data(), 0x24);
+        assert!(!serial.is_transfer_due());
+    }
+
+    #[test]
+    fn sent_data_is_capped() {
+        let mut serial = Serial::new();
+        for i in 0..MAX_SENT_DATA + 1 {
+            serial.set_data(i as u8);
+            serial.complete_transfer(0xff);
+        }
+        let sent = serial.get_sent_data();
+        assert_eq!(sent.len(), MAX_SENT_DATA / 2 + 1);
+        assert_eq!(sent[sent.len() - 1], MAX_SENT_DATA as u8);
+    }
+}
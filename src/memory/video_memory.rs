@@ -1,5 +1,7 @@
 use super::{locations::*, sizes};
 use crate::bit_ops::BitGetSet;
+use crate::error::Result;
+use crate::save_state::{StateReader, StateWriter};
 use std::default::Default;
 use std::ops::{Index, IndexMut};
 
@@ -45,6 +47,49 @@ impl VideoMemory {
         VideoMemory::new()
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        for x in self.tile_write_counts.iter() {
+            writer.write_u64(*x);
+        }
+
+        let regs = &self.regs;
+        let values = [
+            regs.lcdc, regs.ly, regs.lyc, regs.stat, regs.scy, regs.scx, regs.wy, regs.wx,
+            regs.bgp, regs.obp0, regs.obp1,
+        ];
+        for x in values.iter() {
+            writer.write_u8(*x);
+        }
+        writer.write_bool(regs.vblank_interrupt_enabled);
+        writer.write_bool(regs.stat_interrupt_enabled);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.read_bytes_into(&mut self.vram)?;
+        reader.read_bytes_into(&mut self.oam)?;
+        for x in self.tile_write_counts.iter_mut() {
+            *x = reader.read_u64()?;
+        }
+
+        let regs = &mut self.regs;
+        regs.lcdc = reader.read_u8()?;
+        regs.ly = reader.read_u8()?;
+        regs.lyc = reader.read_u8()?;
+        regs.stat = reader.read_u8()?;
+        regs.scy = reader.read_u8()?;
+        regs.scx = reader.read_u8()?;
+        regs.wy = reader.read_u8()?;
+        regs.wx = reader.read_u8()?;
+        regs.bgp = reader.read_u8()?;
+        regs.obp0 = reader.read_u8()?;
+        regs.obp1 = reader.read_u8()?;
+        regs.vblank_interrupt_enabled = reader.read_bool()?;
+        regs.stat_interrupt_enabled = reader.read_bool()?;
+        Ok(())
+    }
+
     pub fn get_u16(&self, index: usize) -> u16 {
         match index {
             VRAM_START...VRAM_END => get_u16(&self.vram, index - VRAM_START),
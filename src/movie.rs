@@ -0,0 +1,218 @@
+use crate::error::{Error, Result};
+use crate::memory::JoyPad;
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"GBEM";
+const VERSION: u32 = 1;
+// Frames between each memory hash
+const HASH_INTERVAL: u32 = 60;
+
+pub enum MovieStart {
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+// The joypad state for every frame of a run, with a hash of memory
+// every HASH_INTERVAL frames to check playback matches the recording
+pub struct Movie {
+    global_checksum: u16,
+    start: MovieStart,
+    hash_interval: u32,
+    hashes: Vec<u64>,
+    inputs: Vec<u8>,
+}
+
+impl Movie {
+    pub(crate) fn new(global_checksum: u16, start: MovieStart) -> Movie {
+        Movie {
+            global_checksum,
+            start,
+            hash_interval: HASH_INTERVAL,
+            hashes: Vec::new(),
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Movie> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn get_global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+
+    pub fn get_start(&self) -> &MovieStart {
+        &self.start
+    }
+
+    pub fn get_frame_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // Inputs for each frame, in the JoyPad::get_state format
+    pub fn get_inputs(&self) -> &[u8] {
+        &self.inputs
+    }
+
+    // All values are little endian. The header is followed by the start
+    // (0 for power on, or 1 and a length prefixed save state), the hash
+    // interval, then the length prefixed hashes and inputs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(MAGIC);
+        data.extend(&VERSION.to_le_bytes());
+        data.extend(&self.global_checksum.to_le_bytes());
+        match &self.start {
+            MovieStart::PowerOn => data.push(0),
+            MovieStart::SaveState(state) => {
+                data.push(1);
+                data.extend(&(state.len() as u32).to_le_bytes());
+                data.extend(state);
+            }
+        }
+        data.extend(&self.hash_interval.to_le_bytes());
+        data.extend(&(self.hashes.len() as u32).to_le_bytes());
+        for hash in self.hashes.iter() {
+            data.extend(&hash.to_le_bytes());
+        }
+        data.extend(&(self.inputs.len() as u32).to_le_bytes());
+        data.extend(&self.inputs);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie> {
+        let mut data = data;
+        if take(&mut data, MAGIC.len())? != MAGIC {
+            return Err(bad_movie("not a movie file"));
+        }
+        let version = read_u32(&mut data)?;
+        if version != VERSION {
+            return Err(Error::BadMovie(format!(
+                "unsupported movie version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        let global_checksum = read_u16(&mut data)?;
+        let start = match take(&mut data, 1)?[0] {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let length = read_u32(&mut data)? as usize;
+                MovieStart::SaveState(take(&mut data, length)?.to_vec())
+            }
+            _ => return Err(bad_movie("unknown start type")),
+        };
+
+        let hash_interval = read_u32(&mut data)?;
+        if hash_interval == 0 {
+            return Err(bad_movie("hash interval is zero"));
+        }
+        let hash_count = read_u32(&mut data)? as usize;
+        let mut hashes = Vec::new();
+        for chunk in take(&mut data, hash_count * 8)?.chunks(8) {
+            let mut hash = [0; 8];
+            hash.copy_from_slice(chunk);
+            hashes.push(u64::from_le_bytes(hash));
+        }
+        let input_count = read_u32(&mut data)? as usize;
+        let inputs = take(&mut data, input_count)?.to_vec();
+
+        if !data.is_empty() {
+            return Err(bad_movie("unexpected data at end of movie"));
+        }
+
+        Ok(Movie {
+            global_checksum,
+            start,
+            hash_interval,
+            hashes,
+            inputs,
+        })
+    }
+}
+
+// What the emulator is doing with a movie
+pub enum MovieMode {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+impl MovieMode {
+    // Called at the end of each frame, before the app updates the
+    // joypad. Returns the frame number if the hash doesn't match
+    pub fn check_hash(&mut self, hash: u64) -> Option<u64> {
+        match self {
+            MovieMode::Recording(movie) => {
+                if movie.inputs.len() % movie.hash_interval as usize == 0 {
+                    movie.hashes.push(hash);
+                }
+                None
+            }
+            MovieMode::Playing { movie, frame } => {
+                let interval = movie.hash_interval as usize;
+                if *frame % interval != 0 {
+                    return None;
+                }
+                match movie.hashes.get(*frame / interval) {
+                    Some(x) if *x != hash => Some(*frame as u64),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    // Called after the app updates the joypad. Recording saves
+    // the joypad state, playback replaces it with the recorded one.
+    // Returns false once playback has used its last input
+    pub fn update_joypad(&mut self, joypad: &mut JoyPad) -> bool {
+        match self {
+            MovieMode::Recording(movie) => {
+                movie.inputs.push(joypad.get_state());
+                true
+            }
+            MovieMode::Playing { movie, frame } => {
+                if let Some(x) = movie.inputs.get(*frame) {
+                    joypad.set_state(*x);
+                    *frame += 1;
+                }
+                *frame < movie.inputs.len()
+            }
+        }
+    }
+}
+
+// 64 bit FNV-1a
+pub fn hash_bytes<I: Iterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, x| {
+        (hash ^ u64::from(x)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn bad_movie(message: &str) -> Error {
+    Error::BadMovie(message.to_string())
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(bad_movie("movie is truncated"));
+    }
+    let (x, rest) = data.split_at(length);
+    *data = rest;
+    Ok(x)
+}
+
+fn read_u16(data: &mut &[u8]) -> Result<u16> {
+    let x = take(data, 2)?;
+    Ok(u16::from_le_bytes([x[0], x[1]]))
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+    let mut x = [0; 4];
+    x.copy_from_slice(take(data, 4)?);
+    Ok(u32::from_le_bytes(x))
+}
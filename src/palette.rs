@@ -0,0 +1,104 @@
+pub type Rgba = [u8; 4];
+
+// The colours of the 4 shades, from 0 lightest to 3 darkest
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette {
+    pub colours: [Rgba; 4],
+}
+
+impl Palette {
+    // Colours as 0xRRGGBB, lightest first
+    pub fn from_rgb(colours: [u32; 4]) -> Palette {
+        let mut palette = Palette {
+            colours: [[0; 4]; 4],
+        };
+        for (x, rgb) in palette.colours.iter_mut().zip(colours.iter()) {
+            let bytes = rgb.to_be_bytes();
+            *x = [bytes[1], bytes[2], bytes[3], 0xff];
+        }
+        palette
+    }
+
+    pub fn greyscale() -> Palette {
+        Palette::from_rgb([0xff_ffff, 0xaa_aaaa, 0x55_5555, 0x00_0000])
+    }
+
+    // The original Game Boy's green screen
+    pub fn dmg_green() -> Palette {
+        Palette::from_rgb([0x9b_bc0f, 0x8b_ac0f, 0x30_6230, 0x0f_380f])
+    }
+
+    pub fn pocket_grey() -> Palette {
+        Palette::from_rgb([0xc4_cfa1, 0x8b_956d, 0x4d_533c, 0x1f_1f1f])
+    }
+
+    // The Game Boy Light's backlit screen
+    pub fn light() -> Palette {
+        Palette::from_rgb([0x00_b581, 0x00_9a71, 0x00_694a, 0x00_4f3b])
+    }
+}
+
+// Separate palettes for the background and window, and each of the
+// object palettes, like the GBC uses when running DMG games
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palettes {
+    pub background: Palette,
+    pub object0: Palette,
+    pub object1: Palette,
+}
+
+impl Palettes {
+    pub fn all(palette: Palette) -> Palettes {
+        Palettes {
+            background: palette,
+            object0: palette,
+            object1: palette,
+        }
+    }
+
+    // Pixels are a palette (0 background, 1 OBP0, 2 OBP1) * 4 plus a shade
+    pub fn to_rgba(&self, pixels: &[u8]) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(pixels.len() * 4);
+        for pixel in pixels.iter() {
+            let palette = match pixel >> 2 {
+                0 => &self.background,
+                1 => &self.object0,
+                _ => &self.object1,
+            };
+            rgba.extend(&palette.colours[usize::from(pixel & 0b11)]);
+        }
+        rgba
+    }
+}
+
+impl Default for Palettes {
+    fn default() -> Palettes {
+        Palettes::all(Palette::greyscale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_rgb() {
+        let palette = Palette::dmg_green();
+        assert_eq!(palette.colours[0], [0x9b, 0xbc, 0x0f, 0xff]);
+        assert_eq!(palette.colours[3], [0x0f, 0x38, 0x0f, 0xff]);
+    }
+
+    #[test]
+    fn separate_palettes() {
+        let palettes = Palettes {
+            background: Palette::greyscale(),
+            object0: Palette::dmg_green(),
+            object1: Palette::light(),
+        };
+        let rgba = palettes.to_rgba(&[0, 3, 4 + 1, 8 + 2]);
+        assert_eq!(&rgba[0..4], &[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(&rgba[4..8], &[0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(&rgba[8..12], &[0x8b, 0xac, 0x0f, 0xff]);
+        assert_eq!(&rgba[12..16], &[0x00, 0x69, 0x4a, 0xff]);
+    }
+}
@@ -0,0 +1,434 @@
+use crate::error::Result;
+use crate::save_state::{bad_state, StateReader, StateWriter};
+use std::collections::HashSet;
+use std::fs;
+
+pub const PRINTER_WIDTH: usize = 160;
+// 20 tiles across, 16 bytes per tile
+const TILE_ROW_BYTES: usize = 20 * 16;
+// The printer's RAM holds one screen of tiles
+const MAX_DATA: usize = 0x2000;
+// Status packets that report printing after a print command, so games
+// that wait for printing to start and then finish see both
+const PRINTING_STATUS_POLLS: u8 = 4;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_BREAK: u8 = 0x08;
+const COMMAND_STATUS: u8 = 0x0f;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_PRINTING: u8 = 0b0000_0010;
+const STATUS_DATA_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED_DATA: u8 = 0b0000_1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// Indexed by the state's number in save states
+const STATES: [State; 11] = [
+    State::Magic1,
+    State::Magic2,
+    State::Command,
+    State::Compression,
+    State::LengthLow,
+    State::LengthHigh,
+    State::Data,
+    State::ChecksumLow,
+    State::ChecksumHigh,
+    State::Alive,
+    State::Status,
+];
+
+// A Game Boy Printer on the serial port. The game sends packets of
+// 0x88 0x33, command, compression, length, data and a checksum, then
+// two more bytes which the printer answers with 0x81 and its status.
+// Printed images are added to the bottom of a strip of paper 160
+// pixels wide, with one byte per pixel from 0x00 black to 0xff white
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet_data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    printing_polls: u8,
+    // Decompressed tile data waiting to be printed
+    data: Vec<u8>,
+    paper: Vec<u8>,
+}
+
+impl Printer {
+    pub(crate) fn new() -> Printer {
+        Printer {
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet_data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            data: Vec::new(),
+            paper: Vec::new(),
+        }
+    }
+
+    // Everything printed so far, PRINTER_WIDTH pixels wide
+    pub fn get_image(&self) -> &[u8] {
+        &self.paper
+    }
+
+    pub fn get_image_height(&self) -> usize {
+        self.paper.len() / PRINTER_WIDTH
+    }
+
+    // Tear off the paper
+    pub fn take_image(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.paper)
+    }
+
+    // Write the printed image as a binary PGM
+    pub fn save_image(&self, path: &str) -> Result<()> {
+        let mut file =
+            format!("P5\n{} {}\n255\n", PRINTER_WIDTH, self.get_image_height()).into_bytes();
+        file.extend(&self.paper);
+        fs::write(path, file)?;
+        Ok(())
+    }
+
+    // Takes the byte the game shifted out, and returns the byte shifted back
+    pub fn exchange(&mut self, value: u8) -> u8 {
+        let mut response = 0;
+        self.state = match self.state {
+            State::Magic1 if value == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if value == 0x33 => State::Command,
+            State::Magic2 if value == 0x88 => State::Magic2,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = value;
+                self.checksum = u16::from(value);
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = value & 0b1 != 0;
+                self.add_to_checksum(value);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = u16::from(value);
+                self.add_to_checksum(value);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= u16::from(value) << 8;
+                self.add_to_checksum(value);
+                self.packet_data.clear();
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.packet_data.push(value);
+                self.add_to_checksum(value);
+                if self.packet_data.len() == usize::from(self.length) {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = u16::from(value);
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= u16::from(value) << 8;
+                State::Alive
+            }
+            State::Alive => {
+                response = 0x81;
+                State::Status
+            }
+            State::Status => {
+                self.process_packet();
+                response = self.status;
+                State::Magic1
+            }
+        };
+        response
+    }
+
+    // The paper belongs to the host, so isn't part of the state
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        let state = STATES.iter().position(|x| *x == self.state).unwrap();
+        writer.write_u8(state as u8);
+        writer.write_u8(self.command);
+        writer.write_bool(self.compressed);
+        writer.write_u16(self.length);
+        writer.write_bytes(&self.packet_data);
+        writer.write_u16(self.checksum);
+        writer.write_u16(self.received_checksum);
+        writer.write_u8(self.status);
+        writer.write_u8(self.printing_polls);
+        writer.write_bytes(&self.data);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.state = match STATES.get(usize::from(reader.read_u8()?)) {
+            Some(x) => *x,
+            None => return Err(bad_state("bad printer state")),
+        };
+        self.command = reader.read_u8()?;
+        self.compressed = reader.read_bool()?;
+        self.length = reader.read_u16()?;
+        self.packet_data = reader.read_bytes()?.to_vec();
+        if self.packet_data.len() > usize::from(self.length) {
+            return Err(bad_state("printer packet is longer than its length"));
+        }
+        self.checksum = reader.read_u16()?;
+        self.received_checksum = reader.read_u16()?;
+        self.status = reader.read_u8()?;
+        self.printing_polls = reader.read_u8()?;
+        self.data = reader.read_bytes()?.to_vec();
+        if self.data.len() > MAX_DATA {
+            return Err(bad_state("too much printer data"));
+        }
+        Ok(())
+    }
+
+    // Drops any packet in progress and unprinted data, keeping the paper
+    pub(crate) fn reset(&mut self) {
+        let paper = self.take_image();
+        *self = Printer::new();
+        self.paper = paper;
+    }
+
+    fn add_to_checksum(&mut self, value: u8) {
+        self.checksum = self.checksum.wrapping_add(u16::from(value));
+    }
+
+    fn process_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.data.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            }
+            COMMAND_DATA => self.receive_data(),
+            COMMAND_PRINT => {
+                // Sheets, margins, palette and exposure
+                if let Some(palette) = self.packet_data.get(2) {
+                    self.print(*palette);
+                }
+            }
+            COMMAND_BREAK => {
+                self.printing_polls = 0;
+                self.status &= !STATUS_PRINTING;
+            }
+            COMMAND_STATUS => {
+                if self.printing_polls > 0 {
+                    self.printing_polls -= 1;
+                    if self.printing_polls == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+            }
+            _ => {
+                eprintln_once_per_key!(
+                    self.command,
+                    u8,
+                    "warning: unknown printer command {:#04x}",
+                    self.command
+                );
+            }
+        }
+    }
+
+    // An empty data packet marks the end of the data
+    fn receive_data(&mut self) {
+        let data = if self.compressed {
+            decompress(&self.packet_data)
+        } else {
+            self.packet_data.clone()
+        };
+        let space = MAX_DATA - self.data.len();
+        if data.len() > space {
+            self.status |= STATUS_DATA_FULL;
+        }
+        self.data.extend(data.iter().take(space));
+        if !self.data.is_empty() {
+            self.status |= STATUS_UNPROCESSED_DATA;
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        // Games that don't set a palette expect the usual one
+        let palette = if palette == 0 { 0b1110_0100 } else { palette };
+        for tile_row in self.data.chunks_exact(TILE_ROW_BYTES) {
+            for line in 0..8 {
+                for tile in tile_row.chunks_exact(16) {
+                    let low = tile[line * 2];
+                    let high = tile[line * 2 + 1];
+                    for x in (0..8).rev() {
+                        let colour = (((high >> x) & 0b1) << 1) | ((low >> x) & 0b1);
+                        let shade = (palette >> (colour * 2)) & 0b11;
+                        self.paper.push(0xff - shade * 0x55);
+                    }
+                }
+            }
+        }
+        self.data.clear();
+        self.status &= !(STATUS_UNPROCESSED_DATA | STATUS_DATA_FULL);
+        self.status |= STATUS_PRINTING;
+        self.printing_polls = PRINTING_STATUS_POLLS;
+    }
+}
+
+// Run length encoding. A control byte with bit 7 set repeats the
+// next byte (control & 0x7f) + 2 times, otherwise the next
+// control + 1 bytes are copied
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut data = data.iter();
+    while let Some(control) = data.next() {
+        if control & 0x80 != 0 {
+            if let Some(x) = data.next() {
+                let count = usize::from(control & 0x7f) + 2;
+                output.resize(output.len() + count, *x);
+            }
+        } else {
+            output.extend(data.by_ref().take(usize::from(*control) + 1));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the alive byte and status
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![0x88, 0x33, command, u8::from(compressed)];
+        packet.extend(&(data.len() as u16).to_le_bytes());
+        packet.extend(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, x| sum.wrapping_add(u16::from(*x)));
+        packet.extend(&checksum.to_le_bytes());
+        for x in packet.iter() {
+            assert_eq!(printer.exchange(*x), 0);
+        }
+        (printer.exchange(0), printer.exchange(0))
+    }
+
+    #[test]
+    fn print_tiles() {
+        let mut printer = Printer::new();
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_INIT, false, &[]),
+            (0x81, 0)
+        );
+
+        // Two rows of tiles, with every line of every tile using
+        // colours 1, 2, 3, 0, 0, 3, 2, 1. Sent as literal runs
+        let mut tiles = Vec::new();
+        for _ in 0..TILE_ROW_BYTES {
+            tiles.extend(&[0b1010_0101u8, 0b0110_0110]);
+        }
+        let compressed: Vec<u8> = tiles
+            .chunks(128)
+            .flat_map(|x| {
+                let mut chunk = vec![x.len() as u8 - 1];
+                chunk.extend(x);
+                chunk
+            })
+            .collect();
+
+        let (_, status) = send_packet(&mut printer, COMMAND_DATA, true, &compressed);
+        assert_eq!(status, STATUS_UNPROCESSED_DATA);
+        send_packet(&mut printer, COMMAND_DATA, false, &[]);
+        let (_, status) = send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x13, 0xe4, 0x40]);
+        assert_eq!(status, STATUS_PRINTING);
+
+        assert_eq!(printer.get_image_height(), 16);
+        let image = printer.get_image();
+        assert_eq!(
+            &image[0..8],
+            &[0xaa, 0x55, 0x00, 0xff, 0xff, 0x00, 0x55, 0xaa]
+        );
+        assert_eq!(&image[PRINTER_WIDTH * 15 + 152..], &image[0..8]);
+
+        for _ in 0..PRINTING_STATUS_POLLS {
+            send_packet(&mut printer, COMMAND_STATUS, false, &[]);
+        }
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_STATUS, false, &[]),
+            (0x81, 0)
+        );
+    }
+
+    #[test]
+    fn state_mid_packet() {
+        let mut printer = Printer::new();
+        send_packet(&mut printer, COMMAND_DATA, false, &[0; 16]);
+        for x in [0x88, 0x33, COMMAND_STATUS, 0].iter() {
+            printer.exchange(*x);
+        }
+        let mut writer = StateWriter::new();
+        printer.save_state(&mut writer);
+        let state = writer.into_bytes();
+
+        // The loaded printer finishes the packet, and still has the data
+        let mut loaded = Printer::new();
+        let mut reader = StateReader::new(&state).unwrap();
+        loaded.load_state(&mut reader).unwrap();
+        reader.finish().unwrap();
+        for x in [0, 0, 0x0f, 0].iter() {
+            assert_eq!(loaded.exchange(*x), 0);
+        }
+        assert_eq!(loaded.exchange(0), 0x81);
+        assert_eq!(loaded.exchange(0), STATUS_UNPROCESSED_DATA);
+    }
+
+    #[test]
+    fn decompression() {
+        let data = [0x81, 0x5a, 0x01, 0x3c, 0x7e];
+        assert_eq!(decompress(&data), [0x5a, 0x5a, 0x5a, 0x3c, 0x7e]);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut printer = Printer::new();
+        for x in [0x88, 0x33, COMMAND_INIT, 0, 0, 0, 0x05, 0x00].iter() {
+            printer.exchange(*x);
+        }
+        assert_eq!(printer.exchange(0), 0x81);
+        assert_eq!(printer.exchange(0), STATUS_CHECKSUM_ERROR);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+// Keeps save states captured every `interval` frames. Only the newest
+// state is stored in full, older ones are stored as the run length
+// encoded XOR of each state with the state captured after it
+pub struct RewindBuffer {
+    interval: u64,
+    memory_budget: usize,
+    frames_since_capture: u64,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u64, memory_budget: usize) -> RewindBuffer {
+        assert!(interval > 0, "rewind interval must be at least one frame");
+        RewindBuffer {
+            interval,
+            memory_budget,
+            frames_since_capture: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    // Called at the end of every frame, returns true when a
+    // state should be captured and passed to push
+    pub fn end_frame(&mut self) -> bool {
+        self.frames_since_capture += 1;
+        self.frames_since_capture >= self.interval
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            let delta = encode_delta(&state, &latest);
+            self.deltas_size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+        self.frames_since_capture = 0;
+
+        // The newest state is always kept, even if it alone is over budget
+        while self.size() > self.memory_budget {
+            match self.deltas.pop_front() {
+                Some(x) => self.deltas_size -= x.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Finds the newest state at least `frames` old, or the oldest state
+    // if there isn't one that old. That state becomes the newest state
+    // in the buffer, and is returned with how many frames back it is
+    pub fn rewind(&mut self, frames: u64) -> Option<(Vec<u8>, u64)> {
+        let mut state = self.latest.take()?;
+        let mut rewound = self.frames_since_capture;
+        while rewound < frames {
+            match self.deltas.pop_back() {
+                Some(delta) => {
+                    self.deltas_size -= delta.len();
+                    state = apply_delta(&state, &delta);
+                    rewound += self.interval;
+                }
+                None => break,
+            }
+        }
+
+        self.latest = Some(state.clone());
+        self.frames_since_capture = 0;
+        Some((state, rewound))
+    }
+
+    // Number of frames that can be rewound right now
+    pub fn get_available_frames(&self) -> u64 {
+        match self.latest {
+            Some(_) => self.frames_since_capture + self.deltas.len() as u64 * self.interval,
+            None => 0,
+        }
+    }
+
+    // Bytes used by the stored states
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas_size
+    }
+}
+
+// A delta is the length of the target state, followed by pairs of
+// (zero run length, literal length) u16s each followed by the literal
+// bytes, covering the XOR of the two states padded to the same length
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let length = from.len().max(to.len());
+    let xor: Vec<u8> = (0..length)
+        .map(|i| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0))
+        .collect();
+
+    let mut delta = Vec::new();
+    delta.extend(&(to.len() as u32).to_le_bytes());
+
+    let mut i = 0;
+    while i < xor.len() {
+        let zeros = xor[i..]
+            .iter()
+            .take(0xffff)
+            .take_while(|x| **x == 0)
+            .count();
+        i += zeros;
+        let literals = xor[i..]
+            .iter()
+            .take(0xffff)
+            .take_while(|x| **x != 0)
+            .count();
+        delta.extend(&(zeros as u16).to_le_bytes());
+        delta.extend(&(literals as u16).to_le_bytes());
+        delta.extend(&xor[i..i + literals]);
+        i += literals;
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u16 = |i: usize| usize::from(u16::from_le_bytes([delta[i], delta[i + 1]]));
+
+    let mut length = [0; 4];
+    length.copy_from_slice(&delta[0..4]);
+    let length = u32::from_le_bytes(length) as usize;
+
+    let mut state = from.to_vec();
+    state.resize(state.len().max(length), 0);
+
+    let mut position = 0;
+    let mut i = 4;
+    while i < delta.len() {
+        position += read_u16(i);
+        let literals = read_u16(i + 2);
+        i += 4;
+        for (x, v) in state[position..position + literals]
+            .iter_mut()
+            .zip(&delta[i..i + literals])
+        {
+            *x ^= v;
+        }
+        position += literals;
+        i += literals;
+    }
+
+    state.truncate(length);
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip() {
+        let a: Vec<u8> = (0..100_000).map(|x| (x / 7) as u8).collect();
+        let mut b = a.clone();
+        b[10] = 0;
+        b[70_000..70_010].copy_from_slice(&[1; 10]);
+        b.extend(&[5, 0, 5]);
+
+        let delta = encode_delta(&b, &a);
+        assert!(delta.len() < 100);
+        assert_eq!(apply_delta(&b, &delta), a);
+        assert_eq!(apply_delta(&a, &encode_delta(&a, &b)), b);
+    }
+
+    #[test]
+    fn rewind_and_budget() {
+        let state = |x: u8| vec![x; 1000];
+        let mut buffer = RewindBuffer::new(2, 1_000_000);
+        for i in 0..10 {
+            if buffer.end_frame() {
+                buffer.push(state(i));
+            }
+        }
+        // States from frames 2, 4, 6, 8 and 10
+        assert_eq!(buffer.get_available_frames(), 8);
+
+        buffer.end_frame();
+        let (x, frames) = buffer.rewind(4).unwrap();
+        assert_eq!((x, frames), (state(5), 5));
+        let (x, frames) = buffer.rewind(100).unwrap();
+        assert_eq!((x, frames), (state(1), 4));
+        assert_eq!(buffer.get_available_frames(), 0);
+
+        // Each delta here is 1008 bytes, so only the newest state fits
+        let mut buffer = RewindBuffer::new(1, 1000);
+        for i in 0..3 {
+            buffer.end_frame();
+            buffer.push(state(i));
+        }
+        assert_eq!(buffer.size(), 1000);
+        assert_eq!(buffer.rewind(1).unwrap(), (state(2), 0));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::{Error, Result};
+
+// Every save state starts with the magic bytes and the format version.
+// Bump the version whenever a subsystem changes what it writes
+const MAGIC: &[u8; 4] = b"GBES";
+pub const VERSION: u32 = 7;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.data.extend(MAGIC);
+        writer.write_u32(VERSION);
+        writer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend(&value.to_le_bytes());
+    }
+
+    // Length prefixed, so the reader can check it matches what it expects
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>> {
+        let mut reader = StateReader { data, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(bad_state("not a save state"));
+        }
+        let version = reader.read_u32()?;
+        if version != VERSION {
+            return Err(Error::BadSaveState(format!(
+                "unsupported save state version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        Ok(reader)
+    }
+
+    // Check every byte of the state was used
+    pub fn finish(self) -> Result<()> {
+        if self.position == self.data.len() {
+            Ok(())
+        } else {
+            Err(bad_state("unexpected data at end of save state"))
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let mut x = [0; 2];
+        x.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(x))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let mut x = [0; 4];
+        x.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(x))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut x = [0; 8];
+        x.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(x))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let length = self.read_u32()? as usize;
+        self.take(length)
+    }
+
+    // Read bytes written by write_bytes into a buffer of the same size
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let data = self.read_bytes()?;
+        if data.len() != buffer.len() {
+            return Err(bad_state("save state buffer has the wrong size"));
+        }
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.position < length {
+            return Err(bad_state("save state is truncated"));
+        }
+        let x = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(x)
+    }
+}
+
+pub fn bad_state(message: &str) -> Error {
+    Error::BadSaveState(message.to_string())
+}
@@ -0,0 +1,175 @@
+use crate::error::{Error, Result};
+use crate::{App, Command, Emulator};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const VERSION: u8 = 1;
+// Both sides stop and exchange serial state every SYNC_CYCLES, so a
+// transfer completes at most this long after its 8 bits are shifted.
+// A transfer takes 4096 cycles
+const SYNC_CYCLES: u64 = 512;
+
+const MESSAGE_SYNC: u8 = 0;
+const MESSAGE_QUIT: u8 = 1;
+// Flags in a sync message
+const TRANSFER_DUE: u8 = 0b01;
+const WAITING_FOR_CLOCK: u8 = 0b10;
+
+// A link cable to an emulator in another process. After a handshake of
+// MAGIC and VERSION, every message is 3 bytes: a type, flags and SB.
+// Each side runs SYNC_CYCLES then sends a sync message and waits for
+// the other's, which keeps the clocks together. Both then resolve the
+// transfers the same way, so neither side needs to be the master.
+// A quit message is sent when the app stops
+pub struct TcpLink {
+    stream: TcpStream,
+    connected: bool,
+}
+
+impl TcpLink {
+    // Wait for one emulator to connect
+    pub fn listen<A: ToSocketAddrs>(address: A) -> Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        TcpLink::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> Result<TcpLink> {
+        let (stream, _) = listener.accept()?;
+        TcpLink::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<TcpLink> {
+        TcpLink::from_stream(TcpStream::connect(address)?)
+    }
+
+    pub fn from_stream(mut stream: TcpStream) -> Result<TcpLink> {
+        // Each sync is a small round trip, so don't let them be delayed
+        stream.set_nodelay(true)?;
+
+        let mut handshake = MAGIC.to_vec();
+        handshake.push(VERSION);
+        stream.write_all(&handshake)?;
+        let mut other = [0; 5];
+        stream.read_exact(&mut other)?;
+        if other[0..4] != MAGIC[..] {
+            return Err(bad_message("not a gb_emu link cable"));
+        }
+        if other[4] != VERSION {
+            return Err(Error::BadLinkMessage(format!(
+                "unsupported link version {}, expected {}",
+                other[4], VERSION
+            )));
+        }
+
+        Ok(TcpLink {
+            stream,
+            connected: true,
+        })
+    }
+
+    // False once the other side has quit
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    // Run the emulator linked to the other side until the app returns
+    // Stop. If the other side quits first, this keeps running with
+    // nothing connected
+    pub fn run<T: App>(&mut self, emulator: &mut Emulator, app: &mut T) -> Result<()> {
+        emulator.memory.set_serial_linked(self.connected);
+        emulator.update_sample_rate(app);
+        let result = self.run_linked(emulator, app);
+        emulator.memory.set_serial_linked(false);
+        if result.is_err() {
+            self.connected = false;
+        }
+        result
+    }
+
+    fn run_linked<T: App>(&mut self, emulator: &mut Emulator, app: &mut T) -> Result<()> {
+        let mut last_cycles = emulator.get_elapsed_cycles();
+        let mut until_sync = SYNC_CYCLES;
+        loop {
+            emulator.tick(app);
+            if emulator.lcd.is_vblank() {
+                if let Command::Stop = emulator.finish_frame(app) {
+                    return self.quit();
+                }
+            }
+
+            // Rewinding moves the cycles back, which doesn't count.
+            // Time in STOP mode does, or the other side would wait forever
+            let cycles = emulator.get_elapsed_cycles();
+            let elapsed = cycles.saturating_sub(last_cycles);
+            last_cycles = cycles;
+            if !self.connected || elapsed < until_sync {
+                until_sync = until_sync.saturating_sub(elapsed);
+                continue;
+            }
+            until_sync = SYNC_CYCLES - (elapsed - until_sync) % SYNC_CYCLES;
+
+            self.sync(emulator)?;
+            if !self.connected {
+                emulator.memory.set_serial_linked(false);
+            }
+        }
+    }
+
+    fn sync(&mut self, emulator: &mut Emulator) -> Result<()> {
+        let memory = &mut emulator.memory;
+        let mut flags = 0;
+        if memory.is_serial_transfer_due() {
+            flags |= TRANSFER_DUE;
+        }
+        if memory.is_serial_waiting_for_clock() {
+            flags |= WAITING_FOR_CLOCK;
+        }
+        let data = memory.get_serial_byte();
+        self.stream.write_all(&[MESSAGE_SYNC, flags, data])?;
+
+        let mut message = [0; 3];
+        self.stream.read_exact(&mut message)?;
+        let (other_flags, other_data) = match message[0] {
+            MESSAGE_SYNC => (message[1], message[2]),
+            MESSAGE_QUIT => {
+                self.connected = false;
+                self.stream.shutdown(Shutdown::Both)?;
+                (0, 0xff)
+            }
+            x => return Err(Error::BadLinkMessage(format!("unknown type {}", x))),
+        };
+
+        // The same rules as LinkCable, from both sides at once
+        if flags & TRANSFER_DUE != 0 {
+            let received = if other_flags & WAITING_FOR_CLOCK != 0 {
+                other_data
+            } else {
+                0xff
+            };
+            memory.complete_serial_transfer(received);
+        } else if flags & WAITING_FOR_CLOCK != 0 && other_flags & TRANSFER_DUE != 0 {
+            memory.complete_serial_transfer(other_data);
+        }
+        Ok(())
+    }
+
+    // Tell the other side, then wait for it to close the connection so
+    // it doesn't see a reset before reading the quit message
+    fn quit(&mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+        self.connected = false;
+        self.stream.write_all(&[MESSAGE_QUIT, 0, 0])?;
+        self.stream.shutdown(Shutdown::Write)?;
+        let mut rest = Vec::new();
+        // Any error here means the other side is gone anyway
+        let _ = self.stream.read_to_end(&mut rest);
+        Ok(())
+    }
+}
+
+fn bad_message(message: &str) -> Error {
+    Error::BadLinkMessage(message.to_string())
+}
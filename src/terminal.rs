@@ -0,0 +1,315 @@
+use crate::cpu::CLOCK_SPEED;
+use crate::lcd::{FRAME_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::{Palette, Rgba};
+use crate::{App, Command, JoyPad};
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::process::{self, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Terminals only send key presses, so each press holds the
+// button for this many frames, and key repeat keeps it held
+const HOLD_FRAMES: u8 = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColourMode {
+    TrueColour,
+    Colour256,
+}
+
+impl ColourMode {
+    // 24 bit colour if COLORTERM says the terminal supports it
+    pub fn detect() -> ColourMode {
+        match env::var("COLORTERM") {
+            Ok(x) if x.contains("truecolor") || x.contains("24bit") => ColourMode::TrueColour,
+            _ => ColourMode::Colour256,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Key {
+    // A bit in the JoyPad::get_state format
+    Button(u8),
+    Quit,
+}
+
+// Shows the screen in the terminal, two pixels to a character with the
+// upper half block, and reads the joypad from stdin. Arrow keys are the
+// d-pad, z and x are A and B, enter is start and space is select.
+// q or ctrl-c quits. Runs at the Game Boy's 59.7 frames per second
+pub struct TerminalApp {
+    palette: Palette,
+    colour_mode: ColourMode,
+    frame: Vec<u8>,
+    // What is on the terminal, to only redraw rows that change
+    shown: Option<Vec<u8>>,
+    keys: Receiver<Key>,
+    held: [u8; 8],
+    next_frame: Instant,
+    saved_terminal: String,
+}
+
+impl TerminalApp {
+    // Puts the terminal into raw mode, which is undone when dropped
+    pub fn new(palette: Palette, colour_mode: ColourMode) -> io::Result<TerminalApp> {
+        let saved_terminal = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        // Reads block, so they have their own thread, which is left
+        // waiting for input when the app is dropped
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            while let Ok(count) = stdin.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                for key in parse_keys(&buffer[..count]) {
+                    if sender.send(key).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        // Clear the screen and hide the cursor
+        print!("\x1b[2J\x1b[?25l");
+        io::stdout().flush()?;
+
+        Ok(TerminalApp {
+            palette,
+            colour_mode,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shown: None,
+            keys,
+            held: [0; 8],
+            next_frame: Instant::now(),
+            saved_terminal,
+        })
+    }
+
+    // Sleep until it's time for the next frame. If emulation
+    // falls behind, it carries on from now rather than catching up
+    fn throttle(&mut self) {
+        let frame_time = Duration::from_nanos(FRAME_CYCLES * 1_000_000_000 / CLOCK_SPEED);
+        self.next_frame += frame_time;
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+impl App for TerminalApp {
+    fn draw_line(&mut self, line_buffer: &[u8], line_index: u8) {
+        let start = usize::from(line_index) * SCREEN_WIDTH;
+        self.frame[start..start + SCREEN_WIDTH].copy_from_slice(line_buffer);
+    }
+
+    fn update(&mut self, joypad: &mut JoyPad) -> Command {
+        let output = render(
+            &self.frame,
+            self.shown.as_deref(),
+            &self.palette,
+            self.colour_mode,
+        );
+        let mut stdout = io::stdout();
+        if stdout
+            .write_all(output.as_bytes())
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            return Command::Stop;
+        }
+        self.shown = Some(self.frame.clone());
+
+        while let Ok(key) = self.keys.try_recv() {
+            match key {
+                Key::Button(bit) => self.held[usize::from(bit)] = HOLD_FRAMES,
+                Key::Quit => return Command::Stop,
+            }
+        }
+        let mut state = 0;
+        for (bit, frames) in self.held.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                state |= 0b1 << bit;
+            }
+        }
+        joypad.set_state(state);
+
+        self.throttle();
+        Command::Continue
+    }
+}
+
+impl Drop for TerminalApp {
+    fn drop(&mut self) {
+        // Reset colours, show the cursor and move below the screen
+        print!("\x1b[0m\x1b[?25h\x1b[{};1H\r\n", SCREEN_HEIGHT / 2 + 1);
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved_terminal]);
+    }
+}
+
+// Runs stty on the terminal, returning what it prints
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = process::Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "stty failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            // Arrow keys are ESC [ A to D
+            0x1b if bytes.get(i + 1) == Some(&b'[') && i + 2 < bytes.len() => {
+                i += 2;
+                match bytes[i] {
+                    b'C' => Some(Key::Button(4)),
+                    b'D' => Some(Key::Button(5)),
+                    b'A' => Some(Key::Button(6)),
+                    b'B' => Some(Key::Button(7)),
+                    _ => None,
+                }
+            }
+            b'z' => Some(Key::Button(0)),
+            b'x' => Some(Key::Button(1)),
+            b' ' => Some(Key::Button(2)),
+            b'\r' | b'\n' => Some(Key::Button(3)),
+            // ctrl-c doesn't send a signal in raw mode
+            b'q' | 0x03 => Some(Key::Quit),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+// Draws the rows of half blocks that differ from shown. The top
+// pixel is the foreground colour and the bottom is the background
+fn render(frame: &[u8], shown: Option<&[u8]>, palette: &Palette, mode: ColourMode) -> String {
+    let mut output = String::new();
+    for (row, pixels) in frame.chunks(SCREEN_WIDTH * 2).enumerate() {
+        let range = row * SCREEN_WIDTH * 2..(row + 1) * SCREEN_WIDTH * 2;
+        if shown.map(|x| &x[range]) == Some(pixels) {
+            continue;
+        }
+
+        write!(output, "\x1b[{};1H", row + 1).unwrap();
+        let (top, bottom) = pixels.split_at(SCREEN_WIDTH);
+        let mut last = None;
+        for colours in top.iter().zip(bottom.iter()) {
+            if last != Some(colours) {
+                // Shades from the lcd are 0 black to 3 white
+                let foreground = palette.colours[usize::from(3 - colours.0)];
+                let background = palette.colours[usize::from(3 - colours.1)];
+                write!(
+                    output,
+                    "\x1b[38;{}m\x1b[48;{}m",
+                    colour_code(foreground, mode),
+                    colour_code(background, mode)
+                )
+                .unwrap();
+                last = Some(colours);
+            }
+            output.push('\u{2580}');
+        }
+        output.push_str("\x1b[0m");
+    }
+    output
+}
+
+fn colour_code(colour: Rgba, mode: ColourMode) -> String {
+    match mode {
+        ColourMode::TrueColour => format!("2;{};{};{}", colour[0], colour[1], colour[2]),
+        ColourMode::Colour256 => format!("5;{}", rgb_to_256(colour)),
+    }
+}
+
+// The closest colour in xterm's 6x6x6 cube, or its grey ramp for greys
+fn rgb_to_256(colour: Rgba) -> u8 {
+    let [r, g, b, _] = colour;
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            239..=255 => 231,
+            x => 232 + (x - 8) / 10,
+        };
+    }
+    let level = |x: u8| match x {
+        0..=47 => 0,
+        48..=114 => 1,
+        x => (x - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let keys = parse_keys(b"z\x1b[A\x1b[Dx\rq");
+        assert_eq!(
+            keys,
+            [
+                Key::Button(0),
+                Key::Button(6),
+                Key::Button(5),
+                Key::Button(1),
+                Key::Button(3),
+                Key::Quit
+            ]
+        );
+    }
+
+    #[test]
+    fn colours_256() {
+        assert_eq!(rgb_to_256([0, 0, 0, 255]), 16);
+        assert_eq!(rgb_to_256([255, 255, 255, 255]), 231);
+        assert_eq!(rgb_to_256([0x55, 0x55, 0x55, 255]), 239);
+        assert_eq!(rgb_to_256([255, 0, 0, 255]), 196);
+        assert_eq!(rgb_to_256([0x9b, 0xbc, 0x0f, 255]), 142);
+    }
+
+    #[test]
+    fn only_changed_rows_are_drawn() {
+        let palette = Palette::greyscale();
+        let mut frame = vec![3; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let first = render(&frame, None, &palette, ColourMode::TrueColour);
+        assert_eq!(
+            first.matches('\u{2580}').count(),
+            SCREEN_WIDTH * SCREEN_HEIGHT / 2
+        );
+        // Every cell is the same colour, so it's only set once per row
+        assert_eq!(first.matches("38;2;255;255;255").count(), SCREEN_HEIGHT / 2);
+
+        let shown = frame.clone();
+        frame[SCREEN_WIDTH * 3] = 0;
+        let second = render(&frame, Some(&shown), &palette, ColourMode::TrueColour);
+        assert!(second.starts_with("\x1b[2;1H"));
+        assert_eq!(second.matches('\u{2580}').count(), SCREEN_WIDTH);
+        assert!(second.contains("\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m"));
+    }
+}
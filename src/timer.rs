@@ -1,6 +1,8 @@
 use super::bit_ops::BitGetSet;
 use super::cpu;
+use super::error::Result;
 use super::memory::{io_regs, Memory};
+use super::save_state::{StateReader, StateWriter};
 
 pub struct Timer {
     enabled: bool,
@@ -20,6 +22,9 @@ impl Timer {
     }
 
     pub fn tick(&mut self, memory: &mut Memory, cycles: u64) {
+        if memory.take_div_reset() {
+            self.div_update_time = cycles + self.cpu_cycles_per_div_increment();
+        }
         let (tima, tma) = self.read_registers(memory, cycles);
         if self.enabled && cycles > self.update_time {
             self.update_time += self.cpu_cycles_per_tick();
@@ -43,6 +48,21 @@ impl Timer {
         }
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u64(self.input_clock);
+        writer.write_u64(self.update_time);
+        writer.write_u64(self.div_update_time);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.input_clock = reader.read_u64()?;
+        self.update_time = reader.read_u64()?;
+        self.div_update_time = reader.read_u64()?;
+        Ok(())
+    }
+
     fn cpu_cycles_per_tick(&mut self) -> u64 {
         cpu::CLOCK_SPEED / self.input_clock
     }
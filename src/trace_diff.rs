@@ -0,0 +1,489 @@
+use crate::error::{Error, Result};
+use crate::lcd::FRAME_CYCLES;
+use crate::registers::Registers;
+use crate::tracer::format_registers;
+use crate::{Emulator, Headless};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+// A halted cpu that doesn't wake within this many frames never will
+const MAX_HALTED_FRAMES: u64 = 60;
+
+// The state before an instruction runs. Steps read from a
+// reference log only have the registers and the bytes at pc
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub registers: Registers,
+    pub pcmem: [u8; 4],
+    // Writes by the instruction, and by any interrupt dispatched after it
+    pub writes: Option<Vec<(u16, u8)>>,
+    // 0xff00 - 0xff7f then 0xffff, after the instruction
+    pub io: Option<Vec<u8>>,
+}
+
+pub trait TraceSource {
+    // None once the trace has ended
+    fn next_step(&mut self) -> Result<Option<TraceStep>>;
+}
+
+// Steps through an emulator an instruction at a time. The trace
+// ends if the cpu stops, or halts and never wakes up
+pub struct EmulatorSource {
+    emulator: Emulator,
+}
+
+impl EmulatorSource {
+    pub fn new(mut emulator: Emulator) -> EmulatorSource {
+        emulator.memory.set_recording_accesses(true);
+        EmulatorSource { emulator }
+    }
+
+    pub fn get_emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn into_emulator(mut self) -> Emulator {
+        self.emulator.memory.set_recording_accesses(false);
+        self.emulator
+    }
+}
+
+impl TraceSource for EmulatorSource {
+    fn next_step(&mut self) -> Result<Option<TraceStep>> {
+        let emulator = &mut self.emulator;
+        let halted_at = emulator.get_cycles();
+        loop {
+            if emulator.lcd.is_vblank() {
+                emulator.finish_frame(&mut Headless);
+            }
+            if emulator.cpu.is_stopped() {
+                return Ok(None);
+            }
+            if !emulator.cpu.is_halted() {
+                break;
+            }
+            if emulator.get_cycles() - halted_at > MAX_HALTED_FRAMES * FRAME_CYCLES {
+                return Ok(None);
+            }
+            emulator.tick(&mut Headless);
+        }
+
+        let registers = emulator.cpu.get_registers().clone();
+        let pc = registers.pc;
+        let mut pcmem = [0; 4];
+        for (i, x) in pcmem.iter_mut().enumerate() {
+            *x = emulator.memory.peek_u8(pc.wrapping_add(i as u16));
+        }
+
+        emulator.memory.take_accesses();
+        emulator.tick(&mut Headless);
+        let writes = emulator
+            .memory
+            .take_accesses()
+            .into_iter()
+            .filter(|x| x.write)
+            .map(|x| (x.address, x.value))
+            .collect();
+        let io = io_addresses()
+            .map(|x| emulator.memory.fetch_u8(x))
+            .collect();
+
+        Ok(Some(TraceStep {
+            registers,
+            pcmem,
+            writes: Some(writes),
+            io: Some(io),
+        }))
+    }
+}
+
+fn io_addresses() -> impl Iterator<Item = u16> {
+    (0xff00..=0xff7f).chain(0xffff..=0xffff)
+}
+
+// Reads a log in the TraceFormat::Registers format, like
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+// Blank lines are skipped
+pub struct LogSource<R> {
+    lines: io::Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> LogSource<R> {
+    pub fn new(input: R) -> LogSource<R> {
+        LogSource {
+            lines: input.lines(),
+            line_number: 0,
+        }
+    }
+}
+
+impl<R: BufRead> TraceSource for LogSource<R> {
+    fn next_step(&mut self) -> Result<Option<TraceStep>> {
+        for line in &mut self.lines {
+            let line = line?;
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return match parse_step(&line) {
+                Some(x) => Ok(Some(x)),
+                None => Err(Error::BadTraceLog(format!(
+                    "line {}: {}",
+                    self.line_number, line
+                ))),
+            };
+        }
+        Ok(None)
+    }
+}
+
+fn parse_step(line: &str) -> Option<TraceStep> {
+    let mut registers = Registers::default();
+    let mut pcmem = None;
+    let mut found = 0;
+    for field in line.split_whitespace() {
+        let (name, value) = field.split_once(':')?;
+        if name == "PCMEM" {
+            let bytes: Vec<u8> = value
+                .split(',')
+                .map(|x| u8::from_str_radix(x, 16).ok())
+                .collect::<Option<_>>()?;
+            if bytes.len() != 4 {
+                return None;
+            }
+            pcmem = Some([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            continue;
+        }
+        let register = match name {
+            "A" => &mut registers.a,
+            "F" => &mut registers.f,
+            "B" => &mut registers.b,
+            "C" => &mut registers.c,
+            "D" => &mut registers.d,
+            "E" => &mut registers.e,
+            "H" => &mut registers.h,
+            "L" => &mut registers.l,
+            "SP" => {
+                registers.sp = u16::from_str_radix(value, 16).ok()?;
+                found += 1;
+                continue;
+            }
+            "PC" => {
+                registers.pc = u16::from_str_radix(value, 16).ok()?;
+                found += 1;
+                continue;
+            }
+            _ => return None,
+        };
+        *register = u8::from_str_radix(value, 16).ok()?;
+        found += 1;
+    }
+    if found != 10 {
+        return None;
+    }
+    Some(TraceStep {
+        registers,
+        pcmem: pcmem?,
+        writes: None,
+        io: None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    Registers,
+    // The bytes at pc
+    Memory,
+    Writes,
+    // The first io register that differs
+    Io(u16),
+    // One trace ended before the other
+    Length,
+}
+
+// Writes and io are only compared when both steps have them
+fn compare(a: &TraceStep, b: &TraceStep) -> Option<Mismatch> {
+    if a.registers != b.registers {
+        return Some(Mismatch::Registers);
+    }
+    if a.pcmem != b.pcmem {
+        return Some(Mismatch::Memory);
+    }
+    if let (Some(x), Some(y)) = (&a.writes, &b.writes) {
+        if x != y {
+            return Some(Mismatch::Writes);
+        }
+    }
+    if let (Some(x), Some(y)) = (&a.io, &b.io) {
+        let address = io_addresses()
+            .zip(x.iter().zip(y.iter()))
+            .find(|(_, (x, y))| x != y);
+        if let Some((address, _)) = address {
+            return Some(Mismatch::Io(address));
+        }
+    }
+    None
+}
+
+pub struct Divergence {
+    // Steps are counted from 0
+    pub step: u64,
+    pub mismatch: Mismatch,
+    // The steps of both traces around the divergence, starting at first_step
+    pub first_step: u64,
+    pub context: Vec<(Option<TraceStep>, Option<TraceStep>)>,
+}
+
+impl Divergence {
+    // Steps that match are written once, the others are written for
+    // both traces and marked with a and b
+    pub fn write_report<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        writeln!(
+            output,
+            "traces diverge at step {}, {}",
+            self.step,
+            describe(self.mismatch)
+        )?;
+        for (i, (a, b)) in self.context.iter().enumerate() {
+            let step = self.first_step + i as u64;
+            let same = match (a, b) {
+                (Some(x), Some(y)) => compare(x, y).is_none(),
+                _ => false,
+            };
+            if same {
+                writeln!(output, "  {:>10}  {}", step, format_step(a))?;
+            } else {
+                writeln!(output, "a {:>10}  {}", step, format_step(a))?;
+                writeln!(output, "b {:>10}  {}", step, format_step(b))?;
+            }
+            if step == self.step {
+                self.write_details(a, b, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_details<W: Write>(
+        &self,
+        a: &Option<TraceStep>,
+        b: &Option<TraceStep>,
+        output: &mut W,
+    ) -> io::Result<()> {
+        let (a, b) = match (a, b) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Ok(()),
+        };
+        match self.mismatch {
+            Mismatch::Writes => {
+                writeln!(output, "    a writes {}", format_writes(&a.writes))?;
+                writeln!(output, "    b writes {}", format_writes(&b.writes))?;
+            }
+            Mismatch::Io(address) => {
+                let index = io_addresses().position(|x| x == address).unwrap();
+                let value = |x: &TraceStep| x.io.as_ref().map_or(0, |x| x[index]);
+                writeln!(
+                    output,
+                    "    {:04x} is {:02x} in a and {:02x} in b",
+                    address,
+                    value(a),
+                    value(b)
+                )?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+fn describe(mismatch: Mismatch) -> String {
+    match mismatch {
+        Mismatch::Registers => "the registers differ".to_string(),
+        Mismatch::Memory => "the bytes at pc differ".to_string(),
+        Mismatch::Writes => "the memory writes differ".to_string(),
+        Mismatch::Io(x) => format!("io register {:04x} differs", x),
+        Mismatch::Length => "one trace ended first".to_string(),
+    }
+}
+
+fn format_step(step: &Option<TraceStep>) -> String {
+    match step {
+        Some(x) => format_registers(&x.registers, &x.pcmem),
+        None => "(ended)".to_string(),
+    }
+}
+
+fn format_writes(writes: &Option<Vec<(u16, u8)>>) -> String {
+    match writes {
+        Some(x) if x.is_empty() => "nothing".to_string(),
+        Some(x) => {
+            let writes: Vec<String> = x
+                .iter()
+                .map(|(address, value)| format!("{:04x}={:02x}", address, value))
+                .collect();
+            writes.join(" ")
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+// Steps two traces in lockstep until they diverge
+pub struct TraceDiff {
+    context: usize,
+    max_steps: u64,
+    allow_early_end: bool,
+}
+
+impl Default for TraceDiff {
+    fn default() -> TraceDiff {
+        TraceDiff::new()
+    }
+}
+
+impl TraceDiff {
+    pub fn new() -> TraceDiff {
+        TraceDiff {
+            context: 8,
+            max_steps: u64::MAX,
+            allow_early_end: false,
+        }
+    }
+
+    // Steps to keep before and after the divergence
+    pub fn set_context(&mut self, steps: usize) {
+        self.context = steps;
+    }
+
+    // Give up once this many steps have matched
+    pub fn set_max_steps(&mut self, steps: u64) {
+        self.max_steps = steps;
+    }
+
+    // Stop without a divergence when either trace ends, for
+    // comparing against a log of only part of a run
+    pub fn set_allow_early_end(&mut self, allow: bool) {
+        self.allow_early_end = allow;
+    }
+
+    // Returns None if the traces match to the end, or for max_steps
+    pub fn run<A, B>(&self, a: &mut A, b: &mut B) -> Result<Option<Divergence>>
+    where
+        A: TraceSource,
+        B: TraceSource,
+    {
+        let mut context = VecDeque::new();
+        for step in 0..self.max_steps {
+            let pair = (a.next_step()?, b.next_step()?);
+            let mismatch = match &pair {
+                (None, None) => return Ok(None),
+                (Some(x), Some(y)) => compare(x, y),
+                _ if self.allow_early_end => return Ok(None),
+                _ => Some(Mismatch::Length),
+            };
+            context.push_back(pair);
+
+            if let Some(mismatch) = mismatch {
+                let first_step = step + 1 - context.len() as u64;
+                for _ in 0..self.context {
+                    let pair = (a.next_step()?, b.next_step()?);
+                    if pair == (None, None) {
+                        break;
+                    }
+                    context.push_back(pair);
+                }
+                return Ok(Some(Divergence {
+                    step,
+                    mismatch,
+                    first_step,
+                    context: context.into(),
+                }));
+            }
+            if context.len() > self.context {
+                context.pop_front();
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let line = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02";
+        let step = parse_step(line).unwrap();
+        assert_eq!(step.registers.a, 0x01);
+        assert_eq!(step.registers.l, 0x4d);
+        assert_eq!(step.registers.sp, 0xfffe);
+        assert_eq!(step.pcmem, [0x00, 0xc3, 0x13, 0x02]);
+        assert_eq!(format_registers(&step.registers, &step.pcmem), line);
+
+        assert!(parse_step("A:01 F:B0").is_none());
+        assert!(parse_step(&line.replace("PCMEM:00,", "PCMEM:")).is_none());
+        assert!(parse_step(&line.replace("A:01", "A:x1")).is_none());
+        assert!(parse_step(&line.replace("A:01", "Q:01")).is_none());
+    }
+
+    #[test]
+    fn context_window() {
+        let log = |changed: usize| {
+            let mut log = String::new();
+            for i in 0..20 {
+                let a = if i == changed { 0xff } else { 0 };
+                log.push_str(&format!(
+                    "A:{:02X} F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:{:04X} \
+                     PCMEM:00,00,00,00\n",
+                    a,
+                    0x100 + i
+                ));
+            }
+            log
+        };
+        let mut diff = TraceDiff::new();
+        diff.set_context(3);
+        let result = diff
+            .run(
+                &mut LogSource::new(log(20).as_bytes()),
+                &mut LogSource::new(log(20).as_bytes()),
+            )
+            .unwrap();
+        assert!(result.is_none());
+
+        let result = diff
+            .run(
+                &mut LogSource::new(log(20).as_bytes()),
+                &mut LogSource::new(log(10).as_bytes()),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.step, 10);
+        assert_eq!(result.mismatch, Mismatch::Registers);
+        assert_eq!(result.first_step, 7);
+        assert_eq!(result.context.len(), 7);
+
+        // The shorter trace is padded with None
+        let short = log(20).lines().take(18).collect::<Vec<_>>().join("\n");
+        let result = diff
+            .run(
+                &mut LogSource::new(log(20).as_bytes()),
+                &mut LogSource::new(short.as_bytes()),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.step, 18);
+        assert_eq!(result.mismatch, Mismatch::Length);
+        assert_eq!(result.context.len(), 5);
+        assert!(result.context[4].1.is_none());
+
+        diff.set_allow_early_end(true);
+        let result = diff
+            .run(
+                &mut LogSource::new(log(20).as_bytes()),
+                &mut LogSource::new(short.as_bytes()),
+            )
+            .unwrap();
+        assert!(result.is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::disasm;
+use crate::memory::Memory;
+use crate::registers::Registers;
+use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+    // the format used by reference logs like gameboy-doctor's
+    Registers,
+    // 0100  00        nop
+    Disassembly,
+}
+
+// Writes a line for every instruction before it runs. Instructions at
+// excluded pcs are skipped, and if any pcs are included, only those are
+// written. The first write error stops tracing and is kept for finish
+pub struct Tracer {
+    output: Box<dyn Write + Send>,
+    format: TraceFormat,
+    included: Vec<RangeInclusive<u16>>,
+    excluded: Vec<RangeInclusive<u16>>,
+    cycles: Range<u64>,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + Send + 'static>(output: W) -> Tracer {
+        Tracer {
+            output: Box::new(output),
+            format: TraceFormat::Registers,
+            included: Vec::new(),
+            excluded: Vec::new(),
+            cycles: 0..u64::MAX,
+            error: None,
+        }
+    }
+
+    pub fn set_format(&mut self, format: TraceFormat) {
+        self.format = format;
+    }
+
+    pub fn get_format(&self) -> TraceFormat {
+        self.format
+    }
+
+    pub fn include_pcs(&mut self, range: RangeInclusive<u16>) {
+        self.included.push(range);
+    }
+
+    pub fn exclude_pcs(&mut self, range: RangeInclusive<u16>) {
+        self.excluded.push(range);
+    }
+
+    // Only trace instructions starting within this range of cycles
+    pub fn set_cycle_window(&mut self, cycles: Range<u64>) {
+        self.cycles = cycles;
+    }
+
+    // Flushes the output, returning the first error while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.output.flush()
+    }
+
+    pub(crate) fn is_tracing(&self, pc: u16, cycles: u64) -> bool {
+        self.error.is_none()
+            && self.cycles.contains(&cycles)
+            && (self.included.is_empty() || self.included.iter().any(|x| x.contains(&pc)))
+            && !self.excluded.iter().any(|x| x.contains(&pc))
+    }
+
+    pub(crate) fn trace(&mut self, registers: &Registers, memory: &Memory, cycles: u64) {
+        let pc = registers.pc;
+        if !self.is_tracing(pc, cycles) {
+            return;
+        }
+        let bytes: Vec<u8> = (0..4)
+            .map(|i| memory.peek_u8(pc.wrapping_add(i)))
+            .collect();
+        let result = match self.format {
+            TraceFormat::Registers => {
+                writeln!(self.output, "{}", format_registers(registers, &bytes))
+            }
+            TraceFormat::Disassembly => {
+                writeln!(self.output, "{}", format_disassembly(pc, &bytes))
+            }
+        };
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
+
+// bytes are the 4 bytes at pc
+pub(crate) fn format_registers(registers: &Registers, bytes: &[u8]) -> String {
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+         SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        registers.a,
+        registers.f,
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+        registers.sp,
+        registers.pc,
+        bytes[0],
+        bytes[1],
+        bytes[2],
+        bytes[3]
+    )
+}
+
+fn format_disassembly(pc: u16, bytes: &[u8]) -> String {
+    let instruction = disasm::decode(bytes, pc).unwrap();
+    let hex: Vec<String> = bytes[..usize::from(instruction.length)]
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+    format!("{:04x}  {:<8}  {}", pc, hex.join(" "), instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let registers = Registers {
+            a: 0x01,
+            f: 0xb0,
+            c: 0x13,
+            e: 0xd8,
+            h: 0x01,
+            l: 0x4d,
+            sp: 0xfffe,
+            pc: 0x0100,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_registers(&registers, &[0x00, 0xc3, 0x13, 0x02]),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+        assert_eq!(
+            format_disassembly(0x101, &[0xc3, 0x50, 0x01, 0x00]),
+            "0101  c3 50 01  jp $0150"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let mut tracer = Tracer::new(io::sink());
+        assert!(tracer.is_tracing(0x2b4, 0));
+        tracer.exclude_pcs(0x2b2..=0x2b6);
+        assert!(!tracer.is_tracing(0x2b4, 0));
+        assert!(tracer.is_tracing(0x2b7, 0));
+
+        tracer.include_pcs(0x200..=0x2ff);
+        assert!(!tracer.is_tracing(0x150, 0));
+        assert!(tracer.is_tracing(0x2b7, 0));
+
+        tracer.set_cycle_window(100..200);
+        assert!(!tracer.is_tracing(0x2b7, 99));
+        assert!(tracer.is_tracing(0x2b7, 100));
+        assert!(!tracer.is_tracing(0x2b7, 200));
+    }
+}
@@ -79,7 +79,7 @@ impl App for DummyApp {
 fn run_test_rom(test_rom: &str, max_cycles: u64) {
     let test_rom_path = Path::new("gb-test-roms/cpu_instrs").join(test_rom);
 
-    let mut emulator = Emulator::new(None, test_rom_path.to_str().unwrap());
+    let mut emulator = Emulator::new(None, test_rom_path.to_str().unwrap()).unwrap();
     let mut app = DummyApp {};
     for _ in 0..max_cycles {
         emulator.tick(&mut app);
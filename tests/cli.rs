@@ -0,0 +1,76 @@
+extern crate gb_emu;
+mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// Turns on the LCD and sends "ok" over serial
+fn write_rom(name: &str) -> String {
+    let mut program = vec![
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+    ];
+    for x in b"ok".iter() {
+        program.extend(&[
+            0x3e, *x, // ld a, x
+            0xe0, 0x01, // ldh (0x01), a
+            0x3e, 0x81, // ld a, 0x81
+            0xe0, 0x02, // ldh (0x02), a
+            0xf0, 0x02, // wait: ldh a, (0x02)
+            0xcb, 0x7f, // bit 7, a
+            0x20, 0xfa, // jr nz, wait
+        ]);
+    }
+    program.extend(&[0x18, 0xfe]); // jr -2
+
+    let path = env::temp_dir().join(name);
+    fs::write(&path, common::create_rom(&program)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn gb_emu(args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_gb_emu"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    (output.status.code().unwrap(), stdout)
+}
+
+#[test]
+fn exit_codes() {
+    let rom = write_rom("gb_emu_cli_exit_codes.gb");
+    let (code, stdout) = gb_emu(&[
+        "--frames",
+        "5",
+        "--serial",
+        "-",
+        "--expect-serial",
+        "ok",
+        &rom,
+    ]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "ok");
+    assert_eq!(
+        gb_emu(&["--frames", "5", "--expect-serial", "fail", &rom]).0,
+        1
+    );
+    assert_eq!(gb_emu(&["--frames", "x", &rom]).0, 2);
+    assert_eq!(gb_emu(&["missing.gb"]).0, 2);
+    fs::remove_file(rom).unwrap();
+}
+
+#[test]
+fn screenshot() {
+    let rom = write_rom("gb_emu_cli_screenshot.gb");
+    let path = env::temp_dir().join("gb_emu_cli_screenshot.ppm");
+    let screenshot = format!("2:{}", path.to_str().unwrap());
+    let (code, _) = gb_emu(&["--cycles", "200000", "--screenshot", &screenshot, &rom]);
+    assert_eq!(code, 0);
+
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(path).unwrap();
+    fs::remove_file(rom).unwrap();
+    assert!(data.starts_with(b"P6\n160 144\n255\n"));
+    assert_eq!(data.len(), 15 + 160 * 144 * 3);
+}
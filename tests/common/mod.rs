@@ -0,0 +1,61 @@
+// Shared by the integration tests, which don't all use everything
+#![allow(dead_code)]
+use gb_emu::{App, Command, JoyPad};
+
+// Builds a 32KB rom only cartridge which runs `program` from 0x0150
+pub fn create_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // nop; jp 0x0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x150..0x150 + program.len()].copy_from_slice(program);
+    rom
+}
+
+// Turn on the LCD and send a byte, then store the byte received at 0xc000 and IF at 0xc001
+pub fn create_transfer_rom(data: u8, control: u8) -> Vec<u8> {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x3e, data, // ld a, data
+        0xe0, 0x01, // ldh (0x01), a
+        0x3e, control, // ld a, control
+        0xe0, 0x02, // ldh (0x02), a
+        0xf0, 0x02, // wait: ldh a, (0x02)
+        0xcb, 0x7f, // bit 7, a
+        0x20, 0xfa, // jr nz, wait
+        0xf0, 0x01, // ldh a, (0x01)
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0xf0, 0x0f, // ldh a, (0x0f)
+        0xea, 0x01, 0xc0, // ld (0xc001), a
+        0x18, 0xfe, // jr -2
+    ];
+    create_rom(&program)
+}
+
+// Draws nothing and never stops
+pub struct NullApp;
+
+impl App for NullApp {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        Command::Continue
+    }
+}
+
+// Draws nothing and stops after a number of frames
+pub struct FrameLimit(pub u32);
+
+impl App for FrameLimit {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        self.0 -= 1;
+        if self.0 == 0 {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
@@ -0,0 +1,193 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Access, Comparison, Condition, Debugger, Emulator, Register, StopReason};
+
+fn create_debugger() -> Debugger {
+    let program = [
+        0x3e, 0x91, // 0x150 ld a, 0x91
+        0xe0, 0x40, // 0x152 ldh (0x40), a
+        0xcd, 0x60, 0x01, // 0x154 call 0x0160
+        0x3e, 0x05, // 0x157 ld a, 5
+        0xea, 0x00, 0xc0, // 0x159 ld (0xc000), a
+        0x18, 0xfe, // 0x15c jr -2
+        0x00, 0x00, // 0x15e
+        0x06, 0x42, // 0x160 ld b, 0x42
+        0xfa, 0x10, 0xc0, // 0x162 ld a, (0xc010)
+        0xc9, // 0x165 ret
+    ];
+    let rom = common::create_rom(&program);
+    Debugger::new(Emulator::from_bytes(rom, None).unwrap())
+}
+
+fn get_pc(debugger: &Debugger) -> u16 {
+    debugger.get_register(Register::PC)
+}
+
+#[test]
+fn breakpoint_and_step_over() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(10);
+    let id = debugger.add_breakpoint(0x154, None);
+    assert_eq!(
+        debugger.continue_running(&mut app),
+        StopReason::Breakpoint(id)
+    );
+    assert_eq!(get_pc(&debugger), 0x154);
+
+    assert_eq!(debugger.step_over(&mut app), StopReason::Step);
+    assert_eq!(get_pc(&debugger), 0x157);
+    assert_eq!(debugger.get_register(Register::B), 0x42);
+}
+
+#[test]
+fn step_in_and_out() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(10);
+    debugger.add_breakpoint(0x154, None);
+    debugger.continue_running(&mut app);
+
+    assert_eq!(debugger.step(&mut app), StopReason::Step);
+    assert_eq!(get_pc(&debugger), 0x160);
+    assert_eq!(debugger.step_out(&mut app), StopReason::Step);
+    assert_eq!(get_pc(&debugger), 0x157);
+}
+
+#[test]
+fn banked_breakpoints() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(3);
+    // 0x0154 is in bank 0
+    debugger.add_breakpoint(0x154, Some(1));
+    assert_eq!(debugger.continue_running(&mut app), StopReason::AppStopped);
+
+    let mut debugger = create_debugger();
+    let id = debugger.add_breakpoint(0x154, Some(0));
+    assert_eq!(
+        debugger.continue_running(&mut app),
+        StopReason::Breakpoint(id)
+    );
+}
+
+#[test]
+fn watchpoints() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(10);
+    let read = debugger.add_watchpoint(0xc008..=0xc010, Access::Read);
+    let write = debugger.add_watchpoint(0xc000..=0xc000, Access::Write);
+    let execute = debugger.add_watchpoint(0x15c..=0x15d, Access::Execute);
+
+    let reason = debugger.continue_running(&mut app);
+    let expected = StopReason::Watchpoint {
+        id: read,
+        address: 0xc010,
+        value: 0,
+        access: Access::Read,
+    };
+    assert_eq!(reason, expected);
+    assert_eq!(get_pc(&debugger), 0x165);
+
+    let reason = debugger.continue_running(&mut app);
+    let expected = StopReason::Watchpoint {
+        id: write,
+        address: 0xc000,
+        value: 5,
+        access: Access::Write,
+    };
+    assert_eq!(reason, expected);
+    assert_eq!(get_pc(&debugger), 0x15c);
+
+    // The instruction at 0x15c hasn't run yet, but the first
+    // instruction after stopping is never stopped at
+    let reason = debugger.continue_running(&mut app);
+    let expected = StopReason::Watchpoint {
+        id: execute,
+        address: 0x15c,
+        value: 0x18,
+        access: Access::Execute,
+    };
+    assert_eq!(reason, expected);
+}
+
+#[test]
+fn conditions() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(10);
+    let condition = Condition::new(Register::B, Comparison::Equal, 0x42);
+    let id = debugger.add_condition(condition);
+    assert_eq!(
+        debugger.continue_running(&mut app),
+        StopReason::Condition(id)
+    );
+    assert_eq!(get_pc(&debugger), 0x162);
+    debugger.remove_break(id);
+
+    let condition = Condition::new(Register::A, Comparison::Equal, 5);
+    debugger.add_break(gb_emu::Break::Breakpoint {
+        address: 0x15c,
+        bank: None,
+        condition: Some(condition),
+    });
+    assert!(matches!(
+        debugger.continue_running(&mut app),
+        StopReason::Breakpoint(_)
+    ));
+    assert_eq!(debugger.get_register(Register::A), 5);
+}
+
+#[test]
+fn inspection() {
+    let mut debugger = create_debugger();
+    debugger.set_register(Register::HL, 0x1234);
+    assert_eq!(debugger.get_register(Register::H), 0x12);
+    debugger.set_register(Register::F, 0xff);
+    assert_eq!(debugger.get_register(Register::F), 0xf0);
+
+    debugger.write_memory(0xc000, 0xab);
+    debugger.write_memory(0xc001, 0xcd);
+    assert_eq!(debugger.read_memory_range(0xc000, 2), [0xab, 0xcd]);
+    assert_eq!(debugger.read_memory_range(0x150, 2), [0x3e, 0x91]);
+    assert_eq!(debugger.get_rom_bank(), 1);
+}
+
+#[test]
+fn repl() {
+    let mut debugger = create_debugger();
+    let mut app = common::FrameLimit(10);
+    let input = "break 154\ncontinue\nnext\nregs\nx c000 2\ndis 160 2\nlist\nfoo\nquit\n";
+    let mut output = Vec::new();
+    debugger
+        .run_repl(&mut app, input.as_bytes(), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("1: break 0154"));
+    assert!(output.contains("hit breakpoint 1"));
+    assert!(output.contains("00:0157  3e 05     ld a, $05"));
+    assert!(output.contains("bc=42"));
+    assert!(output.contains("c000: 00 00"));
+    assert!(output.contains("00:0160  06 42     ld b, $42\n00:0162  fa 10 c0  ld a, [$c010]"));
+    assert!(output.contains("unknown command foo"));
+}
+
+#[test]
+fn continue_for_while_stopped() {
+    let program = [
+        0x3e, 0x91, // 0x150 ld a, 0x91
+        0xe0, 0x40, // 0x152 ldh (0x40), a
+        0x10, 0x00, // 0x154 stop
+        0x18, 0xfe, // 0x156 jr -2
+    ];
+    let rom = common::create_rom(&program);
+    let mut debugger = Debugger::new(Emulator::from_bytes(rom, None).unwrap());
+    let mut app = common::FrameLimit(100);
+    // STOP lasts until a button is pressed, but the time still runs out
+    assert_eq!(
+        debugger.continue_for(&mut app, 100_000, false),
+        StopReason::Step
+    );
+    assert_eq!(get_pc(&debugger), 0x156);
+    assert_eq!(
+        debugger.continue_for(&mut app, 100_000, true),
+        StopReason::Step
+    );
+}
@@ -0,0 +1,132 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, JoyPad, Palette, Palettes, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+struct LineCollector {
+    buffer: Vec<u8>,
+    frames: u32,
+}
+
+impl App for LineCollector {
+    fn draw_line(&mut self, line_buffer: &[u8], line_index: u8) {
+        let start = usize::from(line_index) * SCREEN_WIDTH;
+        self.buffer[start..start + SCREEN_WIDTH].copy_from_slice(line_buffer);
+    }
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        self.frames -= 1;
+        if self.frames == 0 {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
+
+// Fills the screen with tile 0, which has colour 1 on its top line
+fn create_emulator() -> Emulator {
+    let program = [
+        0x3e, 0xff, // ld a, 0xff
+        0xea, 0x00, 0x80, // ld (0x8000), a
+        0x3e, 0xe4, // ld a, 0xe4
+        0xe0, 0x47, // ldh (0x47), a
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x18, 0xfe, // jr -2
+    ];
+    Emulator::from_bytes(common::create_rom(&program), None).unwrap()
+}
+
+#[test]
+fn run_frame_without_app() {
+    let mut emulator = create_emulator();
+    assert_eq!(emulator.get_frame_count(), 0);
+    emulator.run_frame();
+    emulator.run_frame();
+    assert_eq!(emulator.get_frame_count(), 2);
+
+    let frame = emulator.get_frame_buffer();
+    assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    assert_eq!(frame[0], 2);
+    assert_eq!(frame[SCREEN_WIDTH], 3);
+    assert_eq!(frame[SCREEN_WIDTH * 8 + 100], 2);
+
+    let rgba = emulator.get_frame_rgba();
+    assert_eq!(rgba.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+    assert_eq!(&rgba[0..4], &[170, 170, 170, 255]);
+    assert_eq!(
+        &rgba[SCREEN_WIDTH * 4..SCREEN_WIDTH * 4 + 4],
+        &[255, 255, 255, 255]
+    );
+}
+
+#[test]
+fn frame_buffer_matches_draw_line() {
+    let mut emulator = create_emulator();
+    let mut app = LineCollector {
+        buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        frames: 3,
+    };
+    emulator.run(&mut app);
+    assert_eq!(emulator.get_frame_count(), 3);
+    assert_eq!(emulator.get_frame_buffer(), &app.buffer[..]);
+}
+
+#[test]
+fn rgba_uses_palettes() {
+    let mut emulator = create_emulator();
+    emulator.set_palettes(Palettes::all(Palette::dmg_green()));
+    emulator.run_frame();
+
+    let rgba = emulator.get_frame_rgba();
+    assert_eq!(&rgba[0..4], &[0x8b, 0xac, 0x0f, 0xff]);
+    assert_eq!(
+        &rgba[SCREEN_WIDTH * 4..SCREEN_WIDTH * 4 + 4],
+        &[0x9b, 0xbc, 0x0f, 0xff]
+    );
+}
+
+#[test]
+fn sprites_use_their_palette() {
+    let program = [
+        0x21, 0x10, 0x80, // ld hl, 0x8010
+        0x06, 0x08, // ld b, 8
+        0x3e, 0xff, // loop: ld a, 0xff
+        0x22, // ld (hl+), a
+        0xaf, // xor a
+        0x22, // ld (hl+), a
+        0x05, // dec b
+        0x20, 0xf8, // jr nz, loop
+        0xe0, 0x47, // ldh (0x47), a
+        0x3e, 0x04, // ld a, 0x04
+        0xe0, 0x48, // ldh (0x48), a
+        0x3e, 0x0c, // ld a, 0x0c
+        0xe0, 0x49, // ldh (0x49), a
+        // Sprite 0 uses OBP1, sprite 1 uses OBP0
+        0x3e, 0x1e, // ld a, 30
+        0xea, 0x00, 0xfe, // ld (0xfe00), a
+        0xea, 0x04, 0xfe, // ld (0xfe04), a
+        0x3e, 0x10, // ld a, 16
+        0xea, 0x01, 0xfe, // ld (0xfe01), a
+        0xea, 0x03, 0xfe, // ld (0xfe03), a
+        0x3e, 0x20, // ld a, 32
+        0xea, 0x05, 0xfe, // ld (0xfe05), a
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x02, 0xfe, // ld (0xfe02), a
+        0xea, 0x06, 0xfe, // ld (0xfe06), a
+        0x3e, 0x93, // ld a, 0x93
+        0xe0, 0x40, // ldh (0x40), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    emulator.run_frame();
+    emulator.run_frame();
+
+    // Colour 1 is black in OBP1 and light grey in OBP0, over a white background
+    let frame = emulator.get_frame_buffer();
+    let line = (0..SCREEN_HEIGHT)
+        .find(|y| frame[y * SCREEN_WIDTH + 8] != 3)
+        .unwrap();
+    assert_eq!(frame[line * SCREEN_WIDTH + 8], 0);
+    assert_eq!(frame[line * SCREEN_WIDTH + 24], 2);
+}
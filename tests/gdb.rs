@@ -0,0 +1,100 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Debugger, Emulator, GdbStub};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn create_rom() -> Vec<u8> {
+    let program = [
+        0x3e, 0x91, // 0x150 ld a, 0x91
+        0xe0, 0x40, // 0x152 ldh (0x40), a
+        0x3e, 0x07, // 0x154 ld a, 7
+        0xea, 0x00, 0xc0, // 0x156 ld (0xc000), a
+        0x18, 0xfe, // 0x159 jr -2
+    ];
+    common::create_rom(&program)
+}
+
+// Sends a packet and returns the reply. Acks from the stub are skipped,
+// and every reply is acked, which the stub ignores in no ack mode
+fn request(stream: &mut TcpStream, data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |x, y| x.wrapping_add(y));
+    write!(stream, "${}#{:02x}", data, checksum).unwrap();
+    read_reply(stream)
+}
+
+fn read_reply(stream: &mut TcpStream) -> String {
+    let mut byte = [0];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+    let mut reply = Vec::new();
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'#' {
+            break;
+        }
+        reply.push(byte[0]);
+    }
+    let mut checksum = [0; 2];
+    stream.read_exact(&mut checksum).unwrap();
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(reply).unwrap()
+}
+
+#[test]
+fn gdb_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let stub = thread::spawn(move || {
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        let emulator = Emulator::from_bytes(create_rom(), None).unwrap();
+        let mut debugger = Debugger::new(emulator);
+        stub.run(&mut debugger, &mut common::NullApp).unwrap();
+        // The stub's breaks are removed when gdb detaches
+        debugger.get_breaks().len()
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    let supported = request(&mut stream, "qSupported:swbreak+");
+    assert!(supported.contains("qXfer:features:read+"));
+    assert_eq!(request(&mut stream, "QStartNoAckMode"), "OK");
+    let xml = request(&mut stream, "qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert_eq!(request(&mut stream, "?"), "S05");
+
+    // Registers are af, bc, de, hl, sp and pc, little endian
+    assert_eq!(request(&mut stream, "Z0,154,1"), "OK");
+    assert_eq!(request(&mut stream, "c"), "S05");
+    let registers = request(&mut stream, "g");
+    assert_eq!(registers.len(), 24);
+    assert_eq!(&registers[16..], "feff5401");
+    assert_eq!(request(&mut stream, "s"), "S05");
+    assert_eq!(request(&mut stream, "p5"), "5601");
+    assert_eq!(request(&mut stream, "p0")[2..], *"07");
+
+    assert_eq!(request(&mut stream, "Z2,c000,1"), "OK");
+    assert_eq!(request(&mut stream, "c"), "T05watch:c000;");
+    assert_eq!(request(&mut stream, "mc000,1"), "07");
+    assert_eq!(request(&mut stream, "Mc001,2:abcd"), "OK");
+    assert_eq!(request(&mut stream, "mc000,3"), "07abcd");
+    assert_eq!(request(&mut stream, "z2,c000,1"), "OK");
+    assert_eq!(request(&mut stream, "z0,154,1"), "OK");
+
+    // Runs the jr loop until interrupted
+    write!(stream, "$c#63").unwrap();
+    stream.write_all(&[0x03]).unwrap();
+    assert_eq!(read_reply(&mut stream), "S02");
+    assert_eq!(request(&mut stream, "P5=5001"), "OK");
+    assert_eq!(request(&mut stream, "p5"), "5001");
+    assert_eq!(request(&mut stream, "vMustReplyEmpty"), "");
+
+    assert_eq!(request(&mut stream, "Z0,154,1"), "OK");
+    assert_eq!(request(&mut stream, "D"), "OK");
+    assert_eq!(stub.join().unwrap(), 0);
+}
@@ -0,0 +1,135 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, JoyPad};
+
+// Presses a button from one frame, and stops after another
+struct PressAt {
+    frame: u32,
+    press: u32,
+    stop: u32,
+    direction: bool,
+}
+
+impl PressAt {
+    fn new(press: u32, stop: u32) -> PressAt {
+        PressAt {
+            frame: 0,
+            press,
+            stop,
+            direction: false,
+        }
+    }
+}
+
+impl App for PressAt {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, joypad: &mut JoyPad) -> Command {
+        self.frame += 1;
+        if self.direction {
+            joypad.set_right(self.frame >= self.press);
+        } else {
+            joypad.set_a(self.frame >= self.press);
+        }
+        if self.frame == self.stop {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
+
+// Selects the buttons and enables the joypad interrupt, which
+// writes 1 to 0xc000
+fn create_interrupt_rom() -> Vec<u8> {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x3e, 0x10, // ld a, 0x10
+        0xe0, 0x00, // ldh (0x00), a
+        0xea, 0xff, 0xff, // ld (0xffff), a
+        0xfb, // ei
+        0x18, 0xfe, // jr -2
+    ];
+    let mut rom = common::create_rom(&program);
+    let handler = [
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0xd9, // reti
+    ];
+    rom[0x60..0x66].copy_from_slice(&handler);
+    rom
+}
+
+#[test]
+fn interrupt_on_press() {
+    let mut emulator = Emulator::from_bytes(create_interrupt_rom(), None).unwrap();
+    emulator.run(&mut PressAt::new(3, 2));
+    assert_eq!(emulator.read_memory(0xc000), 0);
+    emulator.run(&mut PressAt::new(1, 2));
+    assert_eq!(emulator.read_memory(0xc000), 1);
+}
+
+#[test]
+fn unselected_lines_dont_interrupt() {
+    let mut emulator = Emulator::from_bytes(create_interrupt_rom(), None).unwrap();
+    let mut app = PressAt::new(1, 3);
+    app.direction = true;
+    emulator.run(&mut app);
+    assert_eq!(emulator.read_memory(0xc000), 0);
+}
+
+#[test]
+fn stop_until_press() {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x3e, 0x10, // ld a, 0x10
+        0xe0, 0x00, // ldh (0x00), a
+        0x06, 0x00, // ld b, 0
+        0x05, // delay: dec b
+        0x20, 0xfd, // jr nz, delay
+        0x10, 0x00, // stop
+        0xf0, 0x04, // ldh a, (0x04)
+        0xea, 0x01, 0xc0, // ld (0xc001), a
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    emulator.run(&mut PressAt::new(10, 3));
+    assert_eq!(emulator.read_memory(0xc000), 0);
+    let stopped_at = emulator.get_registers().pc;
+
+    // Frames keep ending while stopped, but the cpu doesn't move
+    emulator.run(&mut PressAt::new(10, 3));
+    assert_eq!(emulator.get_registers().pc, stopped_at);
+
+    emulator.run(&mut PressAt::new(1, 2));
+    assert_eq!(emulator.read_memory(0xc000), 1);
+    // DIV was reset by STOP and hasn't run since
+    assert!(emulator.read_memory(0xc001) <= 1);
+}
+
+#[test]
+fn stop_with_both_lines_selected() {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0xaf, // xor a
+        0xe0, 0x00, // ldh (0x00), a
+        0x10, 0x00, // stop
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    emulator.run(&mut PressAt::new(10, 3));
+    assert_eq!(emulator.read_memory(0xc000), 0);
+
+    // Either a button or a direction wakes the cpu
+    let mut app = PressAt::new(1, 2);
+    app.direction = true;
+    emulator.run(&mut app);
+    assert_eq!(emulator.read_memory(0xc000), 1);
+}
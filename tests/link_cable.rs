@@ -0,0 +1,52 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Emulator, LinkCable};
+
+#[test]
+fn bytes_are_exchanged() {
+    let master = Emulator::from_bytes(common::create_transfer_rom(0x42, 0x81), None).unwrap();
+    let slave = Emulator::from_bytes(common::create_transfer_rom(0x24, 0x80), None).unwrap();
+    let mut cable = LinkCable::new(master, slave);
+    cable.run(&mut common::FrameLimit(2), &mut common::FrameLimit(2));
+
+    let (master, slave) = cable.disconnect();
+    assert_eq!(master.get_serial_data(), &[0x42]);
+    assert_eq!(slave.get_serial_data(), &[0x24]);
+    assert_eq!(master.read_memory(0xc000), 0x24);
+    assert_eq!(slave.read_memory(0xc000), 0x42);
+    assert_eq!(master.read_memory(0xc001) & 0b1000, 0b1000);
+    assert_eq!(slave.read_memory(0xc001) & 0b1000, 0b1000);
+}
+
+#[test]
+fn slave_not_ready() {
+    // The second side never starts a transfer, so it isn't clocked
+    let master = Emulator::from_bytes(common::create_transfer_rom(0x42, 0x81), None).unwrap();
+    let idle = Emulator::from_bytes(common::create_transfer_rom(0x24, 0x00), None).unwrap();
+    let mut cable = LinkCable::new(master, idle);
+    cable.run(&mut common::FrameLimit(2), &mut common::FrameLimit(2));
+
+    assert_eq!(cable.get_first().read_memory(0xc000), 0xff);
+    assert!(cable.get_second().get_serial_data().is_empty());
+}
+
+#[test]
+fn stopped_side_keeps_time() {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x10, 0x00, // stop
+        0x18, 0xfe, // jr -2
+    ];
+    let stopped = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    let master = Emulator::from_bytes(common::create_transfer_rom(0x42, 0x81), None).unwrap();
+    let mut cable = LinkCable::new(stopped, master);
+    // Only the second side's limit can end the run
+    cable.run(
+        &mut common::FrameLimit(u32::MAX),
+        &mut common::FrameLimit(2),
+    );
+
+    assert_eq!(cable.get_second().read_memory(0xc000), 0xff);
+    assert!(cable.get_first().get_frame_count() <= 3);
+}
@@ -0,0 +1,39 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Emulator, Error};
+
+#[test]
+fn truncated_rom() {
+    let rom = vec![0; 0x100];
+    match Emulator::from_bytes(rom, None) {
+        Err(Error::TruncatedRom(0x100)) => (),
+        _ => panic!("expected truncated rom error"),
+    }
+}
+
+#[test]
+fn unsupported_mapper() {
+    let mut rom = common::create_rom(&[]);
+    rom[0x147] = 0xfc;
+    match Emulator::from_bytes(rom, None) {
+        Err(Error::UnsupportedMapper(0xfc)) => (),
+        _ => panic!("expected unsupported mapper error"),
+    }
+}
+
+#[test]
+fn bad_boot_rom_size() {
+    let rom = common::create_rom(&[]);
+    match Emulator::from_bytes(rom, Some(vec![0; 0x200])) {
+        Err(Error::BadBootRomSize(0x200)) => (),
+        _ => panic!("expected bad boot rom size error"),
+    }
+}
+
+#[test]
+fn missing_file() {
+    match Emulator::new(None, "does/not/exist.gb") {
+        Err(Error::Io(_)) => (),
+        _ => panic!("expected io error"),
+    }
+}
@@ -0,0 +1,133 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, JoyPad, Movie, MovieStart};
+
+// Presses A on every third frame, stopping after `frames` frames
+struct Player {
+    frame: u64,
+    frames: u64,
+    press_a: bool,
+}
+
+impl Player {
+    fn new(frames: u64, press_a: bool) -> Player {
+        Player {
+            frame: 0,
+            frames,
+            press_a,
+        }
+    }
+}
+
+impl App for Player {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, joypad: &mut JoyPad) -> Command {
+        joypad.set_a(self.press_a && self.frame % 3 == 1);
+        self.frame += 1;
+        if self.frame == self.frames {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
+
+// Counts the frames where A is held at 0xc000
+fn create_emulator() -> Emulator {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0xf0, 0x44, // wait: ldh a, (0x44)
+        0xfe, 0x90, // cp 0x90
+        0x20, 0xfa, // jr nz, wait
+        0x3e, 0x10, // ld a, 0x10
+        0xe0, 0x00, // ldh (0x00), a
+        0xf0, 0x00, // ldh a, (0x00)
+        0xea, 0x01, 0xc0, // ld (0xc001), a
+        0x1f, // rra
+        0x38, 0x04, // jr c, skip
+        0x21, 0x00, 0xc0, // ld hl, 0xc000
+        0x34, // inc (hl)
+        0xf0, 0x44, // skip: ldh a, (0x44)
+        0xfe, 0x90, // cp 0x90
+        0x28, 0xfa, // jr z, skip
+        0x18, 0xe2, // jr wait
+    ];
+    Emulator::from_bytes(common::create_rom(&program), None).unwrap()
+}
+
+fn record(frames: u64) -> (Movie, Emulator) {
+    let mut emulator = create_emulator();
+    emulator.start_movie_recording();
+    emulator.run(&mut Player::new(frames, true));
+    let movie = emulator.stop_movie_recording().unwrap();
+    (movie, emulator)
+}
+
+#[test]
+fn playback_from_power_on() {
+    let (movie, recorded) = record(200);
+    assert_eq!(movie.get_frame_count(), 200);
+    assert!(recorded.read_memory(0xc000) > 60);
+
+    let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    let mut emulator = create_emulator();
+    emulator.play_movie(movie).unwrap();
+    emulator.run(&mut Player::new(200, false));
+    assert!(!emulator.is_movie_playing());
+    assert_eq!(emulator.get_movie_desync(), None);
+    assert_eq!(emulator.read_memory(0xc000), recorded.read_memory(0xc000));
+}
+
+#[test]
+fn playback_from_save_state() {
+    let mut emulator = create_emulator();
+    emulator.run(&mut Player::new(30, true));
+    emulator.start_movie_recording();
+    emulator.run(&mut Player::new(100, true));
+    let movie = emulator.stop_movie_recording().unwrap();
+    let count = emulator.read_memory(0xc000);
+    match movie.get_start() {
+        MovieStart::SaveState(_) => (),
+        MovieStart::PowerOn => panic!("expected a save state start"),
+    }
+
+    let mut emulator = create_emulator();
+    emulator.play_movie(movie).unwrap();
+    emulator.run(&mut Player::new(100, false));
+    assert_eq!(emulator.get_movie_desync(), None);
+    assert_eq!(emulator.read_memory(0xc000), count);
+}
+
+#[test]
+fn desync() {
+    let (movie, _) = record(200);
+    // Release A on the 8th frame
+    let mut data = movie.to_bytes();
+    let index = data.len() - 200 + 7;
+    assert_eq!(data[index], 0x01);
+    data[index] = 0;
+
+    let mut emulator = create_emulator();
+    emulator
+        .play_movie(Movie::from_bytes(&data).unwrap())
+        .unwrap();
+    emulator.run(&mut Player::new(200, false));
+    assert_eq!(emulator.get_movie_desync(), Some(60));
+}
+
+#[test]
+fn no_rewind_while_recording() {
+    let mut emulator = create_emulator();
+    emulator.enable_rewind(1, 16 * 1024 * 1024);
+    emulator.start_movie_recording();
+    emulator.run(&mut Player::new(20, true));
+    assert_eq!(emulator.rewind(5), 0);
+    assert_eq!(emulator.get_frame_count(), 20);
+
+    let movie = emulator.stop_movie_recording().unwrap();
+    assert_eq!(movie.get_frame_count(), 20);
+    assert_eq!(emulator.rewind(5), 5);
+    assert_eq!(emulator.get_frame_count(), 15);
+}
@@ -0,0 +1,79 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Emulator, LinkCable, PRINTER_WIDTH};
+
+// Header, command, compression, length, data and checksum,
+// then the two bytes the printer answers
+fn packet(command: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x88, 0x33, command, 0];
+    packet.extend(&(data.len() as u16).to_le_bytes());
+    packet.extend(data);
+    let checksum = packet[2..]
+        .iter()
+        .fold(0u16, |sum, x| sum.wrapping_add(u16::from(*x)));
+    packet.extend(&checksum.to_le_bytes());
+    packet.extend(&[0, 0]);
+    packet
+}
+
+// Sends every byte of `data` on the internal clock, then writes 1 to 0xc000
+fn create_sender_rom(data: &[u8]) -> Vec<u8> {
+    let length = (data.len() as u16).to_le_bytes();
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x21, 0x00, 0x10, // ld hl, 0x1000
+        0x01, length[0], length[1], // ld bc, length
+        0x2a,      // next: ld a, (hl+)
+        0xe0, 0x01, // ldh (0x01), a
+        0x3e, 0x81, // ld a, 0x81
+        0xe0, 0x02, // ldh (0x02), a
+        0xf0, 0x02, // wait: ldh a, (0x02)
+        0xcb, 0x7f, // bit 7, a
+        0x20, 0xfa, // jr nz, wait
+        0x0b, // dec bc
+        0x78, // ld a, b
+        0xb1, // or c
+        0x20, 0xee, // jr nz, next
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut rom = common::create_rom(&program);
+    rom[0x1000..0x1000 + data.len()].copy_from_slice(data);
+    rom
+}
+
+#[test]
+fn print_from_rom() {
+    // One row of tiles, all colour 1
+    let tiles: Vec<u8> = (0..20 * 8).flat_map(|_| vec![0xff, 0x00]).collect();
+    let mut data = packet(0x01, &[]);
+    data.extend(packet(0x04, &tiles));
+    data.extend(packet(0x04, &[]));
+    data.extend(packet(0x02, &[1, 0x13, 0xe4, 0x40]));
+
+    let mut emulator = Emulator::from_bytes(create_sender_rom(&data), None).unwrap();
+    emulator.attach_printer();
+    emulator.run(&mut common::FrameLimit(60));
+    assert_eq!(emulator.read_memory(0xc000), 1);
+
+    // Colour 1 is light grey in the 0xe4 palette
+    let printer = emulator.get_printer().unwrap();
+    assert_eq!(printer.get_image_height(), 8);
+    assert_eq!(printer.get_image().len(), PRINTER_WIDTH * 8);
+    assert!(printer.get_image().iter().all(|x| *x == 0xaa));
+
+    assert!(emulator.detach_printer().is_some());
+    assert!(emulator.get_printer().is_none());
+}
+
+#[test]
+fn detach_without_printer_keeps_cable() {
+    let master = Emulator::from_bytes(common::create_transfer_rom(0x42, 0x81), None).unwrap();
+    let slave = Emulator::from_bytes(common::create_transfer_rom(0x24, 0x80), None).unwrap();
+    let mut cable = LinkCable::new(master, slave);
+    assert!(cable.get_first_mut().detach_printer().is_none());
+    cable.run(&mut common::FrameLimit(2), &mut common::FrameLimit(2));
+    assert_eq!(cable.get_first().read_memory(0xc000), 0x24);
+}
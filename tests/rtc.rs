@@ -0,0 +1,60 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, JoyPad};
+
+// Stays in STOP until a press, then stops after a few more frames
+struct WakeAfter {
+    frame: u32,
+    press: u32,
+}
+
+impl App for WakeAfter {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, joypad: &mut JoyPad) -> Command {
+        self.frame += 1;
+        joypad.set_a(self.frame >= self.press);
+        if self.frame == self.press + 5 {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
+
+#[test]
+fn clock_runs_while_stopped() {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x3e, 0x0a, // ld a, 0x0a
+        0xea, 0x00, 0x00, // ld (0x0000), a
+        0x3e, 0x10, // ld a, 0x10
+        0xe0, 0x00, // ldh (0x00), a
+        0x10, 0x00, // stop
+        0xaf, // xor a
+        0xea, 0x00, 0x60, // ld (0x6000), a
+        0x3c, // inc a
+        0xea, 0x00, 0x60, // ld (0x6000), a
+        0x3e, 0x08, // ld a, 0x08
+        0xea, 0x00, 0x40, // ld (0x4000), a
+        0xfa, 0x00, 0xa0, // ld a, (0xa000)
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0x3e, 0x01, // ld a, 1
+        0xea, 0x01, 0xc0, // ld (0xc001), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut rom = common::create_rom(&program);
+    // MBC3 with a timer, ram and battery, and 8KB of ram
+    rom[0x147] = 0x10;
+    rom[0x149] = 0x02;
+    let mut emulator = Emulator::from_bytes(rom, None).unwrap();
+
+    // Over two seconds of frames pass in STOP mode
+    emulator.run(&mut WakeAfter {
+        frame: 0,
+        press: 130,
+    });
+    assert_eq!(emulator.read_memory(0xc001), 1);
+    assert_eq!(emulator.read_memory(0xc000), 2);
+}
@@ -0,0 +1,108 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, Error, JoyPad};
+
+struct LineRecorder {
+    lines: Vec<u8>,
+}
+
+impl App for LineRecorder {
+    fn draw_line(&mut self, line_buffer: &[u8], line_index: u8) {
+        self.lines.push(line_index);
+        self.lines.extend(line_buffer);
+    }
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        Command::Continue
+    }
+}
+
+fn create_emulator() -> Emulator {
+    let program = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (0x40), a
+        0x21, 0x00, 0xc0, // ld hl, 0xc000
+        0x04, // loop: inc b
+        0x78, // ld a, b
+        0x22, // ld (hl+), a
+        0x7c, // ld a, h
+        0xfe, 0xd0, // cp 0xd0
+        0x20, 0xf8, // jr nz, loop
+        0x26, 0xc0, // ld h, 0xc0
+        0x18, 0xf4, // jr loop
+    ];
+    let rom = common::create_rom(&program);
+    Emulator::from_bytes(rom, None).unwrap()
+}
+
+fn run(emulator: &mut Emulator, ticks: usize) -> Vec<u8> {
+    let mut app = LineRecorder { lines: Vec::new() };
+    for _ in 0..ticks {
+        emulator.tick(&mut app);
+    }
+    app.lines
+}
+
+#[test]
+fn restore_is_deterministic() {
+    let mut emulator = create_emulator();
+    run(&mut emulator, 50_000);
+    let state = emulator.save_state();
+
+    let lines = run(&mut emulator, 100_000);
+    let end_state = emulator.save_state();
+    assert!(!lines.is_empty());
+
+    // Into the same emulator, and into a fresh one
+    emulator.load_state(&state).unwrap();
+    assert_eq!(emulator.save_state(), state);
+    assert_eq!(run(&mut emulator, 100_000), lines);
+    assert_eq!(emulator.save_state(), end_state);
+
+    let mut other = create_emulator();
+    other.load_state(&state).unwrap();
+    assert_eq!(run(&mut other, 100_000), lines);
+    assert_eq!(other.save_state(), end_state);
+}
+
+#[test]
+fn bad_state_is_rejected() {
+    let mut emulator = create_emulator();
+    run(&mut emulator, 1000);
+    let state = emulator.save_state();
+
+    let truncated = &state[..state.len() - 1];
+    match emulator.load_state(truncated) {
+        Err(Error::BadSaveState(_)) => (),
+        _ => panic!("expected bad save state error"),
+    }
+    assert_eq!(emulator.save_state(), state);
+
+    let mut rom = common::create_rom(&[]);
+    rom[0x14e] = 0x12;
+    let mut other = Emulator::from_bytes(rom, None).unwrap();
+    match other.load_state(&state) {
+        Err(Error::BadSaveState(_)) => (),
+        _ => panic!("expected a different rom to be rejected"),
+    }
+}
+
+#[test]
+fn rewind() {
+    let mut emulator = create_emulator();
+    emulator.enable_rewind(1, 16 * 1024 * 1024);
+    emulator.run(&mut common::FrameLimit(5));
+    let state = emulator.save_state();
+    emulator.run(&mut common::FrameLimit(5));
+    assert_eq!(emulator.get_rewind_frames(), 9);
+
+    assert_eq!(emulator.get_frame_count(), 10);
+
+    // The frame count goes back with the rest of the machine
+    assert_eq!(emulator.rewind(5), 5);
+    assert_eq!(emulator.save_state(), state);
+    assert_eq!(emulator.get_frame_count(), 5);
+    assert_eq!(emulator.rewind(100), 4);
+    assert_eq!(emulator.get_frame_count(), 1);
+    assert_eq!(emulator.get_rewind_frames(), 0);
+}
@@ -0,0 +1,70 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::Emulator;
+
+#[test]
+fn internal_clock_transfer() {
+    let program = [
+        0x3e, 0x42, // ld a, 0x42
+        0xe0, 0x01, // ldh (0x01), a
+        0x3e, 0x81, // ld a, 0x81
+        0xe0, 0x02, // ldh (0x02), a
+        0xf0, 0x02, // wait: ldh a, (0x02)
+        0xcb, 0x7f, // bit 7, a
+        0x20, 0xfa, // jr nz, wait
+        0xf0, 0x01, // ldh a, (0x01)
+        0xea, 0x00, 0xc0, // ld (0xc000), a
+        0xf0, 0x0f, // ldh a, (0x0f)
+        0xea, 0x01, 0xc0, // ld (0xc001), a
+        0x18, 0xfe, // jr -2
+    ];
+    let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    for _ in 0..5000 {
+        emulator.tick(&mut common::NullApp);
+    }
+
+    // Nothing is connected, so 0xff is shifted in
+    assert_eq!(emulator.get_serial_data(), &[0x42]);
+    assert_eq!(emulator.read_memory(0xc000), 0xff);
+    assert_eq!(emulator.read_memory(0xc001) & 0b1000, 0b1000);
+}
+
+#[test]
+fn printer_responds() {
+    let program = [
+        0x21, 0x00, 0x03, // ld hl, 0x0300
+        0x11, 0x00, 0xc0, // ld de, 0xc000
+        0x06, 0x0a, // ld b, 10
+        0x2a, // send: ld a, (hl+)
+        0xe0, 0x01, // ldh (0x01), a
+        0x3e, 0x81, // ld a, 0x81
+        0xe0, 0x02, // ldh (0x02), a
+        0xf0, 0x02, // wait: ldh a, (0x02)
+        0xcb, 0x7f, // bit 7, a
+        0x20, 0xfa, // jr nz, wait
+        0xf0, 0x01, // ldh a, (0x01)
+        0x12, // ld (de), a
+        0x13, // inc de
+        0x05, // dec b
+        0x20, 0xec, // jr nz, send
+        0x18, 0xfe, // jr -2
+    ];
+    let mut rom = common::create_rom(&program);
+    // An init packet
+    let packet = [0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+    rom[0x300..0x30a].copy_from_slice(&packet);
+
+    let mut emulator = Emulator::from_bytes(rom, None).unwrap();
+    emulator.attach_printer();
+    for _ in 0..20000 {
+        emulator.tick(&mut common::NullApp);
+    }
+
+    assert_eq!(emulator.get_serial_data(), &packet);
+    for i in 0..8 {
+        assert_eq!(emulator.read_memory(0xc000 + i), 0x00);
+    }
+    assert_eq!(emulator.read_memory(0xc008), 0x81);
+    assert_eq!(emulator.read_memory(0xc009), 0x00);
+    assert!(emulator.detach_printer().is_some());
+}
@@ -0,0 +1,84 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{App, Command, Emulator, JoyPad, TcpLink};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// Runs the emulator for a number of frames, returning the byte received
+fn run_linked(mut link: TcpLink, rom: Vec<u8>, frames: u32) -> u8 {
+    let mut emulator = Emulator::from_bytes(rom, None).unwrap();
+    link.run(&mut emulator, &mut common::FrameLimit(frames))
+        .unwrap();
+    emulator.read_memory(0xc000)
+}
+
+#[test]
+fn bytes_are_exchanged() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let slave = thread::spawn(move || {
+        let link = TcpLink::accept(&listener).unwrap();
+        run_linked(link, common::create_transfer_rom(0x24, 0x80), 3)
+    });
+
+    let link = TcpLink::connect(address).unwrap();
+    let master_received = run_linked(link, common::create_transfer_rom(0x42, 0x81), 2);
+    assert_eq!(master_received, 0x24);
+    assert_eq!(slave.join().unwrap(), 0x42);
+}
+
+// Runs until done is set, or for MAX_FRAMES
+struct UntilDone {
+    done: Arc<AtomicBool>,
+    frames: u32,
+}
+
+const MAX_FRAMES: u32 = 600;
+
+impl App for UntilDone {
+    fn draw_line(&mut self, _line_buffer: &[u8], _line_index: u8) {}
+
+    fn update(&mut self, _joypad: &mut JoyPad) -> Command {
+        self.frames += 1;
+        if self.done.load(Ordering::SeqCst) || self.frames == MAX_FRAMES {
+            Command::Stop
+        } else {
+            Command::Continue
+        }
+    }
+}
+
+#[test]
+fn stopped_side_keeps_syncing() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let mut app = UntilDone {
+        done: done.clone(),
+        frames: 0,
+    };
+    let stopped = thread::spawn(move || {
+        let program = [
+            0x3e, 0x91, // ld a, 0x91
+            0xe0, 0x40, // ldh (0x40), a
+            0x10, 0x00, // stop
+            0x18, 0xfe, // jr -2
+        ];
+        let mut link = TcpLink::accept(&listener).unwrap();
+        let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+        link.run(&mut emulator, &mut app).unwrap();
+        app.frames
+    });
+
+    // The first side only finishes its 2 frames if the
+    // stopped side keeps answering syncs
+    let link = TcpLink::connect(address).unwrap();
+    assert_eq!(
+        run_linked(link, common::create_transfer_rom(0x42, 0x81), 2),
+        0xff
+    );
+    done.store(true, Ordering::SeqCst);
+    assert!(stopped.join().unwrap() < MAX_FRAMES);
+}
@@ -0,0 +1,129 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Debugger, Emulator, EmulatorSource, LogSource, Mismatch, TraceDiff, Tracer};
+use std::env;
+use std::fs::{self, File};
+use std::process::Command;
+
+fn create_rom() -> Vec<u8> {
+    let program = [
+        0x3e, 0x91, // 0x150 ld a, 0x91
+        0xe0, 0x40, // 0x152 ldh (0x40), a
+        0x21, 0x00, 0xc0, // 0x154 ld hl, 0xc000
+        0x34, // 0x157 inc (hl)
+        0x18, 0xfd, // 0x158 jr -3
+    ];
+    common::create_rom(&program)
+}
+
+// Changes memory before the emulator starts
+fn create_emulator(writes: &[(u16, u8)]) -> Emulator {
+    let mut debugger = Debugger::new(Emulator::from_bytes(create_rom(), None).unwrap());
+    for &(address, value) in writes {
+        debugger.write_memory(address, value);
+    }
+    debugger.into_emulator()
+}
+
+fn diff(a: Emulator, b: Emulator) -> Option<gb_emu::Divergence> {
+    let mut diff = TraceDiff::new();
+    diff.set_max_steps(10_000);
+    diff.set_context(2);
+    diff.run(&mut EmulatorSource::new(a), &mut EmulatorSource::new(b))
+        .unwrap()
+}
+
+#[test]
+fn lockstep() {
+    assert!(diff(create_emulator(&[]), create_emulator(&[])).is_none());
+
+    let divergence = diff(create_emulator(&[]), create_emulator(&[(0xc000, 7)])).unwrap();
+    assert_eq!(divergence.mismatch, Mismatch::Writes);
+    let (a, b) = &divergence.context[2];
+    assert_eq!(a.as_ref().unwrap().registers.pc, 0x157);
+    assert_eq!(a.as_ref().unwrap().writes, Some(vec![(0xc000, 1)]));
+    assert_eq!(b.as_ref().unwrap().writes, Some(vec![(0xc000, 8)]));
+
+    let mut report = Vec::new();
+    divergence.write_report(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.contains(&format!(
+        "traces diverge at step {}, the memory writes differ",
+        divergence.step
+    )));
+    assert!(report.contains("    a writes c000=01\n    b writes c000=08\n"));
+
+    let divergence = diff(create_emulator(&[]), create_emulator(&[(0xff42, 3)])).unwrap();
+    assert_eq!(divergence.step, 0);
+    assert_eq!(divergence.mismatch, Mismatch::Io(0xff42));
+}
+
+#[test]
+fn against_log() {
+    let path = env::temp_dir().join("gb_emu_trace_diff.log");
+    let mut emulator = create_emulator(&[]);
+    emulator.set_tracer(Some(Tracer::new(File::create(&path).unwrap())));
+    emulator.run_frame();
+    emulator.take_tracer().unwrap().finish().unwrap();
+
+    let log = fs::read_to_string(&path).unwrap();
+    let mut diff = TraceDiff::new();
+    diff.set_allow_early_end(true);
+    let mut emulator = EmulatorSource::new(create_emulator(&[]));
+    let result = diff
+        .run(&mut emulator, &mut LogSource::new(log.as_bytes()))
+        .unwrap();
+    assert!(result.is_none());
+
+    // The binary stops where the log ends, and at the changed pc
+    let rom = env::temp_dir().join("gb_emu_trace_diff.gb");
+    fs::write(&rom, create_rom()).unwrap();
+    let rom = rom.to_str().unwrap();
+    let log_path = path.to_str().unwrap();
+    let (code, stdout) = trace_diff(&[rom, "--log", log_path]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "traces match\n");
+
+    let mut lines: Vec<String> = log.lines().map(String::from).collect();
+    let pc = lines[100].find("PC:").unwrap();
+    lines[100].replace_range(pc..pc + 7, "PC:DEAD");
+    fs::write(&path, lines.join("\n")).unwrap();
+    let (code, stdout) = trace_diff(&[rom, "--log", log_path, "--context", "1"]);
+    assert_eq!(code, 1);
+    let report: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        report[0],
+        "traces diverge at step 100, the registers differ"
+    );
+    assert!(report[1].starts_with("          99  A:"));
+    assert!(report[2].starts_with("a        100  A:"));
+    assert!(report[3].contains("PC:DEAD"));
+    assert!(report[4].starts_with("         101  A:"));
+
+    assert_eq!(trace_diff(&[rom, "--log", log_path, rom]).0, 2);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(rom).unwrap();
+}
+
+fn trace_diff(args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_trace_diff"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    (output.status.code().unwrap(), stdout)
+}
+
+#[test]
+fn end_of_oam() {
+    // A loop in the last bytes of OAM, just before the unusable area
+    let program = [
+        0x3e, 0x18, // ld a, 0x18
+        0xea, 0x9e, 0xfe, // ld (0xfe9e), a
+        0x3e, 0xfe, // ld a, 0xfe
+        0xea, 0x9f, 0xfe, // ld (0xfe9f), a
+        0xc3, 0x9e, 0xfe, // jp 0xfe9e
+    ];
+    let create = || Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    assert!(diff(create(), create()).is_none());
+}
@@ -0,0 +1,132 @@
+extern crate gb_emu;
+mod common;
+use gb_emu::{Emulator, TraceFormat, Tracer};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// Lets the test read what the emulator's tracer wrote
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn get_lines(&self) -> Vec<String> {
+        let data = self.0.lock().unwrap();
+        String::from_utf8(data.clone())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+fn create_emulator() -> Emulator {
+    let program = [
+        0x3e, 0x91, // 0x150 ld a, 0x91
+        0xe0, 0x40, // 0x152 ldh (0x40), a
+        0x06, 0x03, // 0x154 ld b, 3
+        0x05, // 0x156 dec b
+        0x20, 0xfd, // 0x157 jr nz, -3
+        0x18, 0xfe, // 0x159 jr -2
+    ];
+    let rom = common::create_rom(&program);
+    Emulator::from_bytes(rom, None).unwrap()
+}
+
+// Runs until the program reaches the final loop at 0x159
+fn run(emulator: &mut Emulator) {
+    while emulator.get_registers().pc != 0x159 {
+        emulator.run_frame_until(emulator.get_cycles() + 4);
+    }
+}
+
+#[test]
+fn registers_format() {
+    let mut emulator = create_emulator();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(buffer.clone());
+    tracer.include_pcs(0x150..=0x159);
+    emulator.set_tracer(Some(tracer));
+    run(&mut emulator);
+    emulator.take_tracer().unwrap().finish().unwrap();
+
+    let lines = buffer.get_lines();
+    // 3 instructions, then 3 times round the dec loop
+    assert_eq!(lines.len(), 9);
+    assert!(lines[0].ends_with("SP:FFFE PC:0150 PCMEM:3E,91,E0,40"));
+    assert!(lines[3].starts_with("A:91 F:"));
+    assert!(lines[3].contains("B:03"));
+    assert!(lines[8].contains("B:00"));
+    assert!(lines[8].contains("PC:0157"));
+}
+
+#[test]
+fn filters() {
+    let mut emulator = create_emulator();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(buffer.clone());
+    tracer.set_format(TraceFormat::Disassembly);
+    tracer.include_pcs(0x150..=0x159);
+    tracer.exclude_pcs(0x156..=0x156);
+    emulator.set_tracer(Some(tracer));
+    run(&mut emulator);
+
+    let lines = buffer.get_lines();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "0150  3e 91     ld a, $91");
+    assert_eq!(lines[3], "0157  20 fd     jr nz, $0156");
+}
+
+#[test]
+fn cycle_window() {
+    let mut emulator = create_emulator();
+    while emulator.get_registers().pc != 0x150 {
+        emulator.run_frame_until(emulator.get_cycles() + 4);
+    }
+    // Skips ld a (8 cycles) and ldh (12 cycles), then stops
+    // after ld b (8 cycles) and the first dec b (4 cycles)
+    let start = emulator.get_cycles() + 20;
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(buffer.clone());
+    tracer.set_format(TraceFormat::Disassembly);
+    tracer.set_cycle_window(start..start + 12);
+    emulator.set_tracer(Some(tracer));
+    run(&mut emulator);
+
+    assert_eq!(
+        buffer.get_lines(),
+        ["0154  06 03     ld b, $03", "0156  05        dec b"]
+    );
+}
+
+#[test]
+fn end_of_oam() {
+    // A loop in the last bytes of OAM, just before the unusable area
+    let program = [
+        0x3e, 0x18, // ld a, 0x18
+        0xea, 0x9e, 0xfe, // ld (0xfe9e), a
+        0x3e, 0xfe, // ld a, 0xfe
+        0xea, 0x9f, 0xfe, // ld (0xfe9f), a
+        0xc3, 0x9e, 0xfe, // jp 0xfe9e
+    ];
+    let mut emulator = Emulator::from_bytes(common::create_rom(&program), None).unwrap();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(buffer.clone());
+    tracer.include_pcs(0xfe9e..=0xfe9e);
+    emulator.set_tracer(Some(tracer));
+    while buffer.get_lines().len() < 2 {
+        emulator.run_frame_until(emulator.get_cycles() + 4);
+    }
+
+    let lines = buffer.get_lines();
+    assert!(lines[0].ends_with("PC:FE9E PCMEM:18,FE,FF,FF"));
+}